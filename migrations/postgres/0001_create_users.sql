@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS users (
+    user_id TEXT PRIMARY KEY,
+    password TEXT NOT NULL,
+    api_key TEXT NOT NULL,
+    email_id TEXT NOT NULL
+);
@@ -0,0 +1,18 @@
+CREATE TABLE api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    label TEXT NOT NULL,
+    api_key TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
+
+-- The single key every user had so far becomes their `default` key
+INSERT INTO api_keys (user_id, label, api_key)
+SELECT user_id, 'default', api_key FROM users;
+
+ALTER TABLE users DROP COLUMN api_key;
@@ -0,0 +1,13 @@
+-- Keys are stored as a keyed hash from now on. The hash needs the server
+-- secret, so existing keys are kept in `legacy_key` and hashed by the
+-- server right after migrating, which then clears `legacy_key`.
+ALTER TABLE api_keys RENAME COLUMN api_key TO legacy_key;
+ALTER TABLE api_keys ALTER COLUMN legacy_key DROP NOT NULL;
+
+ALTER TABLE api_keys ADD COLUMN key_prefix TEXT;
+UPDATE api_keys SET key_prefix = substr(legacy_key, 1, 12);
+ALTER TABLE api_keys ALTER COLUMN key_prefix SET NOT NULL;
+
+ALTER TABLE api_keys ADD COLUMN key_hash TEXT UNIQUE;
+
+CREATE INDEX api_keys_key_prefix ON api_keys (key_prefix);
@@ -0,0 +1,12 @@
+-- No foreign key on purpose: events must outlive the users they mention
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target TEXT NOT NULL,
+    field TEXT,
+    ip TEXT
+);
+
+CREATE INDEX audit_log_created_at ON audit_log (created_at);
@@ -0,0 +1,4 @@
+-- Existing users get the time of the upgrade, the earliest one known.
+ALTER TABLE users ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
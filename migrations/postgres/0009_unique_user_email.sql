@@ -0,0 +1,3 @@
+-- Fails on databases where several users share an email ID, which must be
+-- changed or purged before upgrading.
+CREATE UNIQUE INDEX users_email_id ON users (email_id);
@@ -0,0 +1,12 @@
+CREATE TABLE files (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    stored_path TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    content_type TEXT,
+    sha256 TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX files_user_id ON files (user_id, id);
@@ -0,0 +1,2 @@
+-- Files are stored under keys of the configured storage backend, relative to its root
+ALTER TABLE files RENAME COLUMN stored_path TO storage_key;
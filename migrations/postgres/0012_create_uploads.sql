@@ -0,0 +1,13 @@
+CREATE TABLE uploads (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    content_type TEXT,
+    length BIGINT NOT NULL,
+    file_id BIGINT REFERENCES files (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX uploads_user_id ON uploads (user_id);
+CREATE INDEX uploads_expires_at ON uploads (expires_at);
@@ -0,0 +1,4 @@
+-- Quotas of the users whose limits differ from the server defaults, NULL when
+-- the default applies.
+ALTER TABLE users ADD COLUMN quota_bytes BIGINT;
+ALTER TABLE users ADD COLUMN quota_files BIGINT;
@@ -0,0 +1,8 @@
+-- Initial schema. `IF NOT EXISTS` lets databases created before
+-- migrations were introduced adopt this version without changes.
+CREATE TABLE IF NOT EXISTS users (
+    user_id TEXT PRIMARY KEY,
+    password TEXT NOT NULL,
+    api_key TEXT NOT NULL,
+    email_id TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Existing users become regular users: admins are promoted explicitly with
+-- the `promote-admin` command.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
@@ -0,0 +1,27 @@
+-- Keys are stored as a keyed hash from now on. The hash needs the server
+-- secret, so existing keys are copied to `legacy_key` and hashed by the
+-- server right after migrating, which then clears `legacy_key`.
+CREATE TABLE api_keys_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id TEXT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    label TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT UNIQUE,
+    legacy_key TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')),
+    expires_at TEXT,
+    last_used_at TEXT,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+INSERT INTO api_keys_new (
+    id, user_id, label, key_prefix, legacy_key, created_at, expires_at, last_used_at, revoked
+)
+SELECT id, user_id, label, substr(api_key, 1, 12), api_key, created_at, expires_at, last_used_at, revoked
+FROM api_keys;
+
+DROP TABLE api_keys;
+ALTER TABLE api_keys_new RENAME TO api_keys;
+
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
+CREATE INDEX api_keys_key_prefix ON api_keys (key_prefix);
@@ -0,0 +1,10 @@
+-- SQLite only accepts constant defaults when adding columns, the backend sets
+-- both timestamps on insert. Existing users get the time of the upgrade, the
+-- earliest one known.
+ALTER TABLE users ADD COLUMN created_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN updated_at TEXT NOT NULL DEFAULT '';
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+
+UPDATE users SET
+    created_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'),
+    updated_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now');
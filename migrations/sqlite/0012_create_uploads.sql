@@ -0,0 +1,13 @@
+CREATE TABLE uploads (
+    id TEXT PRIMARY KEY NOT NULL,
+    user_id TEXT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    file_name TEXT NOT NULL,
+    content_type TEXT,
+    length INTEGER NOT NULL,
+    file_id INTEGER REFERENCES files (id) ON DELETE SET NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')),
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX uploads_user_id ON uploads (user_id);
+CREATE INDEX uploads_expires_at ON uploads (expires_at);
@@ -0,0 +1,6 @@
+CREATE TABLE used_download_links (
+    nonce TEXT PRIMARY KEY NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX used_download_links_expires_at ON used_download_links (expires_at);
@@ -0,0 +1,188 @@
+use super::{audit, audit_context, check_self_or_admin, generic_response};
+use crate::{
+    db::DynBackend,
+    error::Error,
+    models::{ApiKey, CreatedApiKey, NewApiKey, NewAuditEvent},
+    secure::guards::{AuthGuard, RequestInfo, WriteGuard},
+    server::config::Settings,
+};
+use rocket::{serde::json::Json, State};
+
+/// # List the API keys of a user
+///
+/// This endpoint lists all API keys of a user, including revoked and expired ones.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only list their own keys, `admin` users can list
+/// anyone's.
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user owning the keys.
+///
+/// # Returns
+///
+/// The list of keys in JSON format.
+#[openapi(tag = "API Keys")]
+#[get("/users/<user_id>/keys")]
+pub async fn list_api_keys_endpoint(
+    user_id: &str,
+    backend: &State<DynBackend>,
+    api_guard: AuthGuard,
+) -> Result<Json<Vec<ApiKey>>, Error> {
+    generic_response(
+        async {
+            check_self_or_admin(&api_guard, user_id)?;
+            backend.list_api_keys(user_id).await
+        }
+        .await,
+    )
+}
+
+/// # Create an API key for a user
+///
+/// This endpoint creates an additional, labelled API key for a user, with an
+/// optional expiry time.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only create keys for themselves, `admin` users
+/// can create keys for anyone.
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user owning the key.
+/// - `key`: A JSON object containing the label and optional expiry of the key.
+///
+/// # Returns
+///
+/// The created key in JSON format. The value
+/// of the key is only ever returned here, it is stored hashed.
+#[openapi(tag = "API Keys")]
+#[post("/users/<user_id>/keys", format = "json", data = "<key>")]
+pub async fn create_api_key_endpoint(
+    user_id: &str,
+    key: Json<NewApiKey>,
+    backend: &State<DynBackend>,
+    salt: &State<String>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<CreatedApiKey>, Error> {
+    let result = async {
+        check_self_or_admin(&write_guard, user_id)?;
+        backend
+            .create_api_key(
+                user_id,
+                key.into_inner(),
+                salt,
+                config.server.api_key_length,
+            )
+            .await
+    }
+    .await;
+
+    let target = match &result {
+        Ok(created) => key_target(user_id, created.key.id),
+        Err(_) => user_id.to_string(),
+    };
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("api_key.create", &target, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
+
+/// # Revoke an API key of a user
+///
+/// This endpoint revokes an API key. Revoked keys can no longer be used but are
+/// still listed.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only revoke their own keys, `admin` users can
+/// revoke anyone's.
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user owning the key.
+/// - `key_id`: The ID of the key to revoke.
+///
+/// # Returns
+///
+/// `null` once the key is revoked.
+#[openapi(tag = "API Keys")]
+#[delete("/users/<user_id>/keys/<key_id>")]
+pub async fn revoke_api_key_endpoint(
+    user_id: &str,
+    key_id: i64,
+    backend: &State<DynBackend>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<()>, Error> {
+    let result = async {
+        check_self_or_admin(&write_guard, user_id)?;
+        backend.revoke_api_key(user_id, key_id).await
+    }
+    .await;
+
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("api_key.revoke", &key_target(user_id, key_id), &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
+
+/// # Rotate an API key of a user
+///
+/// This endpoint replaces the value of an API key, keeping its label and expiry.
+/// The previous value stops working immediately.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only rotate their own keys, `admin` users can
+/// rotate anyone's.
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user owning the key.
+/// - `key_id`: The ID of the key to rotate. Revoked keys cannot be rotated.
+///
+/// # Returns
+///
+/// The rotated key, with its new value, in JSON format.
+#[openapi(tag = "API Keys")]
+#[post("/users/<user_id>/keys/<key_id>/rotate")]
+pub async fn rotate_api_key_endpoint(
+    user_id: &str,
+    key_id: i64,
+    backend: &State<DynBackend>,
+    salt: &State<String>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<CreatedApiKey>, Error> {
+    let result = async {
+        check_self_or_admin(&write_guard, user_id)?;
+        backend
+            .rotate_api_key(user_id, key_id, salt, config.server.api_key_length)
+            .await
+    }
+    .await;
+
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("api_key.rotate", &key_target(user_id, key_id), &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
+
+/// Identifies an API key in the audit log.
+fn key_target(user_id: &str, key_id: i64) -> String {
+    format!("{}/keys/{}", user_id, key_id)
+}
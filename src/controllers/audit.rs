@@ -0,0 +1,85 @@
+use super::{generic_response, into_page, page_limit};
+use crate::{
+    db::DynBackend,
+    error::Error,
+    models::{AuditEvent, AuditFilter, Page},
+    secure::guards::AdminGuard,
+};
+use chrono::{DateTime, Utc};
+use rocket::{serde::json::Json, State};
+use rocket_okapi::JsonSchema;
+
+/// Query parameters of `GET /audit`.
+#[derive(FromForm, JsonSchema)]
+pub struct AuditQuery<'r> {
+    /// Only events at or after this RFC 3339 time
+    from: Option<&'r str>,
+    /// Only events before this RFC 3339 time
+    to: Option<&'r str>,
+    /// Only events performed by this user ID
+    actor: Option<String>,
+    /// Only events of this action, e.g. `user.update`
+    action: Option<String>,
+    /// The `next_cursor` of the previous page
+    after: Option<&'r str>,
+    /// The maximum number of events per page, 50 by default and at most 500
+    limit: Option<i64>,
+}
+
+/// # List the audit log
+///
+/// This endpoint lists the recorded actions, newest first, one page at a time.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
+///
+/// # Parameters
+///
+/// - `query`: The filters and pagination of the events.
+///
+/// # Returns
+///
+/// A page of events in JSON format.
+#[openapi(tag = "Audit")]
+#[get("/audit?<query..>")]
+pub async fn list_audit_events_endpoint(
+    query: AuditQuery<'_>,
+    backend: &State<DynBackend>,
+    _admin_guard: AdminGuard,
+) -> Result<Json<Page<AuditEvent>>, Error> {
+    generic_response(
+        async {
+            let limit = page_limit(query.limit);
+            let filter = AuditFilter {
+                from: query.from.map(|t| parse_time("from", t)).transpose()?,
+                to: query.to.map(|t| parse_time("to", t)).transpose()?,
+                actor: query.actor,
+                action: query.action,
+                before_id: query
+                    .after
+                    .map(|cursor| {
+                        cursor
+                            .parse()
+                            .map_err(|_| Error::BadRequest("Invalid cursor".to_string()))
+                    })
+                    .transpose()?,
+                // One more to tell whether there is a next page
+                limit: limit + 1,
+            };
+
+            let (events, total) = backend.list_audit_events(&filter).await?;
+            Ok(into_page(events, total, limit, |event| {
+                event.id.to_string()
+            }))
+        }
+        .await,
+    )
+}
+
+fn parse_time(name: &str, time: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| Error::BadRequest(format!("Invalid `{}` time, expected RFC 3339", name)))
+}
@@ -0,0 +1,139 @@
+use super::generic_response;
+use crate::{
+    db::DynBackend,
+    error::Error,
+    models::{LoginRequest, RefreshRequest, Session, SessionTokens},
+    secure::{
+        guards::SessionGuard,
+        hash_pass::PasswordHasher,
+        token::{generate_random_string, hash_api_key, sign_access_token},
+    },
+    server::config::Settings,
+};
+use chrono::{Duration, Utc};
+use rocket::{serde::json::Json, State};
+
+/// Number of random characters of session IDs and refresh tokens
+const SESSION_TOKEN_LEN: usize = 43;
+
+/// # Log in with a password
+///
+/// This endpoint checks the password of a user and opens a session.
+///
+/// # Parameters
+///
+/// - `login`: A JSON object containing the user ID and password.
+///
+/// # Returns
+///
+/// A short-lived access token to
+/// pass in the `Authorization: Bearer` header along with a refresh token, in JSON format.
+#[openapi(tag = "Auth")]
+#[post("/auth/login", format = "json", data = "<login>")]
+pub async fn login_endpoint(
+    login: Json<LoginRequest>,
+    backend: &State<DynBackend>,
+    hasher: &State<PasswordHasher>,
+    salt: &State<String>,
+    config: &State<Settings>,
+) -> Result<Json<SessionTokens>, Error> {
+    generic_response(
+        async {
+            let user = backend
+                .authenticate_user(&login.user_id, &login.password, hasher)
+                .await?;
+
+            let refresh_token = generate_random_string(SESSION_TOKEN_LEN);
+            let now = Utc::now();
+            let session = Session {
+                id: generate_random_string(SESSION_TOKEN_LEN),
+                user_id: user.user_id,
+                refresh_hash: hash_api_key(&refresh_token, salt),
+                created_at: now,
+                expires_at: now + Duration::seconds(config.server.refresh_token_ttl.into()),
+            };
+            backend.create_session(&session).await?;
+
+            Ok(session_tokens(&session, refresh_token, salt, config))
+        }
+        .await,
+    )
+}
+
+/// # Refresh a session
+///
+/// This endpoint exchanges a refresh token for a new access token and a new refresh
+/// token, and extends the session. Each refresh token can only be used once.
+///
+/// # Parameters
+///
+/// - `refresh`: A JSON object containing the refresh token.
+///
+/// # Returns
+///
+/// The new tokens in JSON format.
+#[openapi(tag = "Auth")]
+#[post("/auth/refresh", format = "json", data = "<refresh>")]
+pub async fn refresh_endpoint(
+    refresh: Json<RefreshRequest>,
+    backend: &State<DynBackend>,
+    salt: &State<String>,
+    config: &State<Settings>,
+) -> Result<Json<SessionTokens>, Error> {
+    generic_response(
+        async {
+            let refresh_token = generate_random_string(SESSION_TOKEN_LEN);
+            let expires_at = Utc::now() + Duration::seconds(config.server.refresh_token_ttl.into());
+            let session = backend
+                .refresh_session(
+                    &hash_api_key(&refresh.refresh_token, salt),
+                    &hash_api_key(&refresh_token, salt),
+                    expires_at,
+                )
+                .await
+                .map_err(|_| Error::UnauthenticatedUser)?;
+
+            Ok(session_tokens(&session, refresh_token, salt, config))
+        }
+        .await,
+    )
+}
+
+/// # Log out
+///
+/// This endpoint closes the session of the access token, which along with its
+/// refresh token can no longer be used.
+///
+/// # Requires
+///
+/// `Authorization: Bearer`: must be passed in the header with a valid access token.
+///
+/// # Returns
+///
+/// `null` once the session is closed.
+#[openapi(tag = "Auth")]
+#[post("/auth/logout")]
+pub async fn logout_endpoint(
+    backend: &State<DynBackend>,
+    session_guard: SessionGuard,
+) -> Result<Json<()>, Error> {
+    generic_response(backend.delete_session(&session_guard.session_id).await)
+}
+
+/// Issues a new access token for a session.
+fn session_tokens(
+    session: &Session,
+    refresh_token: String,
+    secret: &str,
+    config: &Settings,
+) -> SessionTokens {
+    let expires_in = i64::from(config.server.access_token_ttl);
+
+    SessionTokens {
+        access_token: sign_access_token(&session.id, Utc::now().timestamp() + expires_in, secret),
+        token_type: "Bearer".to_string(),
+        expires_in,
+        refresh_token,
+        refresh_expires_at: session.expires_at,
+    }
+}
@@ -0,0 +1,387 @@
+//! Sending stored files: byte ranges, including `multipart/byteranges` ones, and
+//! conditional requests validated by the `ETag` and `Last-Modified` of the file.
+//!
+//! Files are served from the origin of the API, so only types browsers cannot run
+//! scripts from are displayed inline: the others, e.g. HTML documents or SVG images,
+//! are sent as attachments, and browsers are told not to sniff any type.
+
+use super::{http_date, parse_http_date};
+use crate::error::Error;
+use crate::models::FileInfo;
+use crate::secure::token::generate_random_string;
+use crate::storage::{ObjectReader, Storage};
+use chrono::{DateTime, Utc};
+use log::error;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::io::{self, AsyncReadExt};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{
+    Parameter, ParameterValue, RefOr, Response as OpenApiResponse, Responses,
+};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::response::OpenApiResponderInner;
+use std::ops::Range;
+
+/// Beyond this number of ranges, the whole file is sent instead
+const MAX_RANGES: usize = 16;
+/// Number of random characters of the boundary between the parts of a `multipart/byteranges` body
+const BOUNDARY_LEN: usize = 24;
+/// Types displayed inline, besides `audio/*` and `video/*`
+const INLINE_TYPES: [(&str, &str); 8] = [
+    ("application", "pdf"),
+    ("image", "avif"),
+    ("image", "bmp"),
+    ("image", "gif"),
+    ("image", "jpeg"),
+    ("image", "png"),
+    ("image", "webp"),
+    ("text", "plain"),
+];
+
+/// The headers of a download request that select what to send.
+pub struct DownloadRequest<'r> {
+    range: Option<&'r str>,
+    if_range: Option<&'r str>,
+    if_none_match: Option<&'r str>,
+    if_modified_since: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DownloadRequest<'r> {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(Self {
+            range: headers.get_one("Range"),
+            if_range: headers.get_one("If-Range"),
+            if_none_match: headers.get_one("If-None-Match"),
+            if_modified_since: headers.get_one("If-Modified-Since"),
+        })
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for DownloadRequest<'r> {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::Parameter(Parameter {
+            name: "Range".to_string(),
+            location: "header".to_string(),
+            description: Some(
+                "The byte ranges to send, e.g. `bytes=0-499,-500`. `If-Range`, \
+                 `If-None-Match` and `If-Modified-Since` are supported as well."
+                    .to_string(),
+            ),
+            required: false,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema: gen.json_schema::<String>(),
+                example: None,
+                examples: None,
+            },
+            extensions: Default::default(),
+        }))
+    }
+}
+
+impl DownloadRequest<'_> {
+    /// Tells whether the client already has the current content, per `If-None-Match`
+    /// or, without it, `If-Modified-Since`.
+    fn is_not_modified(&self, etag: &str, last_modified: DateTime<Utc>) -> bool {
+        if let Some(if_none_match) = self.if_none_match {
+            // Weak comparison, as for any GET or HEAD request
+            return if_none_match.trim() == "*"
+                || if_none_match
+                    .split(',')
+                    .any(|tag| tag.trim().trim_start_matches("W/") == etag);
+        }
+
+        self.if_modified_since
+            .and_then(parse_http_date)
+            .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+    }
+
+    /// Tells whether `Range` applies, i.e. whether the content did not change since
+    /// the `If-Range` validator, if any, was sent.
+    fn is_range_valid(&self, etag: &str, last_modified: DateTime<Utc>) -> bool {
+        match self.if_range.map(str::trim) {
+            None => true,
+            // Strong comparison: weak tags never match
+            Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == etag,
+            Some(date) => parse_http_date(date)
+                .is_some_and(|date| date.timestamp() == last_modified.timestamp()),
+        }
+    }
+}
+
+/// The byte ranges requested out of a file.
+enum Ranges {
+    /// No or an invalid `Range` header, which is ignored
+    All,
+    /// None of the ranges are within the file
+    Unsatisfiable,
+    /// The ranges within the file, in the requested order
+    Some(Vec<Range<u64>>),
+}
+
+/// Parses a `Range` header such as `bytes=0-499,1000-,-500` for a file of `size` bytes.
+fn parse_ranges(header: &str, size: u64) -> Ranges {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return Ranges::All;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return Ranges::All;
+        };
+        let range = match (parse_position(first), parse_position(last)) {
+            // The last bytes of the file
+            (None, Some(suffix)) if first.is_empty() => size.saturating_sub(suffix)..size,
+            (Some(first), None) if last.is_empty() => first..size,
+            (Some(first), Some(last)) if first <= last => first..last.saturating_add(1).min(size),
+            _ => return Ranges::All,
+        };
+
+        // Ranges starting past the end of the file are skipped
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.len() > MAX_RANGES {
+        Ranges::All
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Some(ranges)
+    }
+}
+
+/// Parses a byte position, which only has digits.
+fn parse_position(position: &str) -> Option<u64> {
+    if position.is_empty() || !position.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    position.parse().ok()
+}
+
+/// The response to a download request: the content of the file or some ranges
+/// of it, or only headers, for `HEAD` requests or when nothing is sent.
+pub struct FileContent {
+    status: Status,
+    headers: Vec<Header<'static>>,
+    length: Option<u64>,
+    body: Option<ObjectReader>,
+}
+
+impl FileContent {
+    fn header(mut self, header: impl Into<Header<'static>>) -> Self {
+        self.headers.push(header.into());
+        self
+    }
+}
+
+impl<'r> Responder<'r, 'static> for FileContent {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(self.status);
+        // The type of a file is the one detected on upload, not for browsers to guess
+        response.raw_header("X-Content-Type-Options", "nosniff");
+        for header in self.headers {
+            response.header_adjoin(header);
+        }
+        match (self.length, self.body) {
+            (Some(length), Some(body)) => {
+                // Streamed bodies are chunked unless their length is known upfront
+                response.raw_header("Content-Length", length.to_string());
+                response.streamed_body(body);
+            }
+            // Rocket strips the body of HEAD responses, but keeps the size of
+            // seekable ones as their Content-Length
+            (Some(length), None) => {
+                response.sized_body(Some(length as usize), std::io::Cursor::new(Vec::new()));
+            }
+            _ => {}
+        }
+
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for FileContent {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = <Vec<u8>>::responses(gen)?;
+        for (status, description) in [
+            (
+                "206",
+                "The requested ranges, as `multipart/byteranges` if there are several",
+            ),
+            ("304", "The content is the one the client already has"),
+            ("416", "None of the requested ranges are within the file"),
+        ] {
+            responses.responses.insert(
+                status.to_string(),
+                RefOr::Object(OpenApiResponse {
+                    description: description.to_string(),
+                    ..Default::default()
+                }),
+            );
+        }
+
+        Ok(responses)
+    }
+}
+
+/// Responds to a download request for `file`. Its content is only read from
+/// `storage` when it is sent, i.e. neither for `HEAD` requests, as told by
+/// `head_only`, nor when the client already has it.
+pub(crate) async fn serve_file(
+    file: &FileInfo,
+    storage: &dyn Storage,
+    request: &DownloadRequest<'_>,
+    head_only: bool,
+) -> Result<FileContent, Error> {
+    let size = file.size as u64;
+    // The content of a file never changes, so its digest makes a strong validator
+    let etag = format!("\"{}\"", file.sha256);
+    let response = FileContent {
+        status: Status::Ok,
+        headers: Vec::new(),
+        length: None,
+        body: None,
+    }
+    .header(Header::new("ETag", etag.to_owned()))
+    .header(Header::new("Last-Modified", http_date(file.created_at)))
+    .header(Header::new("Accept-Ranges", "bytes"));
+
+    if request.is_not_modified(&etag, file.created_at) {
+        return Ok(FileContent {
+            status: Status::NotModified,
+            ..response
+        });
+    }
+
+    // Stored files have no extension to guess their type from
+    let content_type = file
+        .content_type
+        .as_deref()
+        .and_then(ContentType::parse_flexible)
+        .unwrap_or(ContentType::Binary);
+    let response = if is_inline(&content_type) {
+        response
+    } else {
+        response.header(Header::new("Content-Disposition", "attachment"))
+    };
+
+    // Ranges are only sent in response to GET requests
+    let ranges = match request.range {
+        Some(range) if !head_only && request.is_range_valid(&etag, file.created_at) => {
+            parse_ranges(range, size)
+        }
+        _ => Ranges::All,
+    };
+
+    match ranges {
+        Ranges::All if head_only => Ok(FileContent {
+            length: Some(size),
+            ..response.header(content_type)
+        }),
+        Ranges::All => {
+            let (object, reader) = storage
+                .get(&file.storage_key)
+                .await
+                .map_err(|e| open_error(file, e))?;
+
+            Ok(FileContent {
+                length: Some(object.size),
+                body: Some(reader),
+                ..response.header(content_type)
+            })
+        }
+        Ranges::Unsatisfiable => Ok(FileContent {
+            status: Status::RangeNotSatisfiable,
+            ..response.header(Header::new("Content-Range", format!("bytes */{}", size)))
+        }),
+        Ranges::Some(ranges) if ranges.len() == 1 => {
+            let range = ranges[0].to_owned();
+            let reader = storage
+                .get_range(&file.storage_key, range.to_owned())
+                .await
+                .map_err(|e| open_error(file, e))?;
+
+            Ok(FileContent {
+                status: Status::PartialContent,
+                length: Some(range.end - range.start),
+                body: Some(reader),
+                ..response
+                    .header(content_type)
+                    .header(Header::new("Content-Range", content_range(&range, size)))
+            })
+        }
+        Ranges::Some(ranges) => {
+            let boundary = generate_random_string(BOUNDARY_LEN);
+            let mut length = 0;
+            let mut body: ObjectReader = Box::pin(io::empty());
+            for (i, range) in ranges.iter().enumerate() {
+                let part_headers = format!(
+                    "{}--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                    if i == 0 { "" } else { "\r\n" },
+                    boundary,
+                    content_type,
+                    content_range(range, size)
+                );
+                let reader = storage
+                    .get_range(&file.storage_key, range.to_owned())
+                    .await
+                    .map_err(|e| open_error(file, e))?;
+
+                length += part_headers.len() as u64 + (range.end - range.start);
+                body = Box::pin(
+                    body.chain(std::io::Cursor::new(part_headers.into_bytes()))
+                        .chain(reader),
+                );
+            }
+            let end = format!("\r\n--{}--\r\n", boundary);
+            length += end.len() as u64;
+            body = Box::pin(body.chain(std::io::Cursor::new(end.into_bytes())));
+
+            let content_type =
+                ContentType::new("multipart", "byteranges").with_params(("boundary", boundary));
+            Ok(FileContent {
+                status: Status::PartialContent,
+                length: Some(length),
+                body: Some(body),
+                ..response.header(content_type)
+            })
+        }
+    }
+}
+
+/// Whether files of `content_type` can be displayed by browsers rather than
+/// downloaded, as they cannot run scripts.
+fn is_inline(content_type: &ContentType) -> bool {
+    let top = content_type.top().as_str().to_ascii_lowercase();
+    let sub = content_type.sub().as_str().to_ascii_lowercase();
+    matches!(top.as_str(), "audio" | "video")
+        || INLINE_TYPES.contains(&(top.as_str(), sub.as_str()))
+}
+
+/// Formats the `Content-Range` of a range sent out of a file of `size` bytes.
+fn content_range(range: &Range<u64>, size: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, size)
+}
+
+fn open_error(file: &FileInfo, e: Error) -> Error {
+    error!("Failed to open stored file {}: {}", file.storage_key, e);
+    e
+}
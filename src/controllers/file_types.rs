@@ -0,0 +1,179 @@
+//! Types of uploaded files, detected from their first bytes rather than trusted from
+//! the client, and checked against the name and type the client sent and against
+//! `server.content_types`.
+
+use crate::error::Error;
+use crate::server::config::ContentTypeConfig;
+use rocket::http::ContentType;
+use rocket::tokio::io::{self, AsyncRead, AsyncReadExt};
+use std::path::Path;
+
+/// Number of bytes at the start of a file its type is detected from
+pub(crate) const SNIFF_LEN: usize = 8192;
+
+/// Type of the files nothing is known about
+const UNKNOWN_TYPE: &str = "application/octet-stream";
+
+/// Reads the first `SNIFF_LEN` bytes of a file, or all of it if it is shorter.
+pub(crate) async fn read_head<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
+    Ok(head)
+}
+
+/// What the client says the type of a file is: the type it sent, or else the type
+/// the extension of the file name stands for.
+pub(crate) fn claimed_type(file_name: &str, content_type: Option<&str>) -> Option<String> {
+    declared_type(content_type).or_else(|| {
+        let extension = extension(file_name)?;
+        mime_guess::from_ext(&extension)
+            .first_raw()
+            .filter(|mime| *mime != UNKNOWN_TYPE)
+            .map(str::to_string)
+    })
+}
+
+/// Checks that files of `content_type` can be uploaded.
+pub(crate) fn check_allowed(config: &ContentTypeConfig, content_type: &str) -> Result<(), Error> {
+    if !config.allows(content_type) {
+        return Err(Error::FileTypeNotAllowed(content_type.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Detects the type of a file from its first bytes, `head`, and checks it matches
+/// both the extension of `file_name` and the `content_type` sent by the client.
+///
+/// Contents shared by several formats, such as ZIP archives or text, are taken to be
+/// of the claimed type when their signature cannot tell otherwise: the extension of
+/// an `.odt` document or an `.svg` image is trusted, not that of a `.png` image.
+///
+/// # Returns
+///
+/// The type to record for the file, `Err(Error::FileTypeMismatch)` if the content
+/// contradicts the claims, or `Err(Error::FileTypeNotAllowed)` if the type is not
+/// allowed by `config`.
+pub(crate) fn file_type(
+    config: &ContentTypeConfig,
+    head: &[u8],
+    file_name: &str,
+    content_type: Option<&str>,
+) -> Result<String, Error> {
+    let declared = declared_type(content_type);
+    let mut claims = Vec::new();
+    if let Some(extension) = extension(file_name) {
+        let mimes: Vec<String> = mime_guess::from_ext(&extension)
+            .iter_raw()
+            .filter(|mime| *mime != UNKNOWN_TYPE)
+            .map(str::to_string)
+            .collect();
+        if !mimes.is_empty() || infer::is_supported(&extension) {
+            claims.push(Claim {
+                name: format!(".{}", extension),
+                extensions: vec![extension],
+                mimes,
+            });
+        }
+    }
+    if let Some(declared) = &declared {
+        claims.push(Claim {
+            name: declared.to_owned(),
+            extensions: mime_guess::get_mime_extensions_str(declared)
+                .unwrap_or_default()
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            mimes: vec![declared.to_owned()],
+        });
+    }
+
+    let detected = infer::get(head);
+    for claim in &claims {
+        let matches = match &detected {
+            Some(detected) => {
+                claim.matches(detected) || (is_generic(detected) && !claim.is_detectable())
+            }
+            None => !claim.is_detectable(),
+        };
+        if !matches {
+            let found = detected.map_or("unrecognized content", |detected| detected.mime_type());
+            return Err(Error::FileTypeMismatch(format!(
+                "{} is expected for {}, found {}",
+                claim.mimes.first().map_or("a known type", String::as_str),
+                claim.name,
+                found
+            )));
+        }
+    }
+
+    let claimed = declared.or_else(|| {
+        claims
+            .first()
+            .and_then(|claim| claim.mimes.first().cloned())
+    });
+    let file_type = match detected {
+        Some(detected) if !is_generic(&detected) => detected.mime_type().to_string(),
+        detected => claimed
+            .or_else(|| detected.map(|detected| detected.mime_type().to_string()))
+            .unwrap_or_else(|| UNKNOWN_TYPE.to_string()),
+    };
+    check_allowed(config, &file_type)?;
+
+    Ok(file_type)
+}
+
+/// A type claimed for a file, by its extension or by the client.
+struct Claim {
+    /// The extension or type, to report mismatches
+    name: String,
+    /// Extensions of the files of the type
+    extensions: Vec<String>,
+    /// Media types of the files with the extension
+    mimes: Vec<String>,
+}
+
+impl Claim {
+    /// Whether a detected type is one the claim stands for, under any of its names.
+    fn matches(&self, detected: &infer::Type) -> bool {
+        self.mimes.iter().any(|mime| mime == detected.mime_type())
+            || self
+                .extensions
+                .iter()
+                .any(|extension| extension == detected.extension())
+    }
+
+    /// Whether files of the claimed type can be recognized from their first bytes.
+    fn is_detectable(&self) -> bool {
+        self.mimes.iter().any(|mime| infer::is_mime_supported(mime))
+            || self
+                .extensions
+                .iter()
+                .any(|extension| infer::is_supported(extension))
+    }
+}
+
+/// Whether a detected type is shared by formats that cannot be told apart from their
+/// first bytes.
+fn is_generic(detected: &infer::Type) -> bool {
+    let mime = detected.mime_type();
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/zip" | "application/x-ole-storage" | UNKNOWN_TYPE
+        )
+}
+
+/// The type sent by the client, without parameters, unless it is the type of any file.
+fn declared_type(content_type: Option<&str>) -> Option<String> {
+    let content_type = ContentType::parse_flexible(content_type?)?;
+    let essence = format!("{}/{}", content_type.top(), content_type.sub()).to_ascii_lowercase();
+    (essence != UNKNOWN_TYPE).then_some(essence)
+}
+
+fn extension(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
@@ -1,91 +1,386 @@
-use crate::secure::guards::ApiKeyGuard;
-use crate::server::config::Settings;
+use super::download::{serve_file, DownloadRequest, FileContent};
+use super::file_types::{file_type, read_head};
+use super::quotas::{upload_allowance, Allowance};
+use super::{audit, audit_context, generic_response, into_page, page_limit};
+use crate::db::{Backend, DynBackend};
+use crate::error::{Error, Resource};
+use crate::models::validate::sanitize_file_name;
+use crate::models::{FileFilter, FileInfo, NewAuditEvent, NewFile, Page, UserInfo};
+use crate::secure::guards::{AuthGuard, RequestInfo, WriteGuard};
+use crate::secure::token::generate_random_string;
+use crate::server::config::{ServerConfig, Settings};
+use crate::storage::{DynStorage, HashingReader, Storage};
 use chrono::Datelike;
 use chrono::Utc;
+use log::error;
+use rocket::futures::Stream;
+use rocket::http::ContentType;
 use rocket::serde::json::Json;
-use rocket::serde::Serialize;
-use rocket::{fs::NamedFile, http::Status, Data, State};
-use rocket_multipart_form_data::{
-    MultipartFormData, MultipartFormDataField, MultipartFormDataOptions,
-};
+use rocket::tokio::io::{self, AsyncRead, ReadBuf};
+use rocket::{data::ByteUnit, Data, State};
 use rocket_okapi::JsonSchema;
-use std::fs;
-use std::path::Path;
+use std::io::Cursor;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use validator::ValidationErrors;
 
-#[derive(Serialize, JsonSchema)]
-pub struct UploadResponse {
-    status: String,
-    path: String,
-}
+/// Number of random characters of the names of stored files
+const STORED_NAME_LEN: usize = 32;
 
+/// # Upload a file
+///
+/// This endpoint stores the `file` field of a `multipart/form-data` body. The name
+/// of the file is only kept as metadata: names with directories or reserved by
+/// Windows are rejected. The type of the file is detected from its content, and the
+/// file is rejected with `415 Unsupported Media Type` when it does not match the
+/// extension of its name or the `Content-Type` of the field, or when it is not
+/// allowed by `server.content_types`. Files larger than `server.max_upload_size` are
+/// rejected with `413 Payload Too Large`, and files past the storage quota of the
+/// user or of the server with `507 Insufficient Storage`, as soon as they are.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. `read_only` users cannot upload files.
+///
+/// # Returns
+///
+/// The record of the stored file, including the ID to download it with, in JSON format.
 #[openapi(tag = "Files")]
 #[post("/upload", data = "<data>")]
 pub async fn upload_file(
-    content_type: &rocket::http::ContentType,
+    content_type: Option<&ContentType>,
     data: Data<'_>,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
     config: &State<Settings>,
-    _api_guard: ApiKeyGuard,
-) -> Result<Json<UploadResponse>, Status> {
-    let now = Utc::now();
-    let storage_dir = Path::new(&config.server.storage_path)
-        .join(now.year().to_string())
-        .join(format!("{:02}", now.month()));
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<FileInfo>, Error> {
+    let result = match content_type {
+        Some(content_type) => {
+            async {
+                let allowance =
+                    upload_allowance(backend.as_ref(), &config.server, &write_guard.user_id)
+                        .await?;
+                store_upload(
+                    content_type,
+                    data,
+                    &config.server,
+                    backend.as_ref(),
+                    storage.as_ref(),
+                    &write_guard.user_id,
+                    &allowance,
+                )
+                .await
+            }
+            .await
+        }
+        None => Err(Error::BadRequest(
+            "Expected a multipart/form-data body".to_string(),
+        )),
+    };
 
-    if let Err(e) = fs::create_dir_all(&storage_dir) {
-        eprintln!("Failed to create directory: {}", e);
-        return Err(Status::InternalServerError);
-    }
+    let target = match &result {
+        Ok(file) => file.id.to_string(),
+        Err(_) => "-".to_string(),
+    };
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("file.upload", &target, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
 
-    let mut options = MultipartFormDataOptions::new();
-    options.allowed_fields.push(
-        MultipartFormDataField::file("file")
-            .size_limit(rocket::data::ByteUnit::max_value().as_u64()),
-    );
+/// Stores the `file` field of a multipart upload, and records it as uploaded by `user_id`
+/// with the type detected from its content. The upload is aborted as soon as the file
+/// exceeds `allowance`.
+async fn store_upload(
+    content_type: &ContentType,
+    data: Data<'_>,
+    config: &ServerConfig,
+    backend: &dyn Backend,
+    storage: &dyn Storage,
+    user_id: &str,
+    allowance: &Allowance,
+) -> Result<FileInfo, Error> {
+    let boundary = multer::parse_boundary(content_type.to_string()).map_err(invalid_multipart)?;
+    let mut multipart = multer::Multipart::with_reader(data.open(ByteUnit::max_value()), boundary);
+    let field = loop {
+        match multipart.next_field().await.map_err(invalid_multipart)? {
+            Some(field) if field.name() == Some("file") => break field,
+            Some(_) => continue,
+            None => return Err(Error::BadRequest("Missing `file` field".to_string())),
+        }
+    };
 
-    let form_data = match MultipartFormData::parse(content_type, data, options).await {
-        Ok(form_data) => form_data,
+    let file_name = uploaded_file_name(field.file_name())?;
+    let declared_type = field.content_type().map(ToString::to_string);
+
+    let mut field = FieldReader::new(field);
+    let head = match read_head(&mut field).await {
+        Ok(head) => head,
         Err(e) => {
-            eprintln!("Failed to parse form data: {:?}", e);
-            return Err(Status::BadRequest);
+            return Err(match field.error.take() {
+                Some(multipart_error) => invalid_multipart(multipart_error),
+                None => e.into(),
+            })
         }
     };
+    let content_type = file_type(
+        &config.content_types,
+        &head,
+        &file_name,
+        declared_type.as_deref(),
+    )?;
+
+    let storage_key = new_storage_key();
+
+    // The first bytes are stored along with the rest of the file
+    let content = io::AsyncReadExt::chain(Cursor::new(head), field);
+    let mut reader = HashingReader::new(content).with_limit(allowance.max_size);
+    if let Err(e) = storage.put(&storage_key, &mut reader).await {
+        if reader.limit_exceeded() {
+            return Err(allowance.exceeded());
+        }
+        return Err(match reader.get_mut().get_mut().1.error.take() {
+            Some(multipart_error) => invalid_multipart(multipart_error),
+            None => {
+                error!("Failed to store file {}: {}", storage_key, e);
+                e
+            }
+        });
+    }
+    let (size, sha256) = reader.finish();
+
+    let file = NewFile {
+        user_id: user_id.to_string(),
+        file_name,
+        storage_key,
+        size: size as i64,
+        content_type: Some(content_type),
+        sha256,
+    };
+    let result = backend.create_file(&file, &allowance.limits).await;
+    if result.is_err() {
+        delete_stored_files(storage, &[file.storage_key]).await;
+    }
+
+    result
+}
+
+/// Sanitizes the name of an uploaded file, `uploaded_file` when the client sent none.
+pub(crate) fn uploaded_file_name(file_name: Option<&str>) -> Result<String, Error> {
+    let Some(file_name) = file_name else {
+        return Ok("uploaded_file".to_string());
+    };
+
+    sanitize_file_name(file_name).map_err(|e| {
+        let mut errors = ValidationErrors::new();
+        errors.add("file_name", e);
+        errors.into()
+    })
+}
+
+/// Generates the key of a new file in the storage backend. The client name is only
+/// metadata, so that it never ends up in a key.
+pub(crate) fn new_storage_key() -> String {
+    let now = Utc::now();
+    format!(
+        "{}/{:02}/{}",
+        now.year(),
+        now.month(),
+        generate_random_string(STORED_NAME_LEN)
+    )
+}
+
+fn invalid_multipart(e: multer::Error) -> Error {
+    Error::BadRequest(format!("Invalid multipart form data: {}", e))
+}
 
-    if let Some(file_field) = form_data.files.get("file").and_then(|files| files.get(0)) {
-        let file_name = file_field
-            .file_name
-            .clone()
-            .unwrap_or("uploaded_file".to_string());
-        let file_path = storage_dir.join(file_name);
+/// Reads the content of a multipart field, keeping the multipart error that
+/// stopped the reading, if any, to report it as a bad request.
+struct FieldReader<'r> {
+    field: multer::Field<'r>,
+    chunk: multer::bytes::Bytes,
+    error: Option<multer::Error>,
+}
+
+impl<'r> FieldReader<'r> {
+    fn new(field: multer::Field<'r>) -> Self {
+        Self {
+            field,
+            chunk: Default::default(),
+            error: None,
+        }
+    }
+}
 
-        if let Err(e) = fs::copy(&file_field.path, &file_path) {
-            eprintln!("Failed to save file: {}", e);
-            return Err(Status::InternalServerError);
+impl AsyncRead for FieldReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.chunk.is_empty() {
+            match ready!(Pin::new(&mut this.field).poll_next(cx)) {
+                Some(Ok(chunk)) => this.chunk = chunk,
+                Some(Err(e)) => {
+                    let io_error = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                    this.error = Some(e);
+                    return Poll::Ready(Err(io_error));
+                }
+                None => return Poll::Ready(Ok(())),
+            }
         }
 
-        Ok(Json(UploadResponse {
-            status: "success".into(),
-            path: file_path.to_string_lossy().into(),
-        }))
+        let len = buf.remaining().min(this.chunk.len());
+        buf.put_slice(&this.chunk.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Deletes stored files whose records are gone. Failures are only logged, as
+/// the files can no longer be reached through the API anyway.
+pub(crate) async fn delete_stored_files(storage: &dyn Storage, storage_keys: &[String]) {
+    for key in storage_keys {
+        if let Err(e) = storage.delete(key).await {
+            error!("Failed to delete stored file {}: {}", key, e);
+        }
+    }
+}
+
+/// Retrieves a file uploaded by `caller`, or by anyone for admins. The files of
+/// other users are reported as not found, so that their IDs are not disclosed.
+pub(crate) async fn get_owned_file(
+    backend: &dyn Backend,
+    caller: &UserInfo,
+    file_id: i64,
+) -> Result<FileInfo, Error> {
+    let file = backend.get_file(file_id).await?;
+    if caller.is_admin() || file.user_id == caller.user_id {
+        Ok(file)
     } else {
-        Err(Status::BadRequest)
+        Err(Error::NotFound(Resource::File))
     }
 }
 
-#[openapi(tag = "Users")]
-#[get("/download/<year>/<month>/<file>")]
+/// # Download a file
+///
+/// This endpoint sends the content of an uploaded file, or the byte ranges of it
+/// asked for in the `Range` header: several ranges are sent as `multipart/byteranges`.
+/// The `ETag` of a file is its SHA-256 digest, for `If-None-Match` and `If-Range`,
+/// and its `Last-Modified` date the time it was uploaded.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who uploaded the file, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `file_id`: The ID of the file, as returned on upload.
+///
+/// # Returns
+///
+/// The content of the file, the requested ranges of it with `206 Partial Content`,
+/// or `304 Not Modified` if the client already has it.
+#[openapi(tag = "Files")]
+#[get("/download/<file_id>")]
 pub async fn download_file(
-    year: i32,
-    month: u32,
-    file: String,
-    config: &State<Settings>,
-    _api_guard: ApiKeyGuard,
-) -> Result<NamedFile, Status> {
-    let file_path = Path::new(&config.server.storage_path)
-        .join(year.to_string())
-        .join(format!("{:02}", month))
-        .join(file);
-
-    NamedFile::open(&file_path)
-        .await
-        .map_err(|_| Status::NotFound)
+    file_id: i64,
+    download: DownloadRequest<'_>,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    auth_guard: AuthGuard,
+) -> Result<FileContent, Error> {
+    let file = get_owned_file(backend.as_ref(), &auth_guard.0, file_id).await?;
+    serve_file(&file, storage.as_ref(), &download, false).await
+}
+
+/// # Describe a file download
+///
+/// This endpoint sends the headers `GET /download/<file_id>` would, such as the
+/// `Content-Length`, `ETag` and `Last-Modified` of the file, without its content.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who uploaded the file, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `file_id`: The ID of the file, as returned on upload.
+///
+/// # Returns
+///
+/// The headers of the download of the file.
+#[openapi(tag = "Files")]
+#[head("/download/<file_id>")]
+pub async fn head_file(
+    file_id: i64,
+    download: DownloadRequest<'_>,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    auth_guard: AuthGuard,
+) -> Result<FileContent, Error> {
+    let file = get_owned_file(backend.as_ref(), &auth_guard.0, file_id).await?;
+    serve_file(&file, storage.as_ref(), &download, true).await
+}
+
+/// Query parameters of `GET /files`.
+#[derive(FromForm, JsonSchema)]
+pub struct FilesQuery<'r> {
+    /// The `next_cursor` of the previous page
+    after: Option<&'r str>,
+    /// The maximum number of files per page, 50 by default and at most 500
+    limit: Option<i64>,
+}
+
+/// # List my files
+///
+/// This endpoint lists the files uploaded by the caller, newest first, one page at a time.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token.
+///
+/// # Parameters
+///
+/// - `query`: The pagination of the files.
+///
+/// # Returns
+///
+/// A page of files in JSON format.
+#[openapi(tag = "Files")]
+#[get("/files?<query..>")]
+pub async fn list_files_endpoint(
+    query: FilesQuery<'_>,
+    backend: &State<DynBackend>,
+    auth_guard: AuthGuard,
+) -> Result<Json<Page<FileInfo>>, Error> {
+    generic_response(
+        async {
+            let limit = page_limit(query.limit);
+            let filter = FileFilter {
+                user_id: Some(auth_guard.0.user_id.to_owned()),
+                before_id: query
+                    .after
+                    .map(|cursor| {
+                        cursor
+                            .parse()
+                            .map_err(|_| Error::BadRequest("Invalid cursor".to_string()))
+                    })
+                    .transpose()?,
+                // One more to tell whether there is a next page
+                limit: limit + 1,
+            };
+
+            let (files, total) = backend.list_files(&filter).await?;
+            Ok(into_page(files, total, limit, |file| file.id.to_string()))
+        }
+        .await,
+    )
 }
@@ -0,0 +1,225 @@
+//! Signed links to download a file without credentials, e.g. from a browser, an
+//! `<img>` tag or a third party, until they expire.
+
+use super::download::{serve_file, DownloadRequest, FileContent};
+use super::{audit, audit_context, files::get_owned_file, generic_response};
+use crate::db::{Backend, DynBackend};
+use crate::error::Error;
+use crate::models::validate::validate_link_ttl;
+use crate::models::{DownloadLink, FileInfo, NewAuditEvent, NewDownloadLink};
+use crate::secure::guards::{AuthGuard, RequestInfo};
+use crate::secure::token::{generate_random_string, sign_download_link, verify_download_link};
+use crate::server::config::Settings;
+use crate::storage::DynStorage;
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_okapi::JsonSchema;
+use std::net::{IpAddr, SocketAddr};
+use validator::ValidationErrors;
+
+/// Lifetime in seconds of the links created without `expires_in`
+const DEFAULT_LINK_TTL: u32 = 60 * 60;
+/// Number of random characters of the nonce of single use links
+const NONCE_LEN: usize = 24;
+
+/// # Create a download link
+///
+/// This endpoint signs a link to download a file without credentials, until it
+/// expires. The link can be restricted to a single use, or to a client IP address.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who uploaded the file, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `file_id`: The ID of the file.
+/// - `link`: A JSON object with the lifetime of the link in seconds, 1 hour by default
+///   and at most `server.max_download_link_ttl`, and its restrictions.
+///
+/// # Returns
+///
+/// The path and query of the link, to append to the URL of the server, and its expiry
+/// time in JSON format.
+#[openapi(tag = "Files")]
+#[post("/files/<file_id>/links", format = "json", data = "<link>")]
+pub async fn create_download_link(
+    file_id: i64,
+    link: Json<NewDownloadLink>,
+    backend: &State<DynBackend>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    auth_guard: AuthGuard,
+) -> Result<Json<DownloadLink>, Error> {
+    let result = async {
+        let file = get_owned_file(backend.as_ref(), &auth_guard.0, file_id).await?;
+
+        let expires_in = link.expires_in.unwrap_or(DEFAULT_LINK_TTL);
+        validate_link_ttl(expires_in, config.server.max_download_link_ttl).map_err(|e| {
+            let mut errors = ValidationErrors::new();
+            errors.add("expires_in", e);
+            Error::from(errors)
+        })?;
+        // Links carry their expiry as a timestamp, in seconds
+        let expires_at = Utc::now().trunc_subsecs(0) + Duration::seconds(expires_in.into());
+
+        let nonce = link.single_use.then(|| generate_random_string(NONCE_LEN));
+        let ip = link.ip.map(|ip| ip.to_string());
+        let query = signed_query(expires_at.timestamp(), nonce.as_deref(), ip.as_deref());
+        let signature = sign_download_link(file.id, &query, &config.server.secret_key);
+
+        Ok(DownloadLink {
+            url: format!(
+                "/download/{}/signed?{}&signature={}",
+                file.id, query, signature
+            ),
+            expires_at,
+        })
+    }
+    .await;
+
+    let context = audit_context(&auth_guard, &request_info);
+    let event = NewAuditEvent::from_result("file.link", &file_id.to_string(), &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
+
+/// Query of a signed download link.
+#[derive(FromForm, JsonSchema)]
+pub struct SignedLink<'r> {
+    /// Unix timestamp after which the link is rejected
+    expires: i64,
+    /// Random nonce of a single use link
+    once: Option<&'r str>,
+    /// The only client IP address the link can be used from
+    ip: Option<&'r str>,
+    /// Hex encoded HMAC-SHA256 of the link
+    signature: &'r str,
+}
+
+/// Formats the query parameters of a link covered by its signature, in a fixed order.
+fn signed_query(expires: i64, nonce: Option<&str>, ip: Option<&str>) -> String {
+    let mut query = format!("expires={}", expires);
+    if let Some(nonce) = nonce {
+        query.push_str("&once=");
+        query.push_str(nonce);
+    }
+    if let Some(ip) = ip {
+        query.push_str("&ip=");
+        query.push_str(ip);
+    }
+
+    query
+}
+
+/// # Download a file from a signed link
+///
+/// This endpoint sends the content of a file like `GET /download/<file_id>`, ranges
+/// and conditional requests included, to whoever has a valid link to it.
+///
+/// # Requires
+///
+/// No credentials: the `signature` of the link stands for them. Single use links
+/// are rejected with `410 Gone` once used, like expired ones.
+///
+/// # Parameters
+///
+/// - `file_id`: The ID of the file.
+/// - `link`: The query of the link.
+///
+/// # Returns
+///
+/// The content of the file, or the requested ranges of it.
+#[openapi(tag = "Files")]
+#[get("/download/<file_id>/signed?<link..>")]
+pub async fn download_signed_file(
+    file_id: i64,
+    link: SignedLink<'_>,
+    download: DownloadRequest<'_>,
+    remote: SocketAddr,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    config: &State<Settings>,
+) -> Result<FileContent, Error> {
+    let file = check_link(backend.as_ref(), config, remote.ip(), file_id, &link, true).await?;
+    serve_file(&file, storage.as_ref(), &download, false).await
+}
+
+/// # Describe a download from a signed link
+///
+/// This endpoint sends the headers `GET /download/<file_id>/signed` would, without
+/// the content of the file. It does not use up single use links.
+///
+/// # Requires
+///
+/// No credentials: the `signature` of the link stands for them.
+///
+/// # Parameters
+///
+/// - `file_id`: The ID of the file.
+/// - `link`: The query of the link.
+///
+/// # Returns
+///
+/// The headers of the download of the file.
+#[openapi(tag = "Files")]
+#[head("/download/<file_id>/signed?<link..>")]
+pub async fn head_signed_file(
+    file_id: i64,
+    link: SignedLink<'_>,
+    download: DownloadRequest<'_>,
+    remote: SocketAddr,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    config: &State<Settings>,
+) -> Result<FileContent, Error> {
+    let file = check_link(backend.as_ref(), config, remote.ip(), file_id, &link, false).await?;
+    serve_file(&file, storage.as_ref(), &download, true).await
+}
+
+/// Checks that a link was signed by the server for `file_id` and is still valid
+/// for a client connecting from `peer_ip`, using it up if it is single use and
+/// `use_up` is set.
+///
+/// The IP address a link is bound to is checked against the address of the peer,
+/// not `ClientInfo`: clients can set the `X-Real-IP` and `X-Forwarded-For` headers
+/// it is read from to anything.
+///
+/// # Returns
+///
+/// The file the link leads to.
+async fn check_link(
+    backend: &dyn Backend,
+    config: &Settings,
+    peer_ip: IpAddr,
+    file_id: i64,
+    link: &SignedLink<'_>,
+    use_up: bool,
+) -> Result<FileInfo, Error> {
+    let query = signed_query(link.expires, link.once, link.ip);
+    if !verify_download_link(file_id, &query, link.signature, &config.server.secret_key) {
+        return Err(Error::InvalidDownloadLink);
+    }
+    let expires_at = DateTime::from_timestamp(link.expires, 0).unwrap_or_default();
+    if expires_at <= Utc::now() {
+        return Err(Error::DownloadLinkExpired);
+    }
+    if let Some(ip) = link.ip {
+        // Parsed, as IPv6 addresses have several notations
+        if ip.parse::<IpAddr>().ok() != Some(peer_ip) {
+            return Err(Error::InvalidDownloadLink);
+        }
+    }
+
+    let file = backend.get_file(file_id).await?;
+    if let Some(nonce) = link.once.filter(|_| use_up) {
+        if !backend.use_download_link(nonce, expires_at).await? {
+            return Err(Error::DownloadLinkExpired);
+        }
+    }
+
+    Ok(file)
+}
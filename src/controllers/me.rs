@@ -0,0 +1,120 @@
+use super::{audit, audit_context, generic_response};
+use crate::{
+    db::DynBackend,
+    error::Error,
+    models::{NewAuditEvent, PasswordChange, UpdateUser, UserInfo},
+    secure::{
+        guards::{AuthGuard, RequestInfo, WriteGuard},
+        hash_pass::PasswordHasher,
+    },
+    server::config::Settings,
+};
+use rocket::{serde::json::Json, State};
+use validator::ValidateArgs;
+
+/// # Get the current user
+///
+/// This endpoint returns the user the request is authenticated as.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token.
+///
+/// # Returns
+///
+/// The user information in JSON format.
+#[openapi(tag = "Me")]
+#[get("/me")]
+pub async fn get_me_endpoint(auth_guard: AuthGuard) -> Result<Json<UserInfo>, Error> {
+    generic_response(Ok(auth_guard.0.inner))
+}
+
+/// # Change the password of the current user
+///
+/// This endpoint lets users change their own password, provided they know the
+/// current one. All sessions of the user are closed and all their API keys are
+/// revoked, including the one used for this request.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. `read_only` users cannot change their password.
+///
+/// # Parameters
+///
+/// - `change`: A JSON object containing the current and the new password.
+///
+/// # Returns
+///
+/// `null` once the password is changed.
+#[openapi(tag = "Me")]
+#[patch("/me", format = "json", data = "<change>")]
+pub async fn update_me_endpoint(
+    change: Json<PasswordChange>,
+    backend: &State<DynBackend>,
+    hasher: &State<PasswordHasher>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<()>, Error> {
+    let change = change.into_inner();
+    let context = audit_context(&write_guard, &request_info);
+    let result = async {
+        change.validate_with_args(&config.server.password_policy)?;
+        backend
+            .authenticate_user(&write_guard.user_id, &change.current_password, hasher)
+            .await
+            .map_err(|e| match e {
+                Error::UnauthenticatedUser => Error::IncorrectPassword,
+                e => e,
+            })?;
+
+        let update = UpdateUser {
+            password: Some(change.new_password),
+            role: None,
+        };
+        backend
+            .update_user(update, &write_guard.user_id, hasher, &context)
+            .await
+    }
+    .await;
+
+    // Successful updates are recorded by the backend, along with the change itself
+    if result.is_err() {
+        let event = NewAuditEvent::from_result("user.update", &write_guard.user_id, &result)
+            .with_field("password");
+        audit(backend.as_ref(), &context, event).await;
+    }
+
+    generic_response(result)
+}
+
+/// # Delete the current user
+///
+/// This endpoint lets users delete their own account and closes its sessions. An
+/// admin can restore the account until it is purged.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. `read_only` users cannot delete their account.
+///
+/// # Returns
+///
+/// `null` once the account is deleted.
+#[openapi(tag = "Me")]
+#[delete("/me")]
+pub async fn delete_me_endpoint(
+    backend: &State<DynBackend>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<()>, Error> {
+    let result = backend.delete_user(&write_guard.user_id).await;
+
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.delete", &write_guard.user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
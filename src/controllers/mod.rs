@@ -1,13 +1,99 @@
+pub(crate) mod api_keys;
+pub(crate) mod audit;
+pub(crate) mod auth;
+pub(crate) mod download;
+pub(crate) mod file_types;
 pub(crate) mod files;
+pub(crate) mod links;
+pub(crate) mod me;
+pub(crate) mod quotas;
+pub(crate) mod uploads;
 pub(crate) mod users;
-use rocket::{http::Status, serde::json::Value};
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use log::error;
+use rocket::serde::json::Json;
 
-use crate::error::Error;
+use crate::{
+    db::Backend,
+    error::Error,
+    models::{AuditContext, NewAuditEvent, Page, UserInfo},
+    secure::guards::{GuardedData, RequestInfo},
+};
 
-fn generic_response<T: Serialize>(result: Result<T, Error>) -> (Status, Value) {
-    match result {
-        Ok(data) => json_response!(data),
-        Err(e) => json_response!(e.to_status().code, e.to_string()),
+/// Sends the result of an endpoint as JSON, or the `Problem` describing its error.
+fn generic_response<T>(result: Result<T, Error>) -> Result<Json<T>, Error> {
+    result.map(Json)
+}
+
+/// Default and maximum number of items of a page.
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 500;
+
+/// Clamps the requested page size.
+fn page_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+}
+
+/// Builds a page out of up to `limit + 1` items, the extra one only telling that
+/// there is a next page, and the `total` number of matching items.
+fn into_page<T>(
+    mut items: Vec<T>,
+    total: i64,
+    limit: i64,
+    cursor: impl Fn(&T) -> String,
+) -> Page<T> {
+    let next_cursor = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(cursor)
+    } else {
+        None
+    };
+
+    Page {
+        items,
+        next_cursor,
+        total,
+    }
+}
+
+/// Formats a time as an HTTP date, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`.
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Parses an HTTP date, as sent in conditional request headers.
+fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Allows the caller to act on `user_id` only if it is their own account or they are an admin.
+fn check_self_or_admin(caller: &UserInfo, user_id: &str) -> Result<(), Error> {
+    if caller.is_admin() || caller.user_id == user_id {
+        Ok(())
+    } else {
+        Err(Error::ForbiddenAccess)
+    }
+}
+
+/// Describes the authenticated caller of a request for the audit log.
+fn audit_context(caller: &GuardedData<UserInfo>, request_info: &RequestInfo) -> AuditContext {
+    AuditContext {
+        actor: caller.user_id.to_owned(),
+        ip: request_info.client.ip.to_owned(),
+        user_agent: request_info.client.user_agent.to_owned(),
+        request_id: request_info.request_id.0.to_owned(),
+    }
+}
+
+/// Records an action in the audit log. Failing to do so is logged but does not fail
+/// the request, as the action has already been performed.
+async fn audit(backend: &dyn Backend, context: &AuditContext, event: NewAuditEvent) {
+    if let Err(e) = backend.record_audit_event(context, &event).await {
+        error!(
+            "Failed to record audit event {} on {}: {}",
+            event.action, event.target, e
+        );
     }
 }
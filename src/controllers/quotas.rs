@@ -0,0 +1,229 @@
+//! Storage quotas: limits on the bytes and number of files of each user, and of all
+//! users together, checked before and while files are uploaded, and again when they
+//! are recorded. Uploads in progress take up their full length.
+
+use super::{audit, audit_context, check_self_or_admin, generic_response};
+use crate::db::{Backend, DynBackend};
+use crate::error::Error;
+use crate::models::{NewAuditEvent, Quota, StorageLimits, TotalUsage, Usage, UserQuota};
+use crate::secure::guards::{AdminGuard, AuthGuard, RequestInfo};
+use crate::server::config::{ServerConfig, Settings};
+use rocket::serde::json::Json;
+use rocket::State;
+use validator::Validate;
+
+/// The largest file a user can upload next, and the quotas it is checked against
+/// again when recorded.
+pub(crate) struct Allowance {
+    /// Maximum size in bytes of the file
+    pub(crate) max_size: u64,
+    /// The limit setting the maximum size
+    limit: Limit,
+    /// The quotas of the user and of the server
+    pub(crate) limits: StorageLimits,
+}
+
+enum Limit {
+    UploadSize,
+    Quota(Quota),
+}
+
+impl Allowance {
+    /// The error reporting a file larger than the allowance: `413 Payload Too Large`
+    /// past the maximum upload size, `507 Insufficient Storage` past a quota.
+    pub(crate) fn exceeded(&self) -> Error {
+        match self.limit {
+            Limit::UploadSize => Error::UploadTooLarge(self.max_size),
+            Limit::Quota(quota) => quota.into(),
+        }
+    }
+
+    /// Checks the size of a file known upfront.
+    pub(crate) fn check(&self, size: u64) -> Result<(), Error> {
+        if size > self.max_size {
+            return Err(self.exceeded());
+        }
+
+        Ok(())
+    }
+}
+
+/// Computes the largest file `user_id` can upload next, given the maximum upload
+/// size and the quotas of the user and of the server.
+///
+/// The allowance only rejects files early: other uploads may take the room in the
+/// meantime, so the quotas are enforced when the file or upload is recorded, with
+/// `allowance.limits`.
+///
+/// # Returns
+///
+/// `Err(Error::QuotaExceeded)` if the user cannot store one more file.
+pub(crate) async fn upload_allowance(
+    backend: &dyn Backend,
+    config: &ServerConfig,
+    user_id: &str,
+) -> Result<Allowance, Error> {
+    let usage = get_usage(backend, config, user_id).await?;
+    if let Some(max_files) = usage.max_files.filter(|max| usage.files >= *max) {
+        return Err(Quota::UserFiles(max_files).into());
+    }
+
+    let limits = StorageLimits {
+        user_bytes: usage.max_bytes,
+        user_files: usage.max_files,
+        total_bytes: config.quota.total_bytes.map(to_i64),
+        total_files: config.quota.total_files.map(to_i64),
+    };
+    let total = if limits.limits_total() {
+        backend.get_total_usage().await?
+    } else {
+        TotalUsage::default()
+    };
+    if let Some(total_files) = limits.total_files.filter(|max| total.files >= *max) {
+        return Err(Quota::TotalFiles(total_files).into());
+    }
+
+    let mut allowance = Allowance {
+        max_size: config.max_upload_size,
+        limit: Limit::UploadSize,
+        limits,
+    };
+    if let Some(max_bytes) = usage.max_bytes {
+        let remaining = (max_bytes - usage.bytes).max(0) as u64;
+        if remaining < allowance.max_size {
+            allowance.max_size = remaining;
+            allowance.limit = Limit::Quota(Quota::UserBytes(max_bytes));
+        }
+    }
+    if let Some(total_bytes) = limits.total_bytes {
+        let remaining = (total_bytes - total.bytes).max(0) as u64;
+        if remaining < allowance.max_size {
+            allowance.max_size = remaining;
+            allowance.limit = Limit::Quota(Quota::TotalBytes(total_bytes));
+        }
+    }
+
+    Ok(allowance)
+}
+
+fn to_i64(limit: u64) -> i64 {
+    limit.min(i64::MAX as u64) as i64
+}
+
+/// Retrieves the storage used by a user, against their own quota or else the
+/// server defaults.
+async fn get_usage(
+    backend: &dyn Backend,
+    config: &ServerConfig,
+    user_id: &str,
+) -> Result<Usage, Error> {
+    let usage = backend.get_usage(user_id).await?;
+    let default = |limit: Option<u64>| limit.map(to_i64);
+
+    Ok(Usage {
+        max_bytes: usage.max_bytes.or(default(config.quota.user_bytes)),
+        max_files: usage.max_files.or(default(config.quota.user_files)),
+        ..usage
+    })
+}
+
+/// # Get the storage usage of the current user
+///
+/// This endpoint tells how many bytes and files the caller stores, and how many
+/// they can store.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token.
+///
+/// # Returns
+///
+/// The usage and quota of the caller in JSON format.
+#[openapi(tag = "Me")]
+#[get("/me/usage")]
+pub async fn get_my_usage(
+    backend: &State<DynBackend>,
+    config: &State<Settings>,
+    auth_guard: AuthGuard,
+) -> Result<Json<Usage>, Error> {
+    generic_response(get_usage(backend.as_ref(), &config.server, &auth_guard.user_id).await)
+}
+
+/// # Get the storage usage of a user
+///
+/// This endpoint tells how many bytes and files a user stores, and how many they
+/// can store.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only get their own usage, `admin` users can get
+/// anyone's.
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user.
+///
+/// # Returns
+///
+/// The usage and quota of the user in JSON format.
+#[openapi(tag = "Users")]
+#[get("/users/<user_id>/usage")]
+pub async fn get_user_usage(
+    user_id: &str,
+    backend: &State<DynBackend>,
+    config: &State<Settings>,
+    auth_guard: AuthGuard,
+) -> Result<Json<Usage>, Error> {
+    generic_response(
+        async {
+            check_self_or_admin(&auth_guard, user_id)?;
+            get_usage(backend.as_ref(), &config.server, user_id).await
+        }
+        .await,
+    )
+}
+
+/// # Set the quota of a user
+///
+/// This endpoint gives a user their own limits on the bytes and number of files they
+/// can store, in place of the `server.quota` defaults. `null` limits reset them to
+/// the defaults. Files already stored are kept, even past the new limits.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
+///
+/// # Parameters
+///
+/// - `user_id`: The ID of the user.
+/// - `quota`: A JSON object with the maximum bytes and files of the user.
+///
+/// # Returns
+///
+/// The usage and new quota of the user in JSON format.
+#[openapi(tag = "Users")]
+#[put("/users/<user_id>/quota", format = "json", data = "<quota>")]
+pub async fn set_user_quota(
+    user_id: &str,
+    quota: Json<UserQuota>,
+    backend: &State<DynBackend>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    admin_guard: AdminGuard,
+) -> Result<Json<Usage>, Error> {
+    let result = async {
+        quota.validate()?;
+        backend.set_user_quota(user_id, &quota).await?;
+        get_usage(backend.as_ref(), &config.server, user_id).await
+    }
+    .await;
+
+    let context = audit_context(&admin_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.quota", user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
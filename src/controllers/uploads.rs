@@ -0,0 +1,674 @@
+//! Resumable uploads following the [tus 1.0](https://tus.io/protocols/resumable-upload)
+//! protocol, with its creation, expiration and termination extensions.
+
+use super::{
+    audit, audit_context,
+    file_types::{check_allowed, claimed_type, file_type, read_head, SNIFF_LEN},
+    files::{delete_stored_files, new_storage_key, uploaded_file_name},
+    http_date,
+    quotas::upload_allowance,
+};
+use crate::db::{Backend, DynBackend};
+use crate::error::{Error, Resource};
+use crate::models::{FileInfo, NewAuditEvent, NewFile, NewUpload, Upload, UserInfo};
+use crate::secure::guards::{guard_error, AuthGuard, RequestInfo, WriteGuard};
+use crate::secure::token::generate_random_string;
+use crate::server::config::{ServerConfig, Settings};
+use crate::storage::{DynStorage, HashingReader, PartialLock, PartialUploads, Storage};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{Duration, Utc};
+use log::error;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header, HeaderMap, Method, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{data::ByteUnit, Data, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{
+    Parameter, ParameterValue, RefOr, Response as OpenApiResponse, Responses,
+};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::response::OpenApiResponderInner;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// The only version of the protocol implemented
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+/// Number of random characters of upload IDs
+const UPLOAD_ID_LEN: usize = 32;
+/// Header telling the ID of the file a complete upload was recorded as
+const FILE_ID_HEADER: &str = "X-File-Id";
+
+/// A tus request, which must carry the `Tus-Resumable` header of the version of the
+/// protocol implemented here.
+pub struct TusRequest<'r> {
+    headers: &'r HeaderMap<'r>,
+    content_type: Option<&'r ContentType>,
+}
+
+impl<'r> TusRequest<'r> {
+    fn header(&self, name: &str) -> Option<&'r str> {
+        self.headers.get_one(name)
+    }
+
+    fn parse_header<T: FromStr>(&self, name: &str) -> Result<T, Error> {
+        self.header(name)
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| Error::BadRequest(format!("Missing or invalid {} header", name)))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TusRequest<'r> {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if request.headers().get_one("Tus-Resumable") != Some(TUS_VERSION) {
+            return guard_error(request, Error::UnsupportedTusVersion);
+        }
+
+        Outcome::Success(Self {
+            headers: request.headers(),
+            content_type: request.content_type(),
+        })
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for TusRequest<'r> {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::Parameter(Parameter {
+            name: "Tus-Resumable".to_string(),
+            location: "header".to_string(),
+            description: Some(format!(
+                "The version of the tus protocol, `{}`",
+                TUS_VERSION
+            )),
+            required,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema: gen.json_schema::<String>(),
+                example: None,
+                examples: None,
+            },
+            extensions: Default::default(),
+        }))
+    }
+}
+
+/// Adds the headers required by the tus protocol to every response of the upload
+/// endpoints, errors included.
+pub struct TusHeaders;
+
+#[rocket::async_trait]
+impl Fairing for TusHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add tus headers to upload responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if request.uri().path().segments().next() != Some("uploads") {
+            return;
+        }
+
+        if request.method() != Method::Options {
+            response.set_header(Header::new("Tus-Resumable", TUS_VERSION));
+        }
+        if response.status() == Status::PreconditionFailed {
+            response.set_header(Header::new("Tus-Version", TUS_VERSION));
+        }
+    }
+}
+
+/// A response to a tus request, whose content is all in its headers.
+pub struct TusResponse {
+    status: Status,
+    headers: Vec<Header<'static>>,
+}
+
+impl TusResponse {
+    fn new(status: Status) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.headers.push(Header::new(name, value.to_string()));
+        self
+    }
+
+    /// Describes where an upload stands.
+    fn upload(status: Status, upload: &Upload, offset: u64) -> Self {
+        let response = Self::new(status)
+            .header("Upload-Offset", offset)
+            .header("Upload-Expires", http_date(upload.expires_at));
+
+        match upload.file_id {
+            Some(file_id) => response.header(FILE_ID_HEADER, file_id),
+            None => response,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for TusResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(self.status);
+        for header in self.headers {
+            response.header(header);
+        }
+
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for TusResponse {
+    fn responses(_: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        responses.responses.insert(
+            "2XX".to_string(),
+            RefOr::Object(OpenApiResponse {
+                description: "The state of the upload, in the tus headers".to_string(),
+                ..Default::default()
+            }),
+        );
+
+        Ok(responses)
+    }
+}
+
+/// Parses the `Upload-Metadata` header: comma separated keys, each followed by a
+/// space and its base64 encoded value, if any.
+fn parse_metadata(header: &str) -> Result<HashMap<&str, String>, Error> {
+    let invalid = || Error::BadRequest("Invalid Upload-Metadata header".to_string());
+    let mut metadata = HashMap::new();
+
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD.decode(value.trim()).map_err(|_| invalid())?;
+                (key, String::from_utf8(value).map_err(|_| invalid())?)
+            }
+            None => (pair, String::new()),
+        };
+        metadata.insert(key, value);
+    }
+
+    Ok(metadata)
+}
+
+/// # Discover the upload capabilities
+///
+/// This endpoint describes the version and extensions of the tus protocol supported
+/// by the server, along with the maximum size of an upload.
+///
+/// # Returns
+///
+/// The capabilities, in the `Tus-Version`, `Tus-Extension` and `Tus-Max-Size` headers.
+#[openapi(tag = "Uploads")]
+#[options("/uploads")]
+pub async fn upload_options(config: &State<Settings>) -> TusResponse {
+    TusResponse::new(Status::NoContent)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .header("Tus-Max-Size", config.server.max_upload_size)
+}
+
+/// # Create a resumable upload
+///
+/// This endpoint starts a tus upload of `Upload-Length` bytes, whose content is then
+/// sent with `PATCH /uploads/<upload_id>`. The `filename` and `filetype` keys of the
+/// `Upload-Metadata` header are kept as the name and claimed type of the file: the
+/// upload is rejected with `415 Unsupported Media Type` as soon as its content shows
+/// another type, or a type `server.content_types` does not allow.
+///
+/// The full `Upload-Length` counts toward the storage quotas of the user and of the
+/// server from the start, until the upload is deleted or expires: uploads that do
+/// not fit are rejected with `507 Insufficient Storage`.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. `read_only` users cannot upload files.
+///
+/// # Returns
+///
+/// The URL of the upload in the `Location` header, and when it expires in the
+/// `Upload-Expires` header.
+#[openapi(tag = "Uploads")]
+#[post("/uploads")]
+pub async fn create_upload(
+    tus: TusRequest<'_>,
+    config: &State<Settings>,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    partial_uploads: &State<PartialUploads>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<TusResponse, Error> {
+    let result = start_upload(
+        &tus,
+        config,
+        backend.as_ref(),
+        partial_uploads,
+        &write_guard.user_id,
+    )
+    .await;
+
+    let target = match &result {
+        Ok(upload) => upload.id.to_owned(),
+        Err(_) => "-".to_string(),
+    };
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("upload.create", &target, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    let mut upload = result?;
+    // Clients have nothing to send for empty files
+    if upload.length == 0 {
+        let lock = partial_uploads.lock(&upload.id)?;
+        let file = complete(
+            &upload,
+            &lock,
+            &config.server,
+            backend.as_ref(),
+            storage.as_ref(),
+            partial_uploads,
+            &write_guard,
+            &request_info,
+        )
+        .await?;
+        upload.file_id = Some(file.id);
+    }
+
+    Ok(TusResponse::upload(Status::Created, &upload, 0)
+        .header("Location", format!("/uploads/{}", upload.id)))
+}
+
+/// Records a new upload of `user_id`, with empty content.
+async fn start_upload(
+    tus: &TusRequest<'_>,
+    config: &Settings,
+    backend: &dyn Backend,
+    partial_uploads: &PartialUploads,
+    user_id: &str,
+) -> Result<Upload, Error> {
+    if tus.header("Upload-Defer-Length").is_some() {
+        return Err(Error::BadRequest(
+            "Upload-Defer-Length is not supported".to_string(),
+        ));
+    }
+    let length: u64 = tus.parse_header("Upload-Length")?;
+    let allowance = upload_allowance(backend, &config.server, user_id).await?;
+    allowance.check(length)?;
+
+    let metadata = parse_metadata(tus.header("Upload-Metadata").unwrap_or_default())?;
+    let file_name = uploaded_file_name(metadata.get("filename").map(String::as_str))?;
+    let content_type = metadata
+        .get("filetype")
+        .map(|filetype| {
+            ContentType::parse_flexible(filetype)
+                .map(|content_type| content_type.to_string())
+                .ok_or_else(|| Error::BadRequest(format!("Invalid filetype: {}", filetype)))
+        })
+        .transpose()?;
+    if let Some(claimed) = claimed_type(&file_name, content_type.as_deref()) {
+        check_allowed(&config.server.content_types, &claimed)?;
+    }
+
+    expire_uploads(backend, partial_uploads).await;
+
+    let upload = NewUpload {
+        id: generate_random_string(UPLOAD_ID_LEN),
+        user_id: user_id.to_string(),
+        file_name,
+        content_type,
+        length: length as i64,
+        expires_at: Utc::now() + Duration::seconds(config.server.upload_ttl.into()),
+    };
+    partial_uploads.create(&upload.id).await?;
+    let result = backend.create_upload(&upload, &allowance.limits).await;
+    if result.is_err() {
+        remove_partial_uploads(partial_uploads, &[upload.id]).await;
+    }
+
+    result
+}
+
+/// Deletes the uploads that have expired, along with their content. Failures are
+/// only logged, as they are retried on the next upload.
+async fn expire_uploads(backend: &dyn Backend, partial_uploads: &PartialUploads) {
+    match backend.delete_expired_uploads().await {
+        Ok(upload_ids) => remove_partial_uploads(partial_uploads, &upload_ids).await,
+        Err(e) => error!("Failed to delete expired uploads: {}", e),
+    }
+}
+
+/// Deletes the content of uploads whose records are gone. Failures are only logged,
+/// as the content can no longer be reached through the API anyway.
+pub(crate) async fn remove_partial_uploads(
+    partial_uploads: &PartialUploads,
+    upload_ids: &[String],
+) {
+    for id in upload_ids {
+        if let Err(e) = partial_uploads.remove(id).await {
+            error!("Failed to remove partial upload {}: {}", id, e);
+        }
+    }
+}
+
+/// Retrieves an upload created by `caller`, or by anyone for admins. The uploads of
+/// other users are reported as not found, so that their IDs are not disclosed.
+async fn get_owned_upload(
+    backend: &dyn Backend,
+    caller: &UserInfo,
+    upload_id: &str,
+) -> Result<Upload, Error> {
+    let upload = backend.get_upload(upload_id).await?;
+    if caller.is_admin() || upload.user_id == caller.user_id {
+        Ok(upload)
+    } else {
+        Err(Error::NotFound(Resource::Upload))
+    }
+}
+
+/// Retrieves an upload like `get_owned_upload`, which must not have expired.
+async fn get_live_upload(
+    backend: &dyn Backend,
+    caller: &UserInfo,
+    upload_id: &str,
+) -> Result<Upload, Error> {
+    let upload = get_owned_upload(backend, caller, upload_id).await?;
+    if upload.is_expired() {
+        return Err(Error::UploadExpired);
+    }
+
+    Ok(upload)
+}
+
+/// # Get the offset of an upload
+///
+/// This endpoint tells how much of an upload the server has received, to resume
+/// it from there.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who created the upload, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `upload_id`: The ID of the upload, at the end of its URL.
+///
+/// # Returns
+///
+/// The number of bytes received in the `Upload-Offset` header, and the ID of the
+/// file in the `X-File-Id` header once the upload is complete.
+#[openapi(tag = "Uploads")]
+#[head("/uploads/<upload_id>")]
+pub async fn get_upload_offset(
+    upload_id: &str,
+    _tus: TusRequest<'_>,
+    backend: &State<DynBackend>,
+    partial_uploads: &State<PartialUploads>,
+    auth_guard: AuthGuard,
+) -> Result<TusResponse, Error> {
+    let upload = get_live_upload(backend.as_ref(), &auth_guard.0, upload_id).await?;
+    let offset = match upload.file_id {
+        Some(_) => upload.length as u64,
+        None => partial_uploads.offset(&upload.id).await?,
+    };
+
+    Ok(TusResponse::upload(Status::Ok, &upload, offset)
+        .header("Upload-Length", upload.length)
+        .header("Cache-Control", "no-store"))
+}
+
+/// # Resume an upload
+///
+/// This endpoint appends the `application/offset+octet-stream` body to an upload,
+/// from the `Upload-Offset` it has reached. Once all of it has been received, the
+/// upload is recorded as a file that can be downloaded.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who created the upload, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `upload_id`: The ID of the upload, at the end of its URL.
+///
+/// # Returns
+///
+/// The new offset in the `Upload-Offset` header, and the ID of the file in the
+/// `X-File-Id` header once the upload is complete.
+#[openapi(tag = "Uploads")]
+#[patch("/uploads/<upload_id>", data = "<data>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn append_upload(
+    upload_id: &str,
+    data: Data<'_>,
+    tus: TusRequest<'_>,
+    config: &State<Settings>,
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    partial_uploads: &State<PartialUploads>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<TusResponse, Error> {
+    let mut upload = get_live_upload(backend.as_ref(), &write_guard.0, upload_id).await?;
+    match tus.content_type {
+        Some(content_type)
+            if content_type.top() == "application"
+                && content_type.sub() == "offset+octet-stream" => {}
+        content_type => {
+            return Err(Error::UnsupportedMediaType(
+                content_type.map(ToString::to_string).unwrap_or_default(),
+            ))
+        }
+    }
+    let offset: u64 = tus.parse_header("Upload-Offset")?;
+    let lock = partial_uploads.lock(&upload.id)?;
+
+    let length = upload.length as u64;
+    let current = match upload.file_id {
+        Some(_) => length,
+        None => partial_uploads.offset(&upload.id).await?,
+    };
+    if offset != current {
+        return Err(Error::UploadOffsetMismatch(current));
+    }
+    // Already completed into a file, there is nothing left to receive
+    if upload.file_id.is_some() {
+        return Ok(TusResponse::upload(Status::NoContent, &upload, length));
+    }
+    let content_length: Option<u64> = tus.header("Content-Length").and_then(|l| l.parse().ok());
+    if content_length.is_some_and(|content_length| current + content_length > length) {
+        return Err(Error::UploadTooLarge(length));
+    }
+
+    // One more byte than the rest of the upload, to tell whether the client sent too much
+    let mut body = data.open(ByteUnit::from(length - current + 1));
+    let offset = partial_uploads.append(&lock, &mut body).await?;
+    if offset > length {
+        partial_uploads.truncate(&lock, current).await?;
+        return Err(Error::UploadTooLarge(length));
+    }
+    // Content of another type is rejected as soon as it can be recognized, rather
+    // than once complete. It can never be recorded, so the upload is discarded.
+    let sniff_len = SNIFF_LEN as u64;
+    if current < sniff_len && (offset >= sniff_len || offset == length) {
+        if let Err(e) = detect_type(&upload, &lock, &config.server, partial_uploads).await {
+            backend.delete_upload(&upload.id).await?;
+            remove_partial_uploads(partial_uploads, &[upload.id]).await;
+            return Err(e);
+        }
+    }
+
+    // Content received in full but not recorded yet, e.g. on a failure of the storage
+    // backend, is recorded on the next request, with an empty body
+    if offset == length {
+        let file = complete(
+            &upload,
+            &lock,
+            &config.server,
+            backend.as_ref(),
+            storage.as_ref(),
+            partial_uploads,
+            &write_guard,
+            &request_info,
+        )
+        .await?;
+        upload.file_id = Some(file.id);
+    }
+
+    Ok(TusResponse::upload(Status::NoContent, &upload, offset))
+}
+
+/// Moves the content of a complete upload to the storage backend and records it as
+/// a file, audited like the files uploaded at once. Quotas are not checked again:
+/// the room for the file was reserved when the upload was created.
+#[allow(clippy::too_many_arguments)]
+async fn complete(
+    upload: &Upload,
+    lock: &PartialLock<'_>,
+    config: &ServerConfig,
+    backend: &dyn Backend,
+    storage: &dyn Storage,
+    partial_uploads: &PartialUploads,
+    write_guard: &WriteGuard,
+    request_info: &RequestInfo,
+) -> Result<FileInfo, Error> {
+    let result = async {
+        let content_type = detect_type(upload, lock, config, partial_uploads).await?;
+        store_upload(
+            upload,
+            lock,
+            content_type,
+            backend,
+            storage,
+            partial_uploads,
+        )
+        .await
+    }
+    .await;
+    if result.is_ok() {
+        remove_partial_uploads(partial_uploads, &[upload.id.to_owned()]).await;
+    }
+
+    let target = match &result {
+        Ok(file) => file.id.to_string(),
+        Err(_) => "-".to_string(),
+    };
+    let context = audit_context(write_guard, request_info);
+    let event = NewAuditEvent::from_result("file.upload", &target, &result);
+    audit(backend, &context, event).await;
+
+    result
+}
+
+/// Detects the type of the content of an upload from its first bytes, checking it
+/// against the name and type of the upload.
+async fn detect_type(
+    upload: &Upload,
+    lock: &PartialLock<'_>,
+    config: &ServerConfig,
+    partial_uploads: &PartialUploads,
+) -> Result<String, Error> {
+    let head = read_head(&mut partial_uploads.open(lock).await?).await?;
+    file_type(
+        &config.content_types,
+        &head,
+        &upload.file_name,
+        upload.content_type.as_deref(),
+    )
+}
+
+async fn store_upload(
+    upload: &Upload,
+    lock: &PartialLock<'_>,
+    content_type: String,
+    backend: &dyn Backend,
+    storage: &dyn Storage,
+    partial_uploads: &PartialUploads,
+) -> Result<FileInfo, Error> {
+    let storage_key = new_storage_key();
+    let mut reader = HashingReader::new(partial_uploads.open(lock).await?);
+    if let Err(e) = storage.put(&storage_key, &mut reader).await {
+        error!("Failed to store upload {}: {}", upload.id, e);
+        return Err(e);
+    }
+    let (size, sha256) = reader.finish();
+
+    let file = NewFile {
+        user_id: upload.user_id.to_owned(),
+        file_name: upload.file_name.to_owned(),
+        storage_key,
+        size: size as i64,
+        content_type: Some(content_type),
+        sha256,
+    };
+    let result = backend.complete_upload(&upload.id, &file).await;
+    if result.is_err() {
+        delete_stored_files(storage, &[file.storage_key]).await;
+    }
+
+    result
+}
+
+/// # Cancel an upload
+///
+/// This endpoint deletes an upload along with the content received so far. The file
+/// of a complete upload is kept.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of the user who created the upload, or of an `admin` user.
+///
+/// # Parameters
+///
+/// - `upload_id`: The ID of the upload, at the end of its URL.
+#[openapi(tag = "Uploads")]
+#[delete("/uploads/<upload_id>")]
+pub async fn terminate_upload(
+    upload_id: &str,
+    _tus: TusRequest<'_>,
+    backend: &State<DynBackend>,
+    partial_uploads: &State<PartialUploads>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<TusResponse, Error> {
+    let result = async {
+        let upload = get_owned_upload(backend.as_ref(), &write_guard.0, upload_id).await?;
+        let _lock = partial_uploads.lock(&upload.id)?;
+        backend.delete_upload(&upload.id).await?;
+        remove_partial_uploads(partial_uploads, &[upload.id]).await;
+        Ok(())
+    }
+    .await;
+
+    let context = audit_context(&write_guard, &request_info);
+    let event = NewAuditEvent::from_result("upload.delete", upload_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    result.map(|()| TusResponse::new(Status::NoContent))
+}
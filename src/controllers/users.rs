@@ -1,14 +1,25 @@
-use super::generic_response;
+use super::{
+    audit, audit_context, check_self_or_admin, files::delete_stored_files, generic_response,
+    into_page, page_limit, uploads::remove_partial_uploads,
+};
 use crate::{
-    db::SqliteBackend,
-    models::{NewUser, UpdateUser},
-    secure::guards::ApiKeyGuard,
+    db::DynBackend,
+    error::Error,
+    models::{NewAuditEvent, NewUser, Page, UpdateUser, User, UserFilter, UserInfo, UserSort},
+    secure::{
+        guards::{AdminGuard, AuthGuard, RequestInfo, WriteGuard},
+        hash_pass::PasswordHasher,
+    },
+    server::config::Settings,
+    storage::{DynStorage, PartialUploads},
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use rocket::{
-    http::Status,
-    serde::json::{Json, Value},
+    serde::json::{serde_json, Json},
     State,
 };
+use rocket_okapi::JsonSchema;
+use validator::ValidateArgs;
 
 /// # Create a new user
 ///
@@ -16,7 +27,8 @@ use rocket::{
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
 ///
 /// # Parameters
 ///
@@ -24,53 +36,96 @@ use rocket::{
 ///
 /// # Returns
 ///
-/// A tuple consisting of:
-/// - `Status`: The HTTP status code.
-/// - `Value`: The response body as a JSON value.
+/// The created user in JSON format, along with its `default` API key.
 #[openapi(tag = "Users")]
 #[post("/users", format = "json", data = "<user>")]
 pub async fn create_user_endpoint(
     user: Json<NewUser>,
-    backend: &State<SqliteBackend>,
+    backend: &State<DynBackend>,
+    hasher: &State<PasswordHasher>,
     salt: &State<String>,
-    _api_guard: ApiKeyGuard,
-) -> (Status, Value) {
-    generic_response(backend.create_user(user.into_inner(), salt).await)
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    admin_guard: AdminGuard,
+) -> Result<Json<User>, Error> {
+    let user = user.into_inner();
+    let user_id = user.user_id.to_owned();
+    let result = async {
+        user.validate_with_args(&config.server.password_policy)?;
+        backend
+            .create_user(user, hasher, salt, config.server.api_key_length)
+            .await
+    }
+    .await;
+
+    let context = audit_context(&admin_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.create", &user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
 }
 
 /// # Update an existing user
 ///
-/// This endpoint updates user information with new data.
+/// This endpoint updates user information with new data. Changing the password
+/// closes all sessions of the user and revokes all their API keys.
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user. Users change their own password with
+/// `PATCH /me`, which asks for the current one.
 ///
 /// # Parameters
 /// - `user_id`: The ID of the user to update.
 /// - `user`: The JSON representation of the user to update.
 ///
 /// # Returns
-/// A tuple containing the HTTP status code and the JSON value of the response body.
+/// `null` once the user is updated.
 #[openapi(tag = "Users")]
 #[put("/users/<user_id>", format = "json", data = "<user>")]
 pub async fn update_user_endpoint(
     user_id: &str,
     user: Json<UpdateUser>,
-    backend: &State<SqliteBackend>,
-    salt: &State<String>,
-    _api_guard: ApiKeyGuard,
-) -> (Status, Value) {
-    generic_response(backend.update_user(user.into_inner(), user_id, salt).await)
+    backend: &State<DynBackend>,
+    hasher: &State<PasswordHasher>,
+    config: &State<Settings>,
+    request_info: RequestInfo,
+    write_guard: WriteGuard,
+) -> Result<Json<()>, Error> {
+    let user = user.into_inner();
+    let context = audit_context(&write_guard, &request_info);
+    let result = async {
+        check_self_or_admin(&write_guard, user_id)?;
+        // Without the current password, a stolen key or token must not be enough to
+        // take the account over
+        if !write_guard.is_admin() && (user.role.is_some() || user.password.is_some()) {
+            return Err(Error::ForbiddenAccess);
+        }
+        user.validate_with_args(&config.server.password_policy)?;
+        backend.update_user(user, user_id, hasher, &context).await
+    }
+    .await;
+
+    // Successful updates are recorded by the backend, along with the change itself
+    if result.is_err() {
+        let event = NewAuditEvent::from_result("user.update", user_id, &result);
+        audit(backend.as_ref(), &context, event).await;
+    }
+
+    generic_response(result)
 }
 
 /// # Delete an existing user with the specified ID
 ///
-/// This endpoint deletes a user by a given user id.
+/// This endpoint deletes a user by a given user id. The user can no longer authenticate
+/// and its sessions are closed, but it is kept until purged and can be restored along
+/// with its API keys.
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
 ///
 /// # Parameters
 ///
@@ -78,87 +133,207 @@ pub async fn update_user_endpoint(
 ///
 /// # Returns
 ///
-/// Returns `Result` indicating success or failure of the deletion operation. If the user is
-/// deleted successfully, the function returns `Ok(Status::NoContent)`. If there is an error
-/// during deletion, it returns `Err` with a String containing the error message.
+/// `null` once the user is deleted.
 #[openapi(tag = "Users")]
 #[delete("/users/<user_id>")]
 pub async fn delete_user_endpoint(
     user_id: String,
-    backend: &State<SqliteBackend>,
-    _api_guard: ApiKeyGuard,
-) -> (Status, Value) {
-    generic_response(backend.delete_user(&user_id).await)
+    backend: &State<DynBackend>,
+    request_info: RequestInfo,
+    admin_guard: AdminGuard,
+) -> Result<Json<()>, Error> {
+    let result = backend.delete_user(&user_id).await;
+
+    let context = audit_context(&admin_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.delete", &user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
 }
 
-/// # List all Users
+/// # Restore a deleted user
 ///
-/// This endpoint lists all the users.
+/// This endpoint restores a user deleted but not purged yet, along with its API keys.
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
 ///
 /// # Parameters
 ///
-/// `NONE`
+/// - `user_id`: The ID of the user to restore.
 ///
-/// ## Returns
+/// # Returns
 ///
-/// Returns a tuple containing the HTTP status and a `Value` representing the response payload.
+/// The restored user in JSON format.
 #[openapi(tag = "Users")]
-#[get("/users")]
-pub async fn list_all_users_endpoint(
-    backend: &State<SqliteBackend>,
-    _api_guard: ApiKeyGuard,
-) -> (Status, Value) {
-    generic_response(backend.get_all_users().await)
+#[post("/users/<user_id>/restore")]
+pub async fn restore_user_endpoint(
+    user_id: String,
+    backend: &State<DynBackend>,
+    request_info: RequestInfo,
+    admin_guard: AdminGuard,
+) -> Result<Json<UserInfo>, Error> {
+    let result = backend.restore_user(&user_id).await;
+
+    let context = audit_context(&admin_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.restore", &user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
 }
 
-/// # Get user information by ID
+/// # Permanently delete a user
 ///
-/// This endpoint gets user information by a given user id.
+/// This endpoint permanently deletes a user, deleted or not, along with its API keys,
+/// sessions and files. It cannot be undone.
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
 ///
 /// # Parameters
 ///
-/// * `user_id` - The ID of the user to retrieve.
+/// - `user_id`: The ID of the user to purge.
 ///
 /// # Returns
-/// Returns a tuple with a `Status` and a `Value`.
-/// The `Status` represents the HTTP status code of the response.
-/// The `Value` contains the user information in JSON format.
+///
+/// `null` once the user is purged.
 #[openapi(tag = "Users")]
-#[get("/users/<user_id>")]
-pub async fn get_user_by_id_endpoint(
+#[delete("/users/<user_id>/purge")]
+pub async fn purge_user_endpoint(
     user_id: String,
-    backend: &State<SqliteBackend>,
-    _api_guard: ApiKeyGuard,
-) -> (Status, Value) {
-    generic_response(backend.get_user_with_id(&user_id).await)
+    backend: &State<DynBackend>,
+    storage: &State<DynStorage>,
+    partial_uploads: &State<PartialUploads>,
+    request_info: RequestInfo,
+    admin_guard: AdminGuard,
+) -> Result<Json<()>, Error> {
+    let result = backend.purge_user(&user_id).await;
+    if let Ok(purged) = &result {
+        delete_stored_files(storage.as_ref(), &purged.storage_keys).await;
+        remove_partial_uploads(partial_uploads, &purged.upload_ids).await;
+    }
+    let result = result.map(|_| ());
+
+    let context = audit_context(&admin_guard, &request_info);
+    let event = NewAuditEvent::from_result("user.purge", &user_id, &result);
+    audit(backend.as_ref(), &context, event).await;
+
+    generic_response(result)
+}
+
+/// Query parameters of `GET /users`.
+#[derive(FromForm, JsonSchema)]
+pub struct UsersQuery<'r> {
+    /// Only the user with this email ID
+    email_id: Option<String>,
+    /// Only users whose ID starts with this prefix
+    user_id_prefix: Option<String>,
+    /// Also list the deleted users that have not been purged yet
+    include_deleted: Option<bool>,
+    /// The sort order, `user_id` by default. Prefix with `-` for descending order
+    #[field(default_with = Some(UserSort::UserId))]
+    #[schemars(with = "Option<UserSort>")]
+    sort: UserSort,
+    /// The `next_cursor` of the previous page, with the same sort order
+    after: Option<&'r str>,
+    /// The maximum number of users per page, 50 by default and at most 500
+    limit: Option<i64>,
+}
+
+/// # List all Users
+///
+/// This endpoint lists the users, one page at a time.
+///
+/// # Requires
+///
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token of an `admin` user
+///
+/// # Parameters
+///
+/// - `query`: The filters, sort order and pagination of the users.
+///
+/// ## Returns
+///
+/// A page of users in JSON format, along with the total number of users matching
+/// the filters.
+#[openapi(tag = "Users")]
+#[get("/users?<query..>")]
+pub async fn list_all_users_endpoint(
+    query: UsersQuery<'_>,
+    backend: &State<DynBackend>,
+    _admin_guard: AdminGuard,
+) -> Result<Json<Page<UserInfo>>, Error> {
+    generic_response(
+        async {
+            let limit = page_limit(query.limit);
+            let sort = query.sort;
+            let filter = UserFilter {
+                email_id: query.email_id,
+                user_id_prefix: query.user_id_prefix,
+                include_deleted: query.include_deleted.unwrap_or_default(),
+                sort,
+                after: query.after.map(decode_user_cursor).transpose()?,
+                // One more to tell whether there is a next page
+                limit: limit + 1,
+            };
+
+            let (users, total) = backend.list_users(&filter).await?;
+            Ok(into_page(users, total, limit, |user| {
+                encode_user_cursor(sort.key(user), &user.user_id)
+            }))
+        }
+        .await,
+    )
+}
+
+/// Encodes the position of a user in the list as an opaque cursor.
+fn encode_user_cursor(key: &str, user_id: &str) -> String {
+    let position = serde_json::to_vec(&(key, user_id)).unwrap_or_default();
+    URL_SAFE_NO_PAD.encode(position)
 }
 
-/// # Get a user by their API key
+/// Decodes a cursor made by `encode_user_cursor`.
+fn decode_user_cursor(cursor: &str) -> Result<(String, String), Error> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|position| serde_json::from_slice(&position).ok())
+        .ok_or_else(|| Error::BadRequest("Invalid cursor".to_string()))
+}
+
+/// # Get user information by ID
 ///
-/// This endpoint gets user information by a given api key.
+/// This endpoint gets user information by a given user id.
 ///
 /// # Requires
 ///
-/// `X-API-KEY`: must be passed in the header with a valid API Key
+/// `X-API-KEY` or `Authorization: Bearer`: must be passed in the header with a valid
+/// API Key or access token. Users can only read themselves, `admin` users can read
+/// anyone.
 ///
 /// # Parameters
-/// - `NONE`
+///
+/// * `user_id` - The ID of the user to retrieve.
 ///
 /// # Returns
-/// A tuple containing the HTTP status and the user information in JSON format.
+/// The user information in JSON format.
 #[openapi(tag = "Users")]
-#[get("/users/apikey")]
-pub async fn get_user_by_api_key_endpoint(
-    api_key: ApiKeyGuard,
-    backend: &State<SqliteBackend>,
-) -> (Status, Value) {
-    generic_response(backend.get_user_with_apikey(&api_key.0.api_key).await)
+#[get("/users/<user_id>")]
+pub async fn get_user_by_id_endpoint(
+    user_id: String,
+    backend: &State<DynBackend>,
+    api_guard: AuthGuard,
+) -> Result<Json<UserInfo>, Error> {
+    generic_response(
+        async {
+            check_self_or_admin(&api_guard, &user_id)?;
+            backend.get_user_with_id(&user_id).await
+        }
+        .await,
+    )
 }
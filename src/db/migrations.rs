@@ -0,0 +1,237 @@
+use crate::error::Error;
+use serde::Serialize;
+
+/// A single schema migration embedded into the binary at compile time.
+#[derive(Debug)]
+pub struct Migration {
+    /// Monotonically increasing version number
+    pub version: i64,
+    /// Short human readable name of the migration
+    pub name: &'static str,
+    /// SQL statements applied when migrating up to this version
+    pub sql: &'static str,
+}
+
+/// Applied / pending state of a migration, as reported by the `migrations` subcommand.
+#[derive(Serialize, Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    /// When the migration was applied, `None` if it is still pending
+    pub applied_at: Option<String>,
+}
+
+/// A row of the `schema_migrations` table: `(version, name, applied_at)`
+pub(crate) type AppliedMigration = (i64, String, String);
+
+/// Ordered list of migrations for the SQLite backend.
+///
+/// New migrations must be appended with a higher version; applied migrations
+/// must never be edited or re-ordered.
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../../migrations/sqlite/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "add_user_roles",
+        sql: include_str!("../../migrations/sqlite/0002_add_user_roles.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_api_keys",
+        sql: include_str!("../../migrations/sqlite/0003_create_api_keys.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "hash_api_keys",
+        sql: include_str!("../../migrations/sqlite/0004_hash_api_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_sessions",
+        sql: include_str!("../../migrations/sqlite/0005_create_sessions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_audit_log",
+        sql: include_str!("../../migrations/sqlite/0006_create_audit_log.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "extend_audit_log",
+        sql: include_str!("../../migrations/sqlite/0007_extend_audit_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "add_user_timestamps",
+        sql: include_str!("../../migrations/sqlite/0008_add_user_timestamps.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "unique_user_email",
+        sql: include_str!("../../migrations/sqlite/0009_unique_user_email.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_files",
+        sql: include_str!("../../migrations/sqlite/0010_create_files.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "rename_files_storage_key",
+        sql: include_str!("../../migrations/sqlite/0011_rename_files_storage_key.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_uploads",
+        sql: include_str!("../../migrations/sqlite/0012_create_uploads.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_used_download_links",
+        sql: include_str!("../../migrations/sqlite/0013_create_used_download_links.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "add_user_quotas",
+        sql: include_str!("../../migrations/sqlite/0014_add_user_quotas.sql"),
+    },
+];
+
+/// Ordered list of migrations for the PostgreSQL backend.
+///
+/// Must be kept at the same versions as [`SQLITE_MIGRATIONS`].
+#[cfg(feature = "postgres")]
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../../migrations/postgres/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "add_user_roles",
+        sql: include_str!("../../migrations/postgres/0002_add_user_roles.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_api_keys",
+        sql: include_str!("../../migrations/postgres/0003_create_api_keys.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "hash_api_keys",
+        sql: include_str!("../../migrations/postgres/0004_hash_api_keys.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_sessions",
+        sql: include_str!("../../migrations/postgres/0005_create_sessions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_audit_log",
+        sql: include_str!("../../migrations/postgres/0006_create_audit_log.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "extend_audit_log",
+        sql: include_str!("../../migrations/postgres/0007_extend_audit_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "add_user_timestamps",
+        sql: include_str!("../../migrations/postgres/0008_add_user_timestamps.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "unique_user_email",
+        sql: include_str!("../../migrations/postgres/0009_unique_user_email.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_files",
+        sql: include_str!("../../migrations/postgres/0010_create_files.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "rename_files_storage_key",
+        sql: include_str!("../../migrations/postgres/0011_rename_files_storage_key.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_uploads",
+        sql: include_str!("../../migrations/postgres/0012_create_uploads.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_used_download_links",
+        sql: include_str!("../../migrations/postgres/0013_create_used_download_links.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "add_user_quotas",
+        sql: include_str!("../../migrations/postgres/0014_add_user_quotas.sql"),
+    },
+];
+
+/// Returns the highest migration version known to this binary.
+pub fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// Returns the migrations that still have to be applied, in order.
+///
+/// # Errors
+///
+/// Returns `Error::SchemaTooNew` if the database has been migrated by a newer binary.
+pub(crate) fn pending<'a>(
+    migrations: &'a [Migration],
+    applied: &[AppliedMigration],
+) -> Result<Vec<&'a Migration>, Error> {
+    let current = applied.iter().map(|(v, _, _)| *v).max().unwrap_or(0);
+    let latest = latest_version(migrations);
+    if current > latest {
+        return Err(Error::SchemaTooNew(current, latest));
+    }
+
+    Ok(migrations
+        .iter()
+        .filter(|m| !applied.iter().any(|(v, _, _)| *v == m.version))
+        .collect())
+}
+
+/// Lists every known migration along with when it was applied, followed by
+/// any migration recorded in the database that this binary does not know about.
+pub(crate) fn status(
+    migrations: &[Migration],
+    applied: Vec<AppliedMigration>,
+) -> Vec<MigrationStatus> {
+    let mut status: Vec<MigrationStatus> = migrations
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            name: m.name.to_string(),
+            applied_at: applied
+                .iter()
+                .find(|(v, _, _)| *v == m.version)
+                .map(|(_, _, at)| at.to_owned()),
+        })
+        .collect();
+
+    let unknown = applied
+        .into_iter()
+        .filter(|(v, _, _)| !migrations.iter().any(|m| m.version == *v))
+        .map(|(version, name, applied_at)| MigrationStatus {
+            version,
+            name,
+            applied_at: Some(applied_at),
+        });
+    status.extend(unknown);
+
+    status
+}
@@ -1,90 +1,149 @@
+pub mod migrations;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresBackend;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteBackend;
+
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("at least one of the `sqlite` or `postgres` features must be enabled");
+
+use self::migrations::MigrationStatus;
 use crate::{
     error::Error,
-    models::{NewUser, UpdateUser, User, UserInfo},
-    secure::{
-        hash_pass::{hash_password, verify_password},
-        token::generate_api_string,
+    models::{
+        ApiKey, AuditContext, AuditEvent, AuditFilter, CreatedApiKey, FileFilter, FileInfo,
+        NewApiKey, NewAuditEvent, NewFile, NewUpload, NewUser, PurgedUser, Session, StorageLimits,
+        TotalUsage, UpdateUser, Upload, Usage, User, UserFilter, UserInfo, UserQuota,
     },
+    secure::{hash_pass::PasswordHasher, token::generate_api_key},
 };
-use sqlx::{
-    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
-    Pool, Sqlite,
-};
-use std::{
-    str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use chrono::{DateTime, Utc};
+use log::warn;
 
-/// Sqlite Backend struct
-#[derive(Clone)]
-pub struct SqliteBackend {
-    /// # Database Connection Pool
+/// Backend shared with the Rocket state, selected at startup from `app.db_url`
+pub type DynBackend = Box<dyn Backend>;
+
+/// Storage operations required by the controllers and request guards.
+#[rocket::async_trait]
+pub trait Backend: Send + Sync {
+    /// Applies all pending schema migrations.
     ///
-    /// The `pool` module provides a connection pool for SQLite databases.
-    pool: Pool<Sqlite>,
-}
+    /// Each migration runs in its own transaction together with its bookkeeping
+    /// row in `schema_migrations`, so a failing migration leaves the database at
+    /// the previous version.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::SchemaTooNew` if the database has been migrated by a newer
+    /// binary, or the underlying error if any migration fails.
+    async fn migrate(&self) -> Result<(), Error>;
 
-impl SqliteBackend {
-    /// Creates a new connection to a SQLite database.
+    /// Lists every known migration along with when it was applied, followed by
+    /// any migration recorded in the database that this binary does not know about.
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error>;
+
+    /// Hashes the API keys still stored in plaintext by databases created before keys
+    /// were hashed, and erases the plaintext.
+    ///
+    /// # Returns
+    ///
+    /// The number of keys that have been hashed.
+    async fn hash_legacy_api_keys(&self, salt: &str) -> Result<u64, Error>;
+
+    /// Retrieves an API key, whatever its state, based on its value.
     ///
     /// # Arguments
     ///
-    /// * `db_path` - A string slice containing the path to the database file.
+    /// - `api_key`: A string representing the API key to be used for the lookup.
+    /// - `salt`: The server secret the stored key hashes are keyed with.
     ///
     /// # Returns
     ///
-    /// Returns a Result containing the `Connection` object if successful, or an `Error` if an error occurred.
-    pub async fn new_connection(db_path: &str) -> Result<Self, Error> {
-        let conn = SqlitePoolOptions::new()
-            .connect_with(
-                SqliteConnectOptions::from_str(&("sqlite://".to_owned() + db_path))?
-                    .create_if_missing(true),
-            )
-            .await?;
+    /// - If the API key is found, `Ok(ApiKey)` is returned. Callers must check it is not
+    ///   revoked or expired before trusting it.
+    /// - If no such API key is found, `Err(Error::NotFound)` is returned.
+    async fn get_api_key(&self, api_key: &str, salt: &str) -> Result<ApiKey, Error>;
 
-        Ok(Self { pool: conn })
-    }
+    /// Records that an API key has just been used.
+    async fn touch_api_key(&self, key_id: i64) -> Result<(), Error>;
 
-    #[inline]
-    pub(crate) async fn check_and_create_table(&self) -> Result<(), Error> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS users (
-                user_id TEXT PRIMARY KEY,
-                password TEXT NOT NULL,
-                api_key TEXT NOT NULL,
-                email_id TEXT NOT NULL
-            )
-            "#,
-        )
-        .execute(&self.pool.to_owned())
-        .await?;
-
-        Ok(())
-    }
+    /// Creates an additional API key for a user, with `key_length` random characters.
+    ///
+    /// # Returns
+    ///
+    /// The created key, or `Err(Error::NotFound)` if the user does not exist.
+    async fn create_api_key(
+        &self,
+        user_id: &str,
+        key: NewApiKey,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<CreatedApiKey, Error>;
+
+    /// Replaces the value of an API key, keeping its label and expiry.
+    ///
+    /// # Returns
+    ///
+    /// The new key, or `Err(Error::NotFound)` if the user has no such key that is not revoked.
+    async fn rotate_api_key(
+        &self,
+        user_id: &str,
+        key_id: i64,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<CreatedApiKey, Error>;
+
+    /// Lists all API keys of a user, including revoked and expired ones.
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Error>;
+
+    /// Revokes an API key of a user. Revoked keys are kept for bookkeeping.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if the user has no key with the given id.
+    async fn revoke_api_key(&self, user_id: &str, key_id: i64) -> Result<(), Error>;
+
+    /// Stores a new login session.
+    async fn create_session(&self, session: &Session) -> Result<(), Error>;
+
+    /// Retrieves a login session, whatever its expiry.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such session, e.g. after a logout.
+    async fn get_session(&self, session_id: &str) -> Result<Session, Error>;
 
-    /// Retrieves a user based on the provided API key.
+    /// Replaces the refresh token of a session that has not expired yet, and
+    /// extends the session until `expires_at`.
     ///
     /// # Arguments
     ///
-    /// - `api_key`: A string representing the API key to be used for the lookup.
+    /// - `refresh_hash`: The hash of the refresh token presented by the client.
+    /// - `new_refresh_hash`: The hash of the refresh token replacing it.
+    /// - `expires_at`: The new expiry time of the session.
     ///
     /// # Returns
     ///
-    /// - If the user with the given API key is found, `Ok(UserInfo)` is returned.
-    /// - If no user with the given API key is found, `Err(Error::NotFound)` is returned.
-    /// - If an error occurs during the database operation, that error is returned as `Err(Error)`.
-    pub async fn get_user_with_apikey(&self, api_key: &str) -> Result<UserInfo, Error> {
-        sqlx::query_as::<_, UserInfo>(
-            r#"
-        SELECT * FROM users WHERE api_key = ?
-        "#,
-        )
-        .bind(api_key.to_owned())
-        .fetch_optional(&self.pool.to_owned())
-        .await?
-        .ok_or(Error::NotFound("User".to_string()))
-    }
+    /// The refreshed session, or `Err(Error::NotFound)` if no live session has this
+    /// refresh token, e.g. because it has already been used.
+    async fn refresh_session(
+        &self,
+        refresh_hash: &str,
+        new_refresh_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Session, Error>;
+
+    /// Deletes a login session.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such session.
+    async fn delete_session(&self, session_id: &str) -> Result<(), Error>;
 
     /// Retrieve a user with the specified user ID from the database.
     ///
@@ -97,162 +156,349 @@ impl SqliteBackend {
     /// A `Result` containing:
     ///
     /// * `Ok(User)` - If a user with the specified ID is found in the database.
-    /// * `Err(Error::NotFound)` - If no user with the specified ID is found in the database.
-    pub async fn get_user_with_id(&self, user_id: &str) -> Result<UserInfo, Error> {
-        sqlx::query_as::<_, UserInfo>(
-            r#"
-        SELECT * FROM users WHERE user_id = ?
-        "#,
-        )
-        .bind(user_id.to_owned())
-        .fetch_optional(&self.pool.to_owned())
-        .await?
-        .ok_or(Error::NotFound("User".to_string()))
-    }
+    /// * `Err(Error::NotFound)` - If no user with the specified ID is found in the database,
+    ///   or if it has been deleted.
+    async fn get_user_with_id(&self, user_id: &str) -> Result<UserInfo, Error>;
 
-    /// Creates a new user with the provided information.
+    /// Creates a new user with the provided information, along with its `default` API key.
     ///
     /// # Arguments
     ///
     /// * `user` - A `NewUser` struct containing the details of the new user.
+    /// * `hasher` - The hasher of the user password.
+    /// * `salt` - The server secret the stored key hashes are keyed with.
+    /// * `key_length` - The number of random characters of the `default` API key.
     ///
     /// # Returns
     ///
     /// An `Ok` result containing the newly created `User` if successful, or an `Err` containing
-    /// an `Error` if there was a problem.
-    pub async fn create_user(&self, user: NewUser, salt: &str) -> Result<User, Error> {
-        let apikey = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .subsec_nanos();
-
-        let password = hash_password(&user.password, salt)?;
-        if let Ok(verified) = verify_password(&user.password, &password.to_owned()) {
-            if !verified {
-                return Err(Error::PasswordHashError);
-            }
-        } else {
-            return Err(Error::PasswordHashError);
-        }
-
-        let new_user = User {
-            user_id: user.user_id,
-            password,
-            api_key: generate_api_string(&apikey.to_string()),
-            email_id: user.email_id,
-        };
-
-        sqlx::query(
-            r#"
-            INSERT INTO users (user_id, password, api_key, email_id) VALUES (?, ?, ?, ?)
-            "#,
-        )
-        .bind(new_user.user_id.to_owned())
-        .bind(new_user.password.to_owned())
-        .bind(new_user.api_key.to_owned())
-        .bind(new_user.email_id.to_owned())
-        .execute(&self.pool.to_owned())
-        .await?;
-
-        Ok(new_user)
-    }
+    /// an `Error` if there was a problem: `Error::UserConflict` if the user ID or email ID is
+    /// already taken, including by a deleted user.
+    async fn create_user(
+        &self,
+        user: NewUser,
+        hasher: &PasswordHasher,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<User, Error>;
 
-    /// Updates a user's password or API key in the database.
+    /// Updates a user's password or role in the database.
+    ///
+    /// Everything runs in a single transaction: the update itself, one `user.update`
+    /// audit event per changed field and, on a password change, the deletion of the
+    /// user's sessions and the revocation of their API keys.
     ///
     /// # Arguments
     ///
     /// * `user_id` - The ID of the user to update.
     /// * `user` - An instance of `UpdateUser` struct that contains the user's updated data.
+    /// * `hasher` - The hasher of the new password.
+    /// * `context` - Who performs the update, for the audit log.
     ///
     /// # Returns
     ///
     /// * `Result<(), Error>` - An empty Ok result if the user was successfully updated, or an Error if any issues occurred.
-    pub async fn update_user(
+    async fn update_user(
         &self,
         user: UpdateUser,
         user_id: &str,
-        salt: &str,
-    ) -> Result<(), Error> {
-        // if both password and api_key are None, return BadRequest
-        if user.api_key.is_none() && user.password.is_none() {
-            return Err(Error::BadRequest("No data to update".to_string()));
-        }
+        hasher: &PasswordHasher,
+        context: &AuditContext,
+    ) -> Result<(), Error>;
 
-        // User found with the given user_id
-        // Lets check and update either password or api_key
-        if self.get_user_with_id(user_id).await.is_ok() {
-            // update password if present
-            if let Some(pass) = user.password.clone() {
-                let password = hash_password(&pass, salt)?;
-                if let Ok(verified) = verify_password(&pass, &password.to_owned()) {
-                    if !verified {
-                        return Err(Error::PasswordHashError);
-                    }
-                } else {
-                    return Err(Error::PasswordHashError);
-                }
-
-                sqlx::query("UPDATE users SET password = ? WHERE user_id = ?")
-                    .bind(pass)
-                    .bind(user_id)
-                    .execute(&self.pool.to_owned())
-                    .await?;
-            }
-            // update api_key if present
-            if let Some(api_key) = user.api_key.clone() {
-                sqlx::query("UPDATE users SET api_key = ? WHERE user_id = ?")
-                    .bind(api_key)
-                    .bind(user_id)
-                    .execute(&self.pool.to_owned())
-                    .await?;
-            }
-        } else {
-            // No user found with the given user_id
-            return Err(Error::NotFound("User".to_string()));
-        }
-        Ok(())
-    }
+    /// Checks the password of a user.
+    ///
+    /// When the stored hash is a legacy one or uses outdated Argon2 parameters, the
+    /// password is transparently rehashed with the current ones.
+    ///
+    /// # Returns
+    ///
+    /// The user, or `Err(Error::UnauthenticatedUser)` if the user does not exist or
+    /// the password does not match, which takes as long to tell.
+    async fn authenticate_user(
+        &self,
+        user_id: &str,
+        password: &str,
+        hasher: &PasswordHasher,
+    ) -> Result<UserInfo, Error>;
+
+    /// Appends an event to the audit log.
+    async fn record_audit_event(
+        &self,
+        context: &AuditContext,
+        event: &NewAuditEvent,
+    ) -> Result<(), Error>;
 
-    /// Delete a user by their ID from the database.
+    /// Lists the events of the audit log matching `filter`, newest first.
+    ///
+    /// # Returns
+    ///
+    /// Up to `filter.limit` events, along with the number of events matching the
+    /// filters regardless of the cursor and limit.
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<(Vec<AuditEvent>, i64), Error>;
+
+    /// Delete a user by their ID.
+    ///
+    /// The user is only marked as deleted, so that it can be restored along with its
+    /// API keys. Its sessions are closed.
     ///
     /// # Arguments
     ///
-    /// - `pool`: A reference to the database connection pool.
     /// - `user_id`: The ID of the user to delete.
     ///
     /// # Returns
     ///
     /// This function returns `Result<(), Error>`, where `Error` represents any error that might occur.
     /// If the user with the given ID is successfully deleted, `Ok(())` is returned.
-    /// If the user with the given ID is not found, `Err(Error::NotFound)` is returned.
-    pub async fn delete_user(&self, user_id: &str) -> Result<(), Error> {
-        sqlx::query("DELETE FROM users WHERE user_id = ?")
-            .bind(user_id)
-            .execute(&self.pool.to_owned())
-            .await
-            .map_err(Into::into)
-            .and_then(|res| {
-                if res.rows_affected() > 0 {
-                    Ok(())
-                } else {
-                    Err(Error::NotFound("User".to_string()))
-                }
-            })
-    }
+    /// If the user with the given ID is not found or already deleted, `Err(Error::NotFound)` is returned.
+    async fn delete_user(&self, user_id: &str) -> Result<(), Error>;
 
-    /// Retrieves information for all users from the database.
+    /// Restores a deleted user, along with its API keys.
     ///
     /// # Returns
     ///
-    /// Returns a `Vec<UserInfo>` containing information for all users.
+    /// The restored user, or `Err(Error::NotFound)` if there is no deleted user with this ID.
+    async fn restore_user(&self, user_id: &str) -> Result<UserInfo, Error>;
+
+    /// Permanently deletes a user, deleted or not, along with its API keys, sessions,
+    /// file records and resumable uploads.
+    ///
+    /// # Returns
+    ///
+    /// The storage keys of the files and the IDs of the uploads of the user, whose
+    /// content is left to the caller to delete, or `Err(Error::NotFound)` if there is
+    /// no user with this ID.
+    async fn purge_user(&self, user_id: &str) -> Result<PurgedUser, Error>;
+
+    /// Creates the first user of an empty database, like `create_user`.
+    ///
+    /// The user is only inserted if there is no other user, deleted ones included,
+    /// checked in the same transaction so that two concurrent calls cannot both
+    /// succeed.
+    ///
+    /// # Returns
+    ///
+    /// The new user, or `Err(Error::AlreadyBootstrapped)` if any user exists.
+    async fn create_first_user(
+        &self,
+        user: NewUser,
+        hasher: &PasswordHasher,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<User, Error>;
+
+    /// Lists the users matching `filter`, in the order of `filter.sort`.
+    ///
+    /// # Returns
+    ///
+    /// Up to `filter.limit` users, along with the number of users matching the
+    /// filters regardless of the cursor and limit.
     ///
     /// # Errors
     ///
     /// An error is returned if there is any issue with the database query or fetching the data.
-    pub async fn get_all_users(&self) -> Result<Vec<UserInfo>, Error> {
-        sqlx::query_as::<_, UserInfo>(r#"SELECT * FROM users"#)
-            .fetch_all(&self.pool.to_owned())
-            .await
-            .map_err(Into::into)
+    async fn list_users(&self, filter: &UserFilter) -> Result<(Vec<UserInfo>, i64), Error>;
+
+    /// Records a file whose content has been stored, if it fits in `limits`.
+    ///
+    /// The quotas are checked in the transaction recording the file, so that
+    /// concurrent uploads cannot together exceed them.
+    ///
+    /// # Returns
+    ///
+    /// The record of the file, along with its generated ID, or
+    /// `Err(Error::QuotaExceeded)` if it does not fit.
+    async fn create_file(&self, file: &NewFile, limits: &StorageLimits) -> Result<FileInfo, Error>;
+
+    /// Retrieves the record of a file, whoever uploaded it.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no file with this ID.
+    async fn get_file(&self, file_id: i64) -> Result<FileInfo, Error>;
+
+    /// Lists the files matching `filter`, newest first.
+    ///
+    /// # Returns
+    ///
+    /// Up to `filter.limit` files, along with the number of files matching the
+    /// filters regardless of the cursor and limit.
+    async fn list_files(&self, filter: &FileFilter) -> Result<(Vec<FileInfo>, i64), Error>;
+
+    /// Records a new resumable upload, reserving its full length in `limits` until
+    /// it is completed, deleted or expires.
+    ///
+    /// The quotas are checked in the transaction recording the upload, like
+    /// `create_file`.
+    ///
+    /// # Returns
+    ///
+    /// The upload, or `Err(Error::QuotaExceeded)` if it does not fit.
+    async fn create_upload(
+        &self,
+        upload: &NewUpload,
+        limits: &StorageLimits,
+    ) -> Result<Upload, Error>;
+
+    /// Retrieves a resumable upload, whatever its expiry.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such upload.
+    async fn get_upload(&self, upload_id: &str) -> Result<Upload, Error>;
+
+    /// Records the file an upload has been completed into, in a single transaction
+    /// with the upload.
+    ///
+    /// # Returns
+    ///
+    /// The file, or `Err(Error::NotFound)` if there is no such upload or it has
+    /// already been completed.
+    async fn complete_upload(&self, upload_id: &str, file: &NewFile) -> Result<FileInfo, Error>;
+
+    /// Deletes a resumable upload. The file it was completed into, if any, is kept.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such upload.
+    async fn delete_upload(&self, upload_id: &str) -> Result<(), Error>;
+
+    /// Deletes the uploads that have expired.
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the deleted uploads, whose content is left to the caller to delete.
+    async fn delete_expired_uploads(&self) -> Result<Vec<String>, Error>;
+
+    /// Marks a single use download link as used, until it expires. Links that have
+    /// expired are forgotten on the way, as their signature is rejected anyway.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the link had already been used.
+    async fn use_download_link(
+        &self,
+        nonce: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Error>;
+
+    /// Retrieves the storage used by a user, uploads in progress included, with the
+    /// quota set for the user itself, whose `None` limits are left to the server
+    /// defaults.
+    ///
+    /// # Returns
+    ///
+    /// The usage, or `Err(Error::NotFound)` if there is no such user.
+    async fn get_usage(&self, user_id: &str) -> Result<Usage, Error>;
+
+    /// Returns the total size and number of the files and uploads in progress of all
+    /// users.
+    async fn get_total_usage(&self) -> Result<TotalUsage, Error>;
+
+    /// Sets the own quota of a user, replacing the previous one.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such user.
+    async fn set_user_quota(&self, user_id: &str, quota: &UserQuota) -> Result<(), Error>;
+}
+
+/// Connects to the database described by `db_url`, picking the backend from its scheme.
+///
+/// `postgres://` and `postgresql://` urls select the PostgreSQL backend; `sqlite://`
+/// urls and plain file paths select the SQLite backend.
+///
+/// # Errors
+///
+/// Returns `Error::EmptyDBUrl` for an empty url and `Error::UnsupportedDatabase` when
+/// the backend for the scheme was not compiled in.
+pub async fn connect(db_url: &str) -> Result<DynBackend, Error> {
+    if db_url.is_empty() {
+        return Err(Error::EmptyDBUrl);
+    }
+
+    match db_url.split_once("://").map(|(scheme, _)| scheme) {
+        #[cfg(feature = "postgres")]
+        Some("postgres" | "postgresql") => {
+            Ok(Box::new(PostgresBackend::new_connection(db_url).await?))
+        }
+        #[cfg(feature = "sqlite")]
+        Some("sqlite") => Ok(Box::new(
+            SqliteBackend::new_connection(db_url.trim_start_matches("sqlite://")).await?,
+        )),
+        #[cfg(feature = "sqlite")]
+        None => Ok(Box::new(SqliteBackend::new_connection(db_url).await?)),
+        _ => Err(Error::UnsupportedDatabase(db_url.to_string())),
+    }
+}
+
+/// Hashes a password and makes sure the produced hash verifies.
+pub(crate) fn hash_verified_password(
+    password: &str,
+    hasher: &PasswordHasher,
+) -> Result<String, Error> {
+    let hash = hasher.hash(password)?;
+    match hasher.verify(password, &hash) {
+        Ok(true) => Ok(hash),
+        _ => Err(Error::PasswordHashError),
     }
 }
+
+/// Verifies a password against the stored hash of a user, `None` if there is no such
+/// user.
+///
+/// Unknown users are verified against a dummy hash, so that login times do not tell
+/// which user IDs exist. Stored hashes that cannot be parsed match no password.
+///
+/// # Returns
+///
+/// The verified hash, or `Err(Error::UnauthenticatedUser)`.
+pub(crate) fn verify_stored_password(
+    password: &str,
+    hash: Option<String>,
+    hasher: &PasswordHasher,
+) -> Result<String, Error> {
+    let verified = match &hash {
+        Some(hash) => hasher.verify(password, hash).unwrap_or_else(|e| {
+            warn!("Stored password hash cannot be verified: {}", e);
+            hasher.verify_dummy(password);
+            false
+        }),
+        None => {
+            hasher.verify_dummy(password);
+            false
+        }
+    };
+
+    hash.filter(|_| verified).ok_or(Error::UnauthenticatedUser)
+}
+
+/// Maps the violation of a unique constraint on `users` to `Error::UserConflict`.
+pub(crate) fn user_conflict(e: sqlx::Error) -> Error {
+    match e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => Error::UserConflict,
+        e => e.into(),
+    }
+}
+
+/// Builds the stored record for a new user, hashing its password and generating its API key.
+pub(crate) fn new_user_record(
+    user: NewUser,
+    hasher: &PasswordHasher,
+    key_length: usize,
+) -> Result<User, Error> {
+    let now = Utc::now();
+    Ok(User {
+        password: hash_verified_password(&user.password, hasher)?,
+        user_id: user.user_id,
+        api_key: generate_api_key(key_length),
+        email_id: user.email_id,
+        role: user.role,
+        created_at: now,
+        updated_at: now,
+        deleted_at: None,
+    })
+}
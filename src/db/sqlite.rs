@@ -0,0 +1,1025 @@
+use super::{
+    hash_verified_password,
+    migrations::{self, AppliedMigration, MigrationStatus, SQLITE_MIGRATIONS},
+    new_user_record, user_conflict, verify_stored_password, Backend,
+};
+use crate::{
+    error::{Error, Resource},
+    models::{
+        ApiKey, AuditContext, AuditEvent, AuditFilter, CreatedApiKey, FileFilter, FileInfo,
+        NewApiKey, NewAuditEvent, NewFile, NewUpload, NewUser, PurgedUser, Session, StorageLimits,
+        TotalUsage, UpdateUser, Upload, Usage, User, UserFilter, UserInfo, UserQuota,
+    },
+    secure::{
+        hash_pass::PasswordHasher,
+        token::{api_key_prefix, generate_api_key, hash_api_key},
+    },
+};
+use chrono::{DateTime, Utc};
+use log::info;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePoolOptions},
+    Executor, Pool, QueryBuilder, Sqlite,
+};
+use std::str::FromStr;
+
+/// Sqlite Backend struct
+#[derive(Clone)]
+pub struct SqliteBackend {
+    /// # Database Connection Pool
+    ///
+    /// The `pool` module provides a connection pool for SQLite databases.
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteBackend {
+    /// Creates a new connection to a SQLite database.
+    ///
+    /// # Arguments
+    ///
+    /// * `db_path` - A string slice containing the path to the database file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a Result containing the `Connection` object if successful, or an `Error` if an error occurred.
+    pub async fn new_connection(db_path: &str) -> Result<Self, Error> {
+        let conn = SqlitePoolOptions::new()
+            .connect_with(
+                SqliteConnectOptions::from_str(&("sqlite://".to_owned() + db_path))?
+                    .create_if_missing(true),
+            )
+            .await?;
+
+        Ok(Self { pool: conn })
+    }
+
+    async fn create_migrations_table(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(&self.pool.to_owned())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<AppliedMigration>, Error> {
+        sqlx::query_as::<_, AppliedMigration>(
+            "SELECT version, name, applied_at FROM schema_migrations ORDER BY version",
+        )
+        .fetch_all(&self.pool.to_owned())
+        .await
+        .map_err(Into::into)
+    }
+}
+
+#[rocket::async_trait]
+impl Backend for SqliteBackend {
+    async fn migrate(&self) -> Result<(), Error> {
+        self.create_migrations_table().await?;
+        let applied = self.applied_migrations().await?;
+
+        for migration in migrations::pending(SQLITE_MIGRATIONS, &applied)? {
+            info!(
+                "Applying migration {:04}_{}",
+                migration.version, migration.name
+            );
+            let mut tx = self.pool.begin().await?;
+            (&mut *tx).execute(migration.sql).await?;
+            sqlx::query("INSERT INTO schema_migrations (version, name) VALUES (?, ?)")
+                .bind(migration.version)
+                .bind(migration.name)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn migration_status(&self) -> Result<Vec<MigrationStatus>, Error> {
+        self.create_migrations_table().await?;
+        let applied = self.applied_migrations().await?;
+
+        Ok(migrations::status(SQLITE_MIGRATIONS, applied))
+    }
+
+    async fn hash_legacy_api_keys(&self, salt: &str) -> Result<u64, Error> {
+        let legacy = sqlx::query_as::<_, (i64, String)>(
+            "SELECT id, legacy_key FROM api_keys WHERE legacy_key IS NOT NULL",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut tx = self.pool.begin().await?;
+        for (id, api_key) in &legacy {
+            sqlx::query("UPDATE api_keys SET key_hash = ?, legacy_key = NULL WHERE id = ?")
+                .bind(hash_api_key(api_key, salt))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(legacy.len() as u64)
+    }
+
+    async fn get_api_key(&self, api_key: &str, salt: &str) -> Result<ApiKey, Error> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE key_prefix = ? AND key_hash = ?")
+            .bind(api_key_prefix(api_key))
+            .bind(hash_api_key(api_key, salt))
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(Error::NotFound(Resource::ApiKey))
+    }
+
+    async fn touch_api_key(&self, key_id: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_api_key(
+        &self,
+        user_id: &str,
+        key: NewApiKey,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<CreatedApiKey, Error> {
+        self.get_user_with_id(user_id).await?;
+
+        let api_key = generate_api_key(key_length);
+        let key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            INSERT INTO api_keys (user_id, label, key_prefix, key_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(user_id)
+        .bind(key.label)
+        .bind(api_key_prefix(&api_key))
+        .bind(hash_api_key(&api_key, salt))
+        .bind(Utc::now())
+        .bind(key.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedApiKey { key, api_key })
+    }
+
+    async fn rotate_api_key(
+        &self,
+        user_id: &str,
+        key_id: i64,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<CreatedApiKey, Error> {
+        let api_key = generate_api_key(key_length);
+        let key = sqlx::query_as::<_, ApiKey>(
+            r#"
+            UPDATE api_keys SET key_prefix = ?, key_hash = ?
+            WHERE user_id = ? AND id = ? AND revoked = FALSE
+            RETURNING *
+            "#,
+        )
+        .bind(api_key_prefix(&api_key))
+        .bind(hash_api_key(&api_key, salt))
+        .bind(user_id)
+        .bind(key_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound(Resource::ApiKey))?;
+
+        Ok(CreatedApiKey { key, api_key })
+    }
+
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>, Error> {
+        sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE user_id = ? ORDER BY id")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn revoke_api_key(&self, user_id: &str, key_id: i64) -> Result<(), Error> {
+        let res = sqlx::query("UPDATE api_keys SET revoked = TRUE WHERE user_id = ? AND id = ?")
+            .bind(user_id)
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+
+        if res.rows_affected() > 0 {
+            Ok(())
+        } else {
+            Err(Error::NotFound(Resource::ApiKey))
+        }
+    }
+
+    async fn create_session(&self, session: &Session) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (id, user_id, refresh_hash, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&session.id)
+        .bind(&session.user_id)
+        .bind(&session.refresh_hash)
+        .bind(session.created_at)
+        .bind(session.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Session, Error> {
+        sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = ?")
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(Error::NotFound(Resource::Session))
+    }
+
+    async fn refresh_session(
+        &self,
+        refresh_hash: &str,
+        new_refresh_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Session, Error> {
+        sqlx::query_as::<_, Session>(
+            r#"
+            UPDATE sessions SET refresh_hash = ?, expires_at = ?
+            WHERE refresh_hash = ? AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(new_refresh_hash)
+        .bind(expires_at)
+        .bind(refresh_hash)
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound(Resource::Session))
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<(), Error> {
+        let res = sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        if res.rows_affected() > 0 {
+            Ok(())
+        } else {
+            Err(Error::NotFound(Resource::Session))
+        }
+    }
+
+    async fn get_user_with_id(&self, user_id: &str) -> Result<UserInfo, Error> {
+        sqlx::query_as::<_, UserInfo>(
+            r#"
+        SELECT * FROM users WHERE user_id = ? AND deleted_at IS NULL
+        "#,
+        )
+        .bind(user_id.to_owned())
+        .fetch_optional(&self.pool.to_owned())
+        .await?
+        .ok_or(Error::NotFound(Resource::User))
+    }
+
+    async fn create_user(
+        &self,
+        user: NewUser,
+        hasher: &PasswordHasher,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<User, Error> {
+        let new_user = new_user_record(user, hasher, key_length)?;
+
+        let mut tx = self.pool.begin().await?;
+        insert_user(&mut tx, &new_user, salt, false).await?;
+        tx.commit().await?;
+
+        Ok(new_user)
+    }
+
+    async fn create_first_user(
+        &self,
+        user: NewUser,
+        hasher: &PasswordHasher,
+        salt: &str,
+        key_length: usize,
+    ) -> Result<User, Error> {
+        let new_user = new_user_record(user, hasher, key_length)?;
+
+        let mut tx = self.pool.begin().await?;
+        if !insert_user(&mut tx, &new_user, salt, true).await? {
+            return Err(Error::AlreadyBootstrapped);
+        }
+        tx.commit().await?;
+
+        Ok(new_user)
+    }
+
+    async fn update_user(
+        &self,
+        user: UpdateUser,
+        user_id: &str,
+        hasher: &PasswordHasher,
+        context: &AuditContext,
+    ) -> Result<(), Error> {
+        if user.password.is_none() && user.role.is_none() {
+            return Err(Error::BadRequest("No data to update".to_string()));
+        }
+
+        // Hash before opening the transaction, hashing is slow on purpose
+        let password = user
+            .password
+            .map(|pass| hash_verified_password(&pass, hasher))
+            .transpose()?;
+
+        let mut tx = self.pool.begin().await?;
+        let res = sqlx::query(
+            r#"
+            UPDATE users
+            SET password = COALESCE(?, password), role = COALESCE(?, role), updated_at = ?
+            WHERE user_id = ? AND deleted_at IS NULL
+            "#,
+        )
+        .bind(&password)
+        .bind(user.role.map(|role| role.as_str()))
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound(Resource::User));
+        }
+
+        if password.is_some() {
+            // Whoever knew the previous password may have opened sessions or created keys
+            sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE api_keys SET revoked = TRUE WHERE user_id = ?")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+            let event = NewAuditEvent::success("user.update", user_id).with_field("password");
+            insert_audit_event(&mut tx, context, &event).await?;
+        }
+        if user.role.is_some() {
+            let event = NewAuditEvent::success("user.update", user_id).with_field("role");
+            insert_audit_event(&mut tx, context, &event).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn authenticate_user(
+        &self,
+        user_id: &str,
+        password: &str,
+        hasher: &PasswordHasher,
+    ) -> Result<UserInfo, Error> {
+        let hash = sqlx::query_scalar::<_, String>(
+            "SELECT password FROM users WHERE user_id = ? AND deleted_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let hash = verify_stored_password(password, hash, hasher)?;
+        if hasher.needs_rehash(&hash) {
+            sqlx::query("UPDATE users SET password = ? WHERE user_id = ?")
+                .bind(hash_verified_password(password, hasher)?)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        self.get_user_with_id(user_id).await
+    }
+
+    async fn record_audit_event(
+        &self,
+        context: &AuditContext,
+        event: &NewAuditEvent,
+    ) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+        insert_audit_event(&mut conn, context, event).await
+    }
+
+    async fn list_audit_events(
+        &self,
+        filter: &AuditFilter,
+    ) -> Result<(Vec<AuditEvent>, i64), Error> {
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM audit_log WHERE TRUE");
+        push_audit_filter(&mut count, filter);
+        let total = count
+            .build_query_scalar::<i64>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM audit_log WHERE TRUE");
+        push_audit_filter(&mut query, filter);
+        if let Some(before_id) = filter.before_id {
+            query.push(" AND id < ").push_bind(before_id);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.limit);
+
+        let events = query
+            .build_query_as::<AuditEvent>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok((events, total))
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<(), Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let res = sqlx::query(
+            "UPDATE users SET deleted_at = ?, updated_at = ? WHERE user_id = ? AND deleted_at IS NULL",
+        )
+        .bind(now)
+        .bind(now)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound(Resource::User));
+        }
+
+        sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn restore_user(&self, user_id: &str) -> Result<UserInfo, Error> {
+        sqlx::query_as::<_, UserInfo>(
+            r#"
+            UPDATE users SET deleted_at = NULL, updated_at = ?
+            WHERE user_id = ? AND deleted_at IS NOT NULL
+            RETURNING *
+            "#,
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::NotFound(Resource::User))
+    }
+
+    async fn purge_user(&self, user_id: &str) -> Result<PurgedUser, Error> {
+        let mut tx = self.pool.begin().await?;
+        let storage_keys =
+            sqlx::query_scalar::<_, String>("SELECT storage_key FROM files WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let upload_ids =
+            sqlx::query_scalar::<_, String>("SELECT id FROM uploads WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await?;
+        let res = sqlx::query("DELETE FROM users WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound(Resource::User));
+        }
+        tx.commit().await?;
+
+        Ok(PurgedUser {
+            storage_keys,
+            upload_ids,
+        })
+    }
+
+    async fn list_users(&self, filter: &UserFilter) -> Result<(Vec<UserInfo>, i64), Error> {
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM users WHERE TRUE");
+        push_user_filter(&mut count, filter);
+        let total = count
+            .build_query_scalar::<i64>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        let column = filter.sort.column();
+        let (operator, direction) = if filter.sort.is_descending() {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM users WHERE TRUE");
+        push_user_filter(&mut query, filter);
+        if let Some((key, user_id)) = &filter.after {
+            query
+                .push(format!(" AND ({column} {operator} "))
+                .push_bind(key)
+                .push(format!(" OR ({column} = "))
+                .push_bind(key)
+                .push(format!(" AND user_id {operator} "))
+                .push_bind(user_id)
+                .push("))");
+        }
+        query
+            .push(format!(
+                " ORDER BY {column} {direction}, user_id {direction} LIMIT "
+            ))
+            .push_bind(filter.limit);
+
+        let users = query
+            .build_query_as::<UserInfo>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok((users, total))
+    }
+
+    async fn create_file(&self, file: &NewFile, limits: &StorageLimits) -> Result<FileInfo, Error> {
+        let mut tx = self.pool.begin().await?;
+        check_quota(&mut tx, &file.user_id, file.size, limits).await?;
+        let file = insert_file(&mut tx, file).await?;
+        tx.commit().await?;
+
+        Ok(file)
+    }
+
+    async fn get_file(&self, file_id: i64) -> Result<FileInfo, Error> {
+        sqlx::query_as::<_, FileInfo>("SELECT * FROM files WHERE id = ?")
+            .bind(file_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(Error::NotFound(Resource::File))
+    }
+
+    async fn list_files(&self, filter: &FileFilter) -> Result<(Vec<FileInfo>, i64), Error> {
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM files WHERE TRUE");
+        push_file_filter(&mut count, filter);
+        let total = count
+            .build_query_scalar::<i64>()
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM files WHERE TRUE");
+        push_file_filter(&mut query, filter);
+        if let Some(before_id) = filter.before_id {
+            query.push(" AND id < ").push_bind(before_id);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.limit);
+
+        let files = query
+            .build_query_as::<FileInfo>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok((files, total))
+    }
+
+    async fn create_upload(
+        &self,
+        upload: &NewUpload,
+        limits: &StorageLimits,
+    ) -> Result<Upload, Error> {
+        let mut tx = self.pool.begin().await?;
+        check_quota(&mut tx, &upload.user_id, upload.length, limits).await?;
+        let upload = sqlx::query_as::<_, Upload>(
+            r#"
+            INSERT INTO uploads
+                (id, user_id, file_name, content_type, length, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(&upload.id)
+        .bind(&upload.user_id)
+        .bind(&upload.file_name)
+        .bind(&upload.content_type)
+        .bind(upload.length)
+        .bind(Utc::now())
+        .bind(upload.expires_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(upload)
+    }
+
+    async fn get_upload(&self, upload_id: &str) -> Result<Upload, Error> {
+        sqlx::query_as::<_, Upload>("SELECT * FROM uploads WHERE id = ?")
+            .bind(upload_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(Error::NotFound(Resource::Upload))
+    }
+
+    async fn complete_upload(&self, upload_id: &str, file: &NewFile) -> Result<FileInfo, Error> {
+        let mut tx = self.pool.begin().await?;
+        let file = insert_file(&mut tx, file).await?;
+        let res = sqlx::query("UPDATE uploads SET file_id = ? WHERE id = ? AND file_id IS NULL")
+            .bind(file.id)
+            .bind(upload_id)
+            .execute(&mut *tx)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound(Resource::Upload));
+        }
+        tx.commit().await?;
+
+        Ok(file)
+    }
+
+    async fn delete_upload(&self, upload_id: &str) -> Result<(), Error> {
+        let res = sqlx::query("DELETE FROM uploads WHERE id = ?")
+            .bind(upload_id)
+            .execute(&self.pool)
+            .await?;
+
+        if res.rows_affected() > 0 {
+            Ok(())
+        } else {
+            Err(Error::NotFound(Resource::Upload))
+        }
+    }
+
+    async fn delete_expired_uploads(&self) -> Result<Vec<String>, Error> {
+        sqlx::query_scalar::<_, String>("DELETE FROM uploads WHERE expires_at <= ? RETURNING id")
+            .bind(Utc::now())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn use_download_link(
+        &self,
+        nonce: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM used_download_links WHERE expires_at <= ?")
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        let inserted = sqlx::query(
+            "INSERT INTO used_download_links (nonce, expires_at) VALUES (?, ?) \
+             ON CONFLICT (nonce) DO NOTHING",
+        )
+        .bind(nonce)
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        tx.commit().await?;
+
+        Ok(inserted == 1)
+    }
+
+    async fn get_usage(&self, user_id: &str) -> Result<Usage, Error> {
+        let mut conn = self.pool.acquire().await?;
+        select_usage(&mut conn, user_id).await
+    }
+
+    async fn get_total_usage(&self) -> Result<TotalUsage, Error> {
+        let mut conn = self.pool.acquire().await?;
+        select_total_usage(&mut conn).await
+    }
+
+    async fn set_user_quota(&self, user_id: &str, quota: &UserQuota) -> Result<(), Error> {
+        let res = sqlx::query(
+            "UPDATE users SET quota_bytes = ?, quota_files = ?, updated_at = ? \
+             WHERE user_id = ? AND deleted_at IS NULL",
+        )
+        .bind(quota.max_bytes)
+        .bind(quota.max_files)
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound(Resource::User));
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds the filters of `filter` to a query on `audit_log`, except its cursor.
+fn push_audit_filter<'a>(query: &mut QueryBuilder<'a, Sqlite>, filter: &'a AuditFilter) {
+    if let Some(from) = filter.from {
+        query.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = filter.to {
+        query.push(" AND created_at < ").push_bind(to);
+    }
+    if let Some(actor) = &filter.actor {
+        query.push(" AND actor = ").push_bind(actor);
+    }
+    if let Some(action) = &filter.action {
+        query.push(" AND action = ").push_bind(action);
+    }
+}
+
+/// Adds the filters of `filter` to a query on `users`, except its cursor.
+fn push_user_filter<'a>(query: &mut QueryBuilder<'a, Sqlite>, filter: &'a UserFilter) {
+    if !filter.include_deleted {
+        query.push(" AND deleted_at IS NULL");
+    }
+    if let Some(email_id) = &filter.email_id {
+        query.push(" AND email_id = ").push_bind(email_id);
+    }
+    if let Some(prefix) = &filter.user_id_prefix {
+        // Unlike LIKE, substr is case sensitive and has no wildcards to escape
+        query
+            .push(" AND substr(user_id, 1, ")
+            .push_bind(prefix.chars().count() as i32)
+            .push(") = ")
+            .push_bind(prefix);
+    }
+}
+
+/// Adds the filters of `filter` to a query on `files`, except its cursor.
+fn push_file_filter<'a>(query: &mut QueryBuilder<'a, Sqlite>, filter: &'a FileFilter) {
+    if let Some(user_id) = &filter.user_id {
+        query.push(" AND user_id = ").push_bind(user_id);
+    }
+}
+
+/// Checks that one more file of `size` bytes of `user_id` fits in `limits`, as part
+/// of the transaction recording it.
+///
+/// The transaction takes the write lock of the database before reading the usage,
+/// so that concurrent uploads are checked one after the other, each counting the
+/// ones recorded before it.
+async fn check_quota(
+    conn: &mut SqliteConnection,
+    user_id: &str,
+    size: i64,
+    limits: &StorageLimits,
+) -> Result<(), Error> {
+    if limits.is_unlimited() {
+        return Ok(());
+    }
+
+    // Writes nothing, but holds the write lock until the transaction ends
+    sqlx::query("UPDATE users SET quota_bytes = quota_bytes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    let usage = select_usage(conn, user_id).await?;
+    let total = if limits.limits_total() {
+        select_total_usage(conn).await?
+    } else {
+        TotalUsage::default()
+    };
+
+    limits.check(&usage, &total, size)
+}
+
+/// Retrieves the storage used by a user, counting the full length of the uploads in
+/// progress, which can be completed until they expire.
+async fn select_usage(conn: &mut SqliteConnection, user_id: &str) -> Result<Usage, Error> {
+    let now = Utc::now();
+    sqlx::query_as::<_, Usage>(
+        r#"
+        SELECT
+            (SELECT COALESCE(SUM(size), 0) FROM files WHERE files.user_id = users.user_id)
+                + (SELECT COALESCE(SUM(length), 0) FROM uploads
+                   WHERE uploads.user_id = users.user_id AND file_id IS NULL AND expires_at > ?)
+                AS bytes,
+            (SELECT COUNT(*) FROM files WHERE files.user_id = users.user_id)
+                + (SELECT COUNT(*) FROM uploads
+                   WHERE uploads.user_id = users.user_id AND file_id IS NULL AND expires_at > ?)
+                AS files,
+            quota_bytes AS max_bytes,
+            quota_files AS max_files
+        FROM users WHERE user_id = ? AND deleted_at IS NULL
+        "#,
+    )
+    .bind(now)
+    .bind(now)
+    .bind(user_id)
+    .fetch_optional(conn)
+    .await?
+    .ok_or(Error::NotFound(Resource::User))
+}
+
+/// Retrieves the storage used by all users, counting the full length of the uploads
+/// in progress.
+async fn select_total_usage(conn: &mut SqliteConnection) -> Result<TotalUsage, Error> {
+    let now = Utc::now();
+    sqlx::query_as::<_, TotalUsage>(
+        r#"
+        SELECT
+            (SELECT COALESCE(SUM(size), 0) FROM files)
+                + (SELECT COALESCE(SUM(length), 0) FROM uploads
+                   WHERE file_id IS NULL AND expires_at > ?)
+                AS bytes,
+            (SELECT COUNT(*) FROM files)
+                + (SELECT COUNT(*) FROM uploads WHERE file_id IS NULL AND expires_at > ?)
+                AS files
+        "#,
+    )
+    .bind(now)
+    .bind(now)
+    .fetch_one(conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// Inserts a new user along with its `default` API key, as part of a transaction.
+/// With `only_first`, the user is only inserted if there is no other user.
+///
+/// # Returns
+///
+/// Whether the user was inserted.
+async fn insert_user(
+    conn: &mut SqliteConnection,
+    user: &User,
+    salt: &str,
+    only_first: bool,
+) -> Result<bool, Error> {
+    let inserted = sqlx::query(
+        r#"
+        INSERT INTO users (user_id, password, email_id, role, created_at, updated_at)
+        SELECT ?, ?, ?, ?, ?, ?
+        WHERE ? OR NOT EXISTS (SELECT 1 FROM users)
+        "#,
+    )
+    .bind(&user.user_id)
+    .bind(&user.password)
+    .bind(&user.email_id)
+    .bind(user.role.as_str())
+    .bind(user.created_at)
+    .bind(user.updated_at)
+    .bind(!only_first)
+    .execute(&mut *conn)
+    .await
+    .map_err(user_conflict)?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO api_keys (user_id, label, key_prefix, key_hash, created_at)
+        VALUES (?, 'default', ?, ?, ?)
+        "#,
+    )
+    .bind(&user.user_id)
+    .bind(api_key_prefix(&user.api_key))
+    .bind(hash_api_key(&user.api_key, salt))
+    .bind(Utc::now())
+    .execute(conn)
+    .await?;
+
+    Ok(true)
+}
+
+/// Records a stored file, possibly as part of a larger transaction.
+async fn insert_file(conn: &mut SqliteConnection, file: &NewFile) -> Result<FileInfo, Error> {
+    sqlx::query_as::<_, FileInfo>(
+        r#"
+        INSERT INTO files
+            (user_id, file_name, storage_key, size, content_type, sha256, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        RETURNING *
+        "#,
+    )
+    .bind(&file.user_id)
+    .bind(&file.file_name)
+    .bind(&file.storage_key)
+    .bind(file.size)
+    .bind(&file.content_type)
+    .bind(&file.sha256)
+    .bind(Utc::now())
+    .fetch_one(conn)
+    .await
+    .map_err(Into::into)
+}
+
+/// Appends an event to the audit log, as part of the transaction of the change it records.
+async fn insert_audit_event(
+    conn: &mut SqliteConnection,
+    context: &AuditContext,
+    event: &NewAuditEvent,
+) -> Result<(), Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_log
+            (created_at, actor, action, target, field, ip, user_agent, outcome, detail, request_id)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(Utc::now())
+    .bind(&context.actor)
+    .bind(&event.action)
+    .bind(&event.target)
+    .bind(&event.field)
+    .bind(&context.ip)
+    .bind(&context.user_agent)
+    .bind(event.outcome.as_str())
+    .bind(&event.detail)
+    .bind(&context.request_id)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrations::latest_version;
+
+    /// A database as created before migrations were introduced, with a single user.
+    async fn baseline_database() -> SqliteBackend {
+        let backend = SqliteBackend::new_connection(":memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE users (user_id TEXT PRIMARY KEY, password TEXT NOT NULL, \
+             api_key TEXT NOT NULL, email_id TEXT NOT NULL)",
+        )
+        .execute(&backend.pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO users VALUES ('alice', 'hash', 'key', 'alice@example.com')")
+            .execute(&backend.pool)
+            .await
+            .unwrap();
+        backend
+    }
+
+    #[rocket::async_test]
+    async fn upgrades_baseline_databases() {
+        let backend = baseline_database().await;
+        backend.create_migrations_table().await.unwrap();
+        let applied = backend.applied_migrations().await.unwrap();
+        assert_eq!(
+            migrations::pending(SQLITE_MIGRATIONS, &applied)
+                .unwrap()
+                .len(),
+            SQLITE_MIGRATIONS.len()
+        );
+
+        backend.migrate().await.unwrap();
+        let applied = backend.applied_migrations().await.unwrap();
+        let versions: Vec<i64> = applied.iter().map(|(v, _, _)| *v).collect();
+        let expected: Vec<i64> = SQLITE_MIGRATIONS.iter().map(|m| m.version).collect();
+        assert_eq!(versions, expected);
+        assert!(migrations::pending(SQLITE_MIGRATIONS, &applied)
+            .unwrap()
+            .is_empty());
+        let user_ids: Vec<String> = sqlx::query_scalar("SELECT user_id FROM users")
+            .fetch_all(&backend.pool)
+            .await
+            .unwrap();
+        assert_eq!(user_ids, ["alice"]);
+
+        // Nothing left to apply on the next start
+        backend.migrate().await.unwrap();
+        assert_eq!(backend.applied_migrations().await.unwrap(), applied);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_newer_schemas() {
+        let backend = baseline_database().await;
+        backend.migrate().await.unwrap();
+        let latest = latest_version(SQLITE_MIGRATIONS);
+        sqlx::query("INSERT INTO schema_migrations (version, name) VALUES (?, 'from_the_future')")
+            .bind(latest + 1)
+            .execute(&backend.pool)
+            .await
+            .unwrap();
+
+        let applied = backend.applied_migrations().await.unwrap();
+        assert!(matches!(
+            migrations::pending(SQLITE_MIGRATIONS, &applied),
+            Err(Error::SchemaTooNew(current, known)) if current == latest + 1 && known == latest
+        ));
+        assert!(matches!(
+            backend.migrate().await,
+            Err(Error::SchemaTooNew(current, _)) if current == latest + 1
+        ));
+    }
+}
@@ -1,12 +1,21 @@
 #![allow(clippy::enum_variant_names)]
 
+use crate::secure::guards::RequestId;
 use rocket::{
-    http::Status,
+    http::{ContentType, Status},
     request::Request,
     response::{self, Responder},
-    serde::json::Json,
+    serde::json::{serde_json, Json},
+    Config,
 };
-use serde::ser::{Serialize, SerializeStruct, Serializer};
+use rocket_okapi::{
+    gen::OpenApiGenerator,
+    okapi::openapi3::{MediaType, RefOr, Response as OpenApiResponse, Responses},
+    response::OpenApiResponderInner,
+    JsonSchema,
+};
+use serde::Serialize;
+use std::fmt;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -25,10 +34,18 @@ pub enum Error {
 
     #[error("Unauthenticated user")]
     UnauthenticatedUser,
+    #[error("API key has been revoked")]
+    ApiKeyRevoked,
+    #[error("API key has expired")]
+    ApiKeyExpired,
+    #[error("Session has expired")]
+    SessionExpired,
     #[error("User does not have access rights")]
     ForbiddenAccess,
+    #[error("Current password is incorrect")]
+    IncorrectPassword,
     #[error("{0} Not found")]
-    NotFound(String),
+    NotFound(Resource),
     #[error("Unknown route")]
     UnknownRoute,
     #[error("{0}")]
@@ -39,8 +56,36 @@ pub enum Error {
     InternalError,
     #[error("User conflict")]
     UserConflict,
+    #[error("Invalid input")]
+    InvalidInput(#[from] validator::ValidationErrors),
     #[error("Too many requests")]
     TooManyRequests,
+    #[error("Users already exist, refusing to bootstrap")]
+    AlreadyBootstrapped,
+    #[error("The passwords do not match")]
+    PasswordMismatch,
+    #[error("Only version 1.0.0 of the tus protocol is supported")]
+    UnsupportedTusVersion,
+    #[error("Unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("Upload-Offset does not match the current offset {0}")]
+    UploadOffsetMismatch(u64),
+    #[error("Upload is larger than the maximum of {0} bytes")]
+    UploadTooLarge(u64),
+    #[error("Upload has expired")]
+    UploadExpired,
+    #[error("Upload is being written by another request")]
+    UploadLocked,
+    #[error("Storage quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("File type is not allowed: {0}")]
+    FileTypeNotAllowed(String),
+    #[error("File content does not match its type: {0}")]
+    FileTypeMismatch(String),
+    #[error("Download link is invalid")]
+    InvalidDownloadLink,
+    #[error("Download link has expired or was already used")]
+    DownloadLinkExpired,
 
     #[error("Configuration Error")]
     ConfigurationError,
@@ -50,8 +95,28 @@ pub enum Error {
     DatabaseNotConfigured,
     #[error("Config file not found")]
     ConfigFileNotFound,
+    #[error(
+        "api_key_length must be between {} and {}, got {0}",
+        crate::secure::token::API_KEY_MIN_LENGTH,
+        crate::secure::token::API_KEY_MAX_LENGTH
+    )]
+    InvalidApiKeyLength(usize),
+    #[error("Invalid argon2 configuration: {0}")]
+    InvalidArgon2Config(String),
+    #[error("Invalid password policy: {0}")]
+    InvalidPasswordPolicy(String),
+    #[error("Invalid storage configuration: {0}")]
+    InvalidStorageConfig(String),
+    #[error("Invalid content type configuration: {0}")]
+    InvalidContentTypeConfig(String),
+    #[error("Storage error: {0}")]
+    StorageError(String),
     #[error("Empty DB Url")]
     EmptyDBUrl,
+    #[error("Unsupported database url: {0}")]
+    UnsupportedDatabase(String),
+    #[error("Database schema version {0} is newer than the latest supported version {1}")]
+    SchemaTooNew(i64, i64),
     #[error("{0}")]
     Config(#[from] config::ConfigError),
     #[error("{0}")]
@@ -72,16 +137,237 @@ impl Error {
     /// The equivalent HTTP status for the given ErrorResponse.
     pub fn to_status(&self) -> Status {
         match *self {
-            Self::UnauthenticatedUser => Status::Unauthorized,
-            Self::ForbiddenAccess => Status::Forbidden,
-            Self::BadRequest(_) | Self::InvalidResult(_) | Self::UserConflict => Status::BadRequest,
+            Self::UnauthenticatedUser
+            | Self::ApiKeyRevoked
+            | Self::ApiKeyExpired
+            | Self::SessionExpired => Status::Unauthorized,
+            Self::ForbiddenAccess | Self::IncorrectPassword => Status::Forbidden,
+            Self::BadRequest(_) | Self::InvalidResult(_) => Status::BadRequest,
+            Self::UserConflict => Status::Conflict,
+            Self::InvalidInput(_) => Status::UnprocessableEntity,
             Self::NotFound(_) | Self::UnknownRoute => Status::NotFound,
             Self::TooManyRequests => Status::TooManyRequests,
+            Self::UnsupportedTusVersion => Status::PreconditionFailed,
+            Self::UnsupportedMediaType(_) => Status::UnsupportedMediaType,
+            Self::UploadOffsetMismatch(_) => Status::Conflict,
+            Self::UploadTooLarge(_) => Status::PayloadTooLarge,
+            Self::UploadExpired => Status::Gone,
+            Self::UploadLocked => Status::Locked,
+            Self::QuotaExceeded(_) => Status::InsufficientStorage,
+            Self::FileTypeNotAllowed(_) => Status::UnsupportedMediaType,
+            Self::FileTypeMismatch(_) => Status::UnsupportedMediaType,
+            Self::InvalidDownloadLink => Status::Forbidden,
+            Self::DownloadLinkExpired => Status::Gone,
             _ => Status::InternalServerError,
         }
     }
 }
 
+/// The kinds of records an `Error::NotFound` can refer to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    User,
+    ApiKey,
+    Session,
+    File,
+    Upload,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::User => "User",
+            Self::ApiKey => "API key",
+            Self::Session => "Session",
+            Self::File => "File",
+            Self::Upload => "Upload",
+        })
+    }
+}
+
+/// A stable, machine-readable identifier of an error, sent as the `code` of every
+/// problem. Codes are never renamed or reused; new ones may be added.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    DatabaseError,
+    FormatError,
+    LaunchFailed,
+    LoggingError,
+    Argon2Error,
+    PasswordHashError,
+    Unauthenticated,
+    ApiKeyRevoked,
+    ApiKeyExpired,
+    SessionExpired,
+    Forbidden,
+    IncorrectPassword,
+    UserNotFound,
+    ApiKeyNotFound,
+    SessionNotFound,
+    FileNotFound,
+    UploadNotFound,
+    UnknownRoute,
+    BadRequest,
+    InvalidResult,
+    InternalError,
+    UserConflict,
+    InvalidInput,
+    TooManyRequests,
+    AlreadyBootstrapped,
+    PasswordMismatch,
+    UnsupportedTusVersion,
+    UnsupportedMediaType,
+    UploadOffsetMismatch,
+    UploadTooLarge,
+    UploadExpired,
+    UploadLocked,
+    QuotaExceeded,
+    FileTypeNotAllowed,
+    FileTypeMismatch,
+    InvalidDownloadLink,
+    DownloadLinkExpired,
+    ConfigurationError,
+    AppConfigurationError,
+    DatabaseNotConfigured,
+    ConfigFileNotFound,
+    InvalidApiKeyLength,
+    InvalidArgon2Config,
+    InvalidPasswordPolicy,
+    InvalidStorageConfig,
+    InvalidContentTypeConfig,
+    StorageError,
+    EmptyDbUrl,
+    UnsupportedDatabase,
+    SchemaTooNew,
+    ConfigError,
+    IoError,
+    Unknown,
+}
+
+impl fmt::Display for ErrorCode {
+    /// Writes the code as sent in problems, e.g. `DATABASE_ERROR`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(code)) => f.write_str(&code),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl ErrorCode {
+    /// The code of a problem only known by its HTTP status, e.g. one raised by Rocket
+    /// before reaching a route.
+    pub fn from_status(status: Status) -> Self {
+        match status.code {
+            401 => Self::Unauthenticated,
+            403 => Self::Forbidden,
+            404 => Self::UnknownRoute,
+            422 => Self::InvalidInput,
+            429 => Self::TooManyRequests,
+            code if code < 500 => Self::BadRequest,
+            _ => Self::InternalError,
+        }
+    }
+}
+
+impl Error {
+    /// Returns the stable code identifying this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::PoolError(_) => ErrorCode::DatabaseError,
+            Self::FormatError(_) => ErrorCode::FormatError,
+            Self::RocketError(_) => ErrorCode::LaunchFailed,
+            Self::LoggingError(_) => ErrorCode::LoggingError,
+            Self::Argon2Error(_) => ErrorCode::Argon2Error,
+            Self::PasswordHashError => ErrorCode::PasswordHashError,
+            Self::UnauthenticatedUser => ErrorCode::Unauthenticated,
+            Self::ApiKeyRevoked => ErrorCode::ApiKeyRevoked,
+            Self::ApiKeyExpired => ErrorCode::ApiKeyExpired,
+            Self::SessionExpired => ErrorCode::SessionExpired,
+            Self::ForbiddenAccess => ErrorCode::Forbidden,
+            Self::IncorrectPassword => ErrorCode::IncorrectPassword,
+            Self::NotFound(Resource::User) => ErrorCode::UserNotFound,
+            Self::NotFound(Resource::ApiKey) => ErrorCode::ApiKeyNotFound,
+            Self::NotFound(Resource::Session) => ErrorCode::SessionNotFound,
+            Self::NotFound(Resource::File) => ErrorCode::FileNotFound,
+            Self::NotFound(Resource::Upload) => ErrorCode::UploadNotFound,
+            Self::UnknownRoute => ErrorCode::UnknownRoute,
+            Self::BadRequest(_) => ErrorCode::BadRequest,
+            Self::InvalidResult(_) => ErrorCode::InvalidResult,
+            Self::InternalError => ErrorCode::InternalError,
+            Self::UserConflict => ErrorCode::UserConflict,
+            Self::InvalidInput(_) => ErrorCode::InvalidInput,
+            Self::TooManyRequests => ErrorCode::TooManyRequests,
+            Self::AlreadyBootstrapped => ErrorCode::AlreadyBootstrapped,
+            Self::PasswordMismatch => ErrorCode::PasswordMismatch,
+            Self::UnsupportedTusVersion => ErrorCode::UnsupportedTusVersion,
+            Self::UnsupportedMediaType(_) => ErrorCode::UnsupportedMediaType,
+            Self::UploadOffsetMismatch(_) => ErrorCode::UploadOffsetMismatch,
+            Self::UploadTooLarge(_) => ErrorCode::UploadTooLarge,
+            Self::UploadExpired => ErrorCode::UploadExpired,
+            Self::UploadLocked => ErrorCode::UploadLocked,
+            Self::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+            Self::FileTypeNotAllowed(_) => ErrorCode::FileTypeNotAllowed,
+            Self::FileTypeMismatch(_) => ErrorCode::FileTypeMismatch,
+            Self::InvalidDownloadLink => ErrorCode::InvalidDownloadLink,
+            Self::DownloadLinkExpired => ErrorCode::DownloadLinkExpired,
+            Self::ConfigurationError => ErrorCode::ConfigurationError,
+            Self::AppConfigurationError => ErrorCode::AppConfigurationError,
+            Self::DatabaseNotConfigured => ErrorCode::DatabaseNotConfigured,
+            Self::ConfigFileNotFound => ErrorCode::ConfigFileNotFound,
+            Self::InvalidApiKeyLength(_) => ErrorCode::InvalidApiKeyLength,
+            Self::InvalidArgon2Config(_) => ErrorCode::InvalidArgon2Config,
+            Self::InvalidPasswordPolicy(_) => ErrorCode::InvalidPasswordPolicy,
+            Self::InvalidStorageConfig(_) => ErrorCode::InvalidStorageConfig,
+            Self::InvalidContentTypeConfig(_) => ErrorCode::InvalidContentTypeConfig,
+            Self::StorageError(_) => ErrorCode::StorageError,
+            Self::EmptyDBUrl => ErrorCode::EmptyDbUrl,
+            Self::UnsupportedDatabase(_) => ErrorCode::UnsupportedDatabase,
+            Self::SchemaTooNew(_, _) => ErrorCode::SchemaTooNew,
+            Self::Config(_) => ErrorCode::ConfigError,
+            Self::Io(_) => ErrorCode::IoError,
+            Self::Unknown => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// Describes why the value of a field was rejected.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct FieldError {
+    pub field: String,
+    /// The rule that failed, e.g. `email` or `length`
+    pub code: String,
+    pub message: String,
+}
+
+impl Error {
+    /// Lists the rejected fields of an `Error::InvalidInput`, sorted by field.
+    pub fn field_errors(&self) -> Vec<FieldError> {
+        let Self::InvalidInput(errors) = self else {
+            return Vec::new();
+        };
+
+        let mut field_errors: Vec<FieldError> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |e| FieldError {
+                    field: field.to_string(),
+                    code: e.code.to_string(),
+                    message: e
+                        .message
+                        .as_ref()
+                        .map_or_else(|| e.code.to_string(), |message| message.to_string()),
+                })
+            })
+            .collect();
+        field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+        field_errors
+    }
+}
+
 impl From<rocket::serde::json::Error<'_>> for Error {
     /// Converts a `rocket::serde::json::Error` into an `Error` enum.
     ///
@@ -97,30 +383,8 @@ impl From<rocket::serde::json::Error<'_>> for Error {
     }
 }
 
-impl Serialize for Error {
-    /// Serializes the Error struct.
-    ///
-    /// # Arguments
-    ///
-    /// * `serializer` - The serializer used for serialization.
-    ///
-    /// # Returns
-    ///
-    /// The Result with the serialized value or an error if serialization fails.
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("Error", 2)?;
-        state.serialize_field("error", &self.to_string())?;
-        state.serialize_field("code", &self.to_status().code)?;
-
-        state.end()
-    }
-}
-
 impl<'r> Responder<'r, 'static> for Error {
-    /// Generates a response based on the specified request.
+    /// Responds with the `Problem` describing this error.
     ///
     /// # Arguments
     ///
@@ -131,10 +395,113 @@ impl<'r> Responder<'r, 'static> for Error {
     ///
     /// Returns a `Result` containing a `Response` if the generation was successful, or an error if it failed.
     fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
-        let status = self.to_status();
+        Problem::from_error(&self, request).respond_to(request)
+    }
+}
+
+impl OpenApiResponderInner for Error {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        Problem::responses(gen)
+    }
+}
+
+/// An RFC 7807 problem details object, the body of every error response, sent as
+/// `application/problem+json`.
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct Problem {
+    /// A URI identifying the type of problem, `about:blank` when the status says it all
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// A short summary of the type of problem
+    pub title: String,
+    /// The HTTP status code
+    pub status: u16,
+    /// The stable code of the error
+    pub code: ErrorCode,
+    /// An explanation specific to this occurrence of the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The path of the request
+    pub instance: String,
+    /// The ID of the request, as sent back in the `X-Request-Id` header
+    pub request_id: String,
+    /// The rejected fields, for invalid input
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[schemars(default)]
+    pub errors: Vec<FieldError>,
+}
+
+impl Problem {
+    /// Describes a problem only known by its HTTP status.
+    pub fn new(status: Status, request: &Request<'_>) -> Self {
+        Self {
+            problem_type: "about:blank".to_string(),
+            title: status.reason_lossy().to_string(),
+            status: status.code,
+            code: ErrorCode::from_status(status),
+            detail: None,
+            instance: request.uri().path().to_string(),
+            request_id: RequestId::of(request).0.to_owned(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Describes an error that occurred while handling `request`.
+    ///
+    /// Unless Rocket runs with the debug profile, server errors only keep their code:
+    /// their message may contain SQL or file paths, so it is logged instead.
+    pub fn from_error(error: &Error, request: &Request<'_>) -> Self {
+        let status = error.to_status();
+        let problem = Self::new(status, request);
+        let detail = if status.class().is_server_error()
+            && request.rocket().config().profile != Config::DEBUG_PROFILE
+        {
+            log::error!("Request {} failed: {}", problem.request_id, error);
+            None
+        } else {
+            Some(error.to_string())
+        };
+
+        Self {
+            code: error.code(),
+            detail,
+            errors: error.field_errors(),
+            ..problem
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Problem {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
 
         response::Response::build_from(Json(self).respond_to(request)?)
             .status(status)
+            .header(ContentType::new("application", "problem+json"))
             .ok()
     }
 }
+
+impl OpenApiResponderInner for Problem {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        responses.responses.insert(
+            "default".to_string(),
+            RefOr::Object(OpenApiResponse {
+                description: "The error, as an RFC 7807 problem".to_string(),
+                content: [(
+                    "application/problem+json".to_string(),
+                    MediaType {
+                        schema: Some(gen.json_schema::<Problem>()),
+                        ..Default::default()
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            }),
+        );
+
+        Ok(responses)
+    }
+}
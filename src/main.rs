@@ -10,12 +10,16 @@ use jemallocator::Jemalloc;
 static GLOBAL: Jemalloc = Jemalloc;
 
 #[rocket::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> Result<(), Box<Error>> {
     // start the server
     match init_server().await {
-        Ok(server) => server.launch().await.map(|_| ()),
+        Ok(Some(server)) => server.launch().await.map(|_| ()).map_err(Box::new),
+        Ok(None) => Ok(()),
         Err(e) => {
             println!("{}", e);
+            for field_error in e.field_errors() {
+                println!("  {}: {}", field_error.field, field_error.message);
+            }
             exit(1)
         }
     }
@@ -1,32 +1,612 @@
+pub(crate) mod validate;
+
+use self::validate::{validate_password, validate_user_id, USER_ID_MAX_LENGTH};
+use crate::{error::Error, server::config::PasswordPolicy};
+use chrono::{DateTime, Utc};
+use rocket::FromFormField;
 use rocket_okapi::JsonSchema;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::net::IpAddr;
+use validator::Validate;
+
+/// Access level of a user.
+///
+/// - `admin`: can manage every user
+/// - `user`: can read and update their own account
+/// - `read_only`: can only read their own account
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Admin,
+    #[default]
+    User,
+    ReadOnly,
+}
+
+impl Role {
+    /// Returns the value stored in the `role` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::User => "user",
+            Self::ReadOnly => "read_only",
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = Error;
+
+    fn try_from(role: String) -> Result<Self, Self::Error> {
+        match role.as_str() {
+            "admin" => Ok(Self::Admin),
+            "user" => Ok(Self::User),
+            "read_only" => Ok(Self::ReadOnly),
+            _ => Err(Error::InvalidResult(format!("Unknown role: {}", role))),
+        }
+    }
+}
 
-/// Struct representing an API user.
+/// Struct representing an API user, as returned on creation along with its `default` API key.
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
 pub struct User {
     pub api_key: String,
     pub user_id: String,
+    /// The password hash, never sent back
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
     pub password: String,
     pub email_id: String,
+    #[sqlx(try_from = "String")]
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
 pub struct UserInfo {
-    pub api_key: String,
     pub user_id: String,
     pub email_id: String,
+    #[sqlx(try_from = "String")]
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the user was deleted, `None` unless they can still be restored
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+impl UserInfo {
+    /// Returns `true` if the user can manage every other user.
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema, Validate)]
+#[validate(context = PasswordPolicy)]
 pub struct NewUser {
+    /// 1 to 64 ASCII letters, digits, `_`, `-` or `.`, starting with a letter or digit
+    #[validate(
+        length(
+            min = 1,
+            max = "USER_ID_MAX_LENGTH",
+            message = "must be 1 to 64 characters"
+        ),
+        custom(function = "validate_user_id")
+    )]
     pub user_id: String,
+    /// Must follow the password policy of the server
+    #[validate(custom(function = "validate_password", use_context))]
     pub password: String,
+    #[validate(email(message = "must be a valid email address"))]
     pub email_id: String,
+    /// Defaults to `user`
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub role: Role,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema, Validate)]
+#[validate(context = PasswordPolicy)]
 pub struct UpdateUser {
+    /// Can only be changed by an admin, and must follow the password policy of the server
+    #[validate(custom(function = "validate_password", use_context))]
     pub password: Option<String>,
-    pub api_key: Option<String>,
+    /// Can only be changed by an admin
+    pub role: Option<Role>,
+}
+
+/// Who performs a change, as recorded in the audit log.
+#[derive(Clone, Debug)]
+pub struct AuditContext {
+    /// ID of the authenticated user performing the change
+    pub actor: String,
+    /// IP address the request came from
+    pub ip: String,
+    pub user_agent: String,
+    /// ID of the request, as sent back in the `X-Request-Id` header
+    pub request_id: String,
+}
+
+/// Whether an audited action succeeded.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    /// Returns the value stored in the `outcome` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+impl TryFrom<String> for AuditOutcome {
+    type Error = Error;
+
+    fn try_from(outcome: String) -> Result<Self, Self::Error> {
+        match outcome.as_str() {
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            _ => Err(Error::InvalidResult(format!(
+                "Unknown outcome: {}",
+                outcome
+            ))),
+        }
+    }
+}
+
+/// An entry of the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// ID of the user who performed the action
+    pub actor: String,
+    /// What was done, e.g. `user.update`
+    pub action: String,
+    /// What the action was performed on, e.g. a user ID
+    pub target: String,
+    /// The changed field, for updates
+    pub field: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub outcome: AuditOutcome,
+    /// The error, for failed actions
+    pub detail: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// An action to record in the audit log, along with an `AuditContext`.
+#[derive(Clone, Debug)]
+pub struct NewAuditEvent {
+    pub action: String,
+    pub target: String,
+    pub field: Option<String>,
+    pub outcome: AuditOutcome,
+    pub detail: Option<String>,
+}
+
+impl NewAuditEvent {
+    /// Describes a successful `action` on `target`.
+    pub fn success(action: &str, target: &str) -> Self {
+        Self {
+            action: action.to_string(),
+            target: target.to_string(),
+            field: None,
+            outcome: AuditOutcome::Success,
+            detail: None,
+        }
+    }
+
+    /// Describes `action` on `target`, which succeeded or failed depending on `result`.
+    ///
+    /// Server errors are only described by their code: like in problems, their
+    /// message may contain SQL or file paths, which the log would keep forever.
+    pub fn from_result<T>(action: &str, target: &str, result: &Result<T, Error>) -> Self {
+        match result {
+            Ok(_) => Self::success(action, target),
+            Err(e) => Self {
+                outcome: AuditOutcome::Failure,
+                detail: Some(if e.to_status().class().is_server_error() {
+                    e.code().to_string()
+                } else {
+                    e.to_string()
+                }),
+                ..Self::success(action, target)
+            },
+        }
+    }
+
+    /// Sets the changed field.
+    pub fn with_field(mut self, field: &str) -> Self {
+        self.field = Some(field.to_string());
+        self
+    }
+}
+
+/// Filters of the audit log, newest events first.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    /// Only events at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only events before this time
+    pub to: Option<DateTime<Utc>>,
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    /// Only events older than the event with this ID
+    pub before_id: Option<i64>,
+    pub limit: i64,
+}
+
+/// Sort orders of the user list. A leading `-` sorts in descending order, and
+/// users with the same email ID are sorted by user ID.
+#[derive(
+    Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, FromFormField, JsonSchema,
+)]
+pub enum UserSort {
+    #[default]
+    #[serde(rename = "user_id")]
+    #[field(value = "user_id")]
+    UserId,
+    #[serde(rename = "-user_id")]
+    #[field(value = "-user_id")]
+    UserIdDesc,
+    #[serde(rename = "email_id")]
+    #[field(value = "email_id")]
+    EmailId,
+    #[serde(rename = "-email_id")]
+    #[field(value = "-email_id")]
+    EmailIdDesc,
+}
+
+impl UserSort {
+    /// Returns the column users are sorted by.
+    pub fn column(&self) -> &'static str {
+        match self {
+            Self::UserId | Self::UserIdDesc => "user_id",
+            Self::EmailId | Self::EmailIdDesc => "email_id",
+        }
+    }
+
+    /// Returns `true` for descending orders.
+    pub fn is_descending(&self) -> bool {
+        matches!(self, Self::UserIdDesc | Self::EmailIdDesc)
+    }
+
+    /// Returns the value of the sort column for `user`.
+    pub fn key<'a>(&self, user: &'a UserInfo) -> &'a str {
+        match self {
+            Self::UserId | Self::UserIdDesc => &user.user_id,
+            Self::EmailId | Self::EmailIdDesc => &user.email_id,
+        }
+    }
+}
+
+/// Filters of the user list.
+#[derive(Clone, Debug, Default)]
+pub struct UserFilter {
+    /// Only the user with this email ID
+    pub email_id: Option<String>,
+    /// Only users whose ID starts with this prefix
+    pub user_id_prefix: Option<String>,
+    /// Also list the deleted users that can still be restored
+    pub include_deleted: bool,
+    pub sort: UserSort,
+    /// Only users after this sort column value and user ID, in the sort order
+    pub after: Option<(String, String)>,
+    pub limit: i64,
+}
+
+/// A page of results, along with the cursor of the next page.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Pass as `after` to get the next page, `None` on the last page
+    pub next_cursor: Option<String>,
+    /// The number of items matching the filters, over all pages
+    pub total: i64,
+}
+
+/// Self-service password change.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema, Validate)]
+#[validate(context = PasswordPolicy)]
+pub struct PasswordChange {
+    pub current_password: String,
+    /// Must follow the password policy of the server
+    #[validate(custom(function = "validate_password", use_context))]
+    pub new_password: String,
+}
+
+/// One of the API keys of a user. Only a hash of the key is stored, so the key
+/// itself is only known when it is created or rotated.
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+pub struct ApiKey {
+    pub id: i64,
+    pub user_id: String,
+    /// Free form name of the key, e.g. the deployment using it
+    pub label: String,
+    /// First characters of the key, to tell keys apart
+    pub key_prefix: String,
+    pub created_at: DateTime<Utc>,
+    /// The key is rejected after this time, `None` if it never expires
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+impl ApiKey {
+    /// Returns `true` if the key has an expiry time in the past.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+/// A freshly created or rotated API key, the only time the full key is returned.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub api_key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct NewApiKey {
+    pub label: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A login session. Only a hash of its refresh token is stored.
+#[derive(Clone, Debug, FromRow)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub refresh_hash: String,
+    pub created_at: DateTime<Utc>,
+    /// The session can no longer be refreshed after this time
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct LoginRequest {
+    pub user_id: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Tokens of a session, returned on login and refresh.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct SessionTokens {
+    /// Short-lived token to pass in the `Authorization: Bearer` header
+    pub access_token: String,
+    /// Always `Bearer`
+    pub token_type: String,
+    /// Lifetime of the access token, in seconds
+    pub expires_in: i64,
+    /// Single use token to get new tokens from `/auth/refresh`
+    pub refresh_token: String,
+    /// The session can no longer be refreshed after this time
+    pub refresh_expires_at: DateTime<Utc>,
+}
+
+/// An uploaded file. Its content lives in the storage backend of the server.
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+pub struct FileInfo {
+    pub id: i64,
+    /// ID of the user who uploaded the file
+    pub user_id: String,
+    /// Name of the file on the client, without control characters
+    pub file_name: String,
+    /// Key of the content in the storage backend
+    #[serde(skip_serializing)]
+    #[schemars(skip)]
+    pub storage_key: String,
+    /// Size in bytes
+    pub size: i64,
+    /// The content type sent with the file, if any
+    pub content_type: Option<String>,
+    /// Hex encoded SHA-256 digest of the content
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A file whose content has just been stored.
+#[derive(Clone, Debug)]
+pub struct NewFile {
+    pub user_id: String,
+    pub file_name: String,
+    pub storage_key: String,
+    pub size: i64,
+    pub content_type: Option<String>,
+    pub sha256: String,
+}
+
+/// A resumable upload, in progress or completed into a file. The received content
+/// is kept outside of the database until it is complete.
+#[derive(Clone, Debug, FromRow)]
+pub struct Upload {
+    pub id: String,
+    /// ID of the user who created the upload
+    pub user_id: String,
+    /// Name of the file on the client, without control characters
+    pub file_name: String,
+    /// The content type announced on creation, if any
+    pub content_type: Option<String>,
+    /// Size in bytes of the whole file
+    pub length: i64,
+    /// The file the upload was completed into
+    pub file_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    /// The upload can no longer be resumed after this time
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Upload {
+    /// Returns `true` if the upload can no longer be resumed.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+/// A resumable upload about to be created.
+#[derive(Clone, Debug)]
+pub struct NewUpload {
+    pub id: String,
+    pub user_id: String,
+    pub file_name: String,
+    pub content_type: Option<String>,
+    pub length: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Options of a signed link to download a file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema)]
+pub struct NewDownloadLink {
+    /// Lifetime of the link in seconds, 1 hour by default
+    pub expires_in: Option<u32>,
+    /// The link can only be used once
+    #[serde(default)]
+    pub single_use: bool,
+    /// The only client IP address the link can be used from
+    pub ip: Option<IpAddr>,
+}
+
+/// A signed link to download a file without credentials.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+pub struct DownloadLink {
+    /// Path and query of the link, relative to the URL of the server
+    pub url: String,
+    /// The link is rejected after this time
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The storage used by a user, against their quota.
+#[derive(Serialize, Deserialize, Clone, Debug, FromRow, JsonSchema)]
+pub struct Usage {
+    /// Total size in bytes of the files of the user, and of their uploads in progress
+    pub bytes: i64,
+    /// Number of files of the user, uploads in progress included
+    pub files: i64,
+    /// Maximum number of bytes, `null` if unlimited
+    pub max_bytes: Option<i64>,
+    /// Maximum number of files, `null` if unlimited
+    pub max_files: Option<i64>,
+}
+
+/// The storage used by all users together.
+#[derive(Clone, Copy, Debug, Default, FromRow)]
+pub struct TotalUsage {
+    /// Total size in bytes of the files and uploads in progress
+    pub bytes: i64,
+    /// Number of files, uploads in progress included
+    pub files: i64,
+}
+
+/// The own quota of a user, whose `null` limits are the server defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, JsonSchema, Validate)]
+pub struct UserQuota {
+    #[validate(range(min = 0))]
+    pub max_bytes: Option<i64>,
+    #[validate(range(min = 0))]
+    pub max_files: Option<i64>,
+}
+
+/// The quotas a new file or upload is checked against as it is recorded, `None`
+/// standing for no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StorageLimits {
+    /// Maximum number of bytes of the user
+    pub user_bytes: Option<i64>,
+    /// Maximum number of files of the user
+    pub user_files: Option<i64>,
+    /// Maximum number of bytes of all users together
+    pub total_bytes: Option<i64>,
+    /// Maximum number of files of all users together
+    pub total_files: Option<i64>,
+}
+
+impl StorageLimits {
+    /// Returns `true` if nothing is limited.
+    pub fn is_unlimited(&self) -> bool {
+        self.user_bytes.is_none() && self.user_files.is_none() && !self.limits_total()
+    }
+
+    /// Returns `true` if the storage of all users together is limited.
+    pub fn limits_total(&self) -> bool {
+        self.total_bytes.is_some() || self.total_files.is_some()
+    }
+
+    /// Checks that one more file of `size` bytes fits, given the storage already used
+    /// by its user, `usage`, and by all users together, `total`.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::QuotaExceeded)` naming the first quota the file does not fit in.
+    pub fn check(&self, usage: &Usage, total: &TotalUsage, size: i64) -> Result<(), Error> {
+        if let Some(max) = self.user_files.filter(|max| usage.files >= *max) {
+            return Err(Quota::UserFiles(max).into());
+        }
+        if let Some(max) = self.user_bytes.filter(|max| usage.bytes + size > *max) {
+            return Err(Quota::UserBytes(max).into());
+        }
+        if let Some(max) = self.total_files.filter(|max| total.files >= *max) {
+            return Err(Quota::TotalFiles(max).into());
+        }
+        if let Some(max) = self.total_bytes.filter(|max| total.bytes + size > *max) {
+            return Err(Quota::TotalBytes(max).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// A storage quota, as reported once exceeded.
+#[derive(Clone, Copy, Debug)]
+pub enum Quota {
+    UserBytes(i64),
+    UserFiles(i64),
+    TotalBytes(i64),
+    TotalFiles(i64),
+}
+
+impl From<Quota> for Error {
+    fn from(quota: Quota) -> Self {
+        Error::QuotaExceeded(match quota {
+            Quota::UserBytes(max) => format!("at most {} bytes per user", max),
+            Quota::UserFiles(max) => format!("at most {} files per user", max),
+            Quota::TotalBytes(max) => format!("at most {} bytes on the server", max),
+            Quota::TotalFiles(max) => format!("at most {} files on the server", max),
+        })
+    }
+}
+
+/// What remains to delete outside of the database once a user has been purged.
+#[derive(Clone, Debug, Default)]
+pub struct PurgedUser {
+    /// Storage keys of the files of the user
+    pub storage_keys: Vec<String>,
+    /// IDs of the resumable uploads of the user
+    pub upload_ids: Vec<String>,
+}
+
+/// Filters of the file list, newest files first.
+#[derive(Clone, Debug, Default)]
+pub struct FileFilter {
+    /// Only files uploaded by this user ID
+    pub user_id: Option<String>,
+    /// Only files older than the file with this ID
+    pub before_id: Option<i64>,
+    pub limit: i64,
 }
@@ -0,0 +1,126 @@
+//! Custom rules of the user input validation.
+
+use crate::server::config::PasswordPolicy;
+use std::borrow::Cow;
+use validator::ValidationError;
+
+/// Maximum number of characters of a user ID
+pub(crate) const USER_ID_MAX_LENGTH: u64 = 64;
+
+/// Accepts user IDs made of ASCII letters, digits, `_`, `-` and `.`, starting with a
+/// letter or a digit, as they end up in URLs and storage paths.
+pub(crate) fn validate_user_id(user_id: &str) -> Result<(), ValidationError> {
+    let valid_start = user_id.starts_with(|c: char| c.is_ascii_alphanumeric());
+    let valid_chars = user_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+    if valid_start && valid_chars {
+        Ok(())
+    } else {
+        Err(error(
+            "charset",
+            "must only contain ASCII letters, digits, `_`, `-` and `.`, and start with a letter or digit".to_string(),
+        ))
+    }
+}
+
+/// Checks a new password against the configured policy.
+pub(crate) fn validate_password(
+    password: &str,
+    policy: &PasswordPolicy,
+) -> Result<(), ValidationError> {
+    let length = password.chars().count();
+    if length < policy.min_length || length > policy.max_length {
+        return Err(error(
+            "length",
+            format!(
+                "must be between {} and {} characters",
+                policy.min_length, policy.max_length
+            ),
+        ));
+    }
+
+    let has = |matches: fn(char) -> bool| password.chars().any(matches);
+    let missing = if policy.require_lowercase && !has(char::is_lowercase) {
+        Some("a lowercase letter")
+    } else if policy.require_uppercase && !has(char::is_uppercase) {
+        Some("an uppercase letter")
+    } else if policy.require_digit && !has(|c| c.is_ascii_digit()) {
+        Some("a digit")
+    } else if policy.require_symbol && !has(|c| !c.is_alphanumeric()) {
+        Some("a character that is neither a letter nor a digit")
+    } else {
+        None
+    };
+    if let Some(missing) = missing {
+        return Err(error("complexity", format!("must contain {}", missing)));
+    }
+
+    Ok(())
+}
+
+/// Maximum number of bytes of the name of an uploaded file
+pub(crate) const FILE_NAME_MAX_LENGTH: usize = 255;
+
+/// Device names reserved by Windows in every directory, whatever the extension.
+const RESERVED_FILE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Cleans up the name of an uploaded file, which is only kept as metadata: control
+/// characters are dropped and surrounding whitespace is trimmed.
+///
+/// Names that are empty, that could point to another directory such as `..`,
+/// `../x`, `/etc/passwd` or `C:x`, or that Windows reserves for devices such as
+/// `NUL.txt` are rejected rather than cleaned up.
+pub(crate) fn sanitize_file_name(name: &str) -> Result<String, ValidationError> {
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+
+    if name.is_empty() {
+        return Err(error("required", "must not be empty".to_string()));
+    }
+    if name.contains(['/', '\\', ':']) || name.chars().all(|c| c == '.') {
+        return Err(error(
+            "path",
+            "must be a plain file name, without directories or drive".to_string(),
+        ));
+    }
+    // Windows ignores the extension and trailing dots and spaces of device names
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_FILE_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(error(
+            "reserved",
+            "must not be a reserved device name".to_string(),
+        ));
+    }
+    if name.len() > FILE_NAME_MAX_LENGTH {
+        return Err(error(
+            "length",
+            format!("must be at most {} bytes long", FILE_NAME_MAX_LENGTH),
+        ));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Checks the requested lifetime of a download link, in seconds.
+pub(crate) fn validate_link_ttl(expires_in: u32, max: u32) -> Result<(), ValidationError> {
+    if expires_in == 0 || expires_in > max {
+        return Err(error(
+            "range",
+            format!("must be between 1 and {} seconds", max),
+        ));
+    }
+
+    Ok(())
+}
+
+fn error(code: &'static str, message: String) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Owned(message))
+}
@@ -1,4 +1,9 @@
-use crate::{db::SqliteBackend, error::Error, models::UserInfo, secure::guards::GuardedData};
+use crate::{
+    db::DynBackend,
+    error::Error,
+    models::{Role, UserInfo},
+    secure::guards::{guard_error, GuardedData},
+};
 use derive_more::Deref;
 use rocket::{
     http::Status,
@@ -18,16 +23,109 @@ impl<'r> FromRequest<'r> for ApiKeyGuard {
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let backend = try_outcome!(request
-            .guard::<&State<SqliteBackend>>()
+            .guard::<&State<DynBackend>>()
+            .await
+            .map_error(|_| (Status::InternalServerError, Error::InternalError)));
+        let salt = try_outcome!(request
+            .guard::<&State<String>>()
             .await
             .map_error(|_| (Status::InternalServerError, Error::InternalError)));
 
-        match super::get_user_from_request(request, backend)
+        match super::get_user_from_request(request, backend.as_ref(), salt)
             .await
             .map(Self)
         {
             Ok(guard) => Outcome::Success(guard),
-            Err(e) => Outcome::Error((e.to_status(), e)),
+            Err(e) => guard_error(request, e),
+        }
+    }
+}
+
+/// Authenticates requests from the access token of their `Authorization: Bearer`
+/// header, as issued by `/auth/login`.
+#[derive(Serialize, Deserialize, Deref, OpenApiFromRequest)]
+pub struct SessionGuard {
+    #[deref]
+    pub user: GuardedData<UserInfo>,
+    /// ID of the session the token belongs to
+    pub session_id: String,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SessionGuard {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let backend = try_outcome!(request
+            .guard::<&State<DynBackend>>()
+            .await
+            .map_error(|_| (Status::InternalServerError, Error::InternalError)));
+        let secret = try_outcome!(request
+            .guard::<&State<String>>()
+            .await
+            .map_error(|_| (Status::InternalServerError, Error::InternalError)));
+
+        match super::get_session_from_request(request, backend.as_ref(), secret).await {
+            Ok((session_id, user)) => Outcome::Success(Self { user, session_id }),
+            Err(e) => guard_error(request, e),
+        }
+    }
+}
+
+/// Authenticates requests either from their `Authorization: Bearer` token or from
+/// their `x-api-key` header.
+#[derive(Serialize, Deserialize, Deref, OpenApiFromRequest)]
+pub struct AuthGuard(pub GuardedData<UserInfo>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthGuard {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if super::bearer_token(request).is_some() {
+            let SessionGuard { user, .. } = try_outcome!(request.guard::<SessionGuard>().await);
+            Outcome::Success(Self(user))
+        } else {
+            let ApiKeyGuard(user) = try_outcome!(request.guard::<ApiKeyGuard>().await);
+            Outcome::Success(Self(user))
+        }
+    }
+}
+
+/// Only lets through users with the `admin` role.
+#[derive(Serialize, Deserialize, Deref, OpenApiFromRequest)]
+pub struct AdminGuard(pub GuardedData<UserInfo>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminGuard {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let AuthGuard(user) = try_outcome!(request.guard::<AuthGuard>().await);
+
+        if user.is_admin() {
+            Outcome::Success(Self(user))
+        } else {
+            guard_error(request, Error::ForbiddenAccess)
+        }
+    }
+}
+
+/// Lets through every user allowed to modify data, i.e. everyone but `read_only` users.
+#[derive(Serialize, Deserialize, Deref, OpenApiFromRequest)]
+pub struct WriteGuard(pub GuardedData<UserInfo>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteGuard {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let AuthGuard(user) = try_outcome!(request.guard::<AuthGuard>().await);
+
+        if user.role == Role::ReadOnly {
+            guard_error(request, Error::ForbiddenAccess)
+        } else {
+            Outcome::Success(Self(user))
         }
     }
 }
@@ -1,10 +1,12 @@
+use super::RequestId;
 use rocket::{
-    outcome::Outcome,
+    outcome::{try_outcome, Outcome},
     request::{self, FromRequest, Request},
 };
+use rocket_okapi::OpenApiFromRequest;
 
 /// Gets the Connecting Client's IP address & Useragent Information
-#[derive(Debug)]
+#[derive(Debug, OpenApiFromRequest)]
 pub struct ClientInfo {
     pub ip: String,
     pub user_agent: String,
@@ -21,7 +23,11 @@ impl<'r> FromRequest<'r> for ClientInfo {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        let ua = request.headers().get("user-agent").next().unwrap();
+        let ua = request
+            .headers()
+            .get("user-agent")
+            .next()
+            .unwrap_or_default();
         let browser_info = if ua.is_empty() {
             "".to_string()
         } else {
@@ -48,3 +54,22 @@ impl<'r> FromRequest<'r> for ClientInfo {
         }
     }
 }
+
+/// Client information along with the ID of the request, as recorded in the audit log.
+#[derive(Debug, OpenApiFromRequest)]
+pub struct RequestInfo {
+    pub client: ClientInfo,
+    pub request_id: RequestId,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let client = try_outcome!(request.guard::<ClientInfo>().await);
+        let request_id = RequestId::of(request).clone();
+
+        Outcome::Success(Self { client, request_id })
+    }
+}
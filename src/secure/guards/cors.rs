@@ -19,11 +19,19 @@ impl Fairing for Cors {
         response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
-            "GET, POST, PUT, DELETE, OPTIONS",
+            "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS",
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
-            "Content-Type, Authorization, X-API-KEY",
+            "Content-Type, Authorization, X-API-KEY, X-Request-Id, Tus-Resumable, \
+             Upload-Length, Upload-Metadata, Upload-Offset, Range, If-Range, If-None-Match, \
+             If-Modified-Since",
+        ));
+        response.set_header(Header::new(
+            "Access-Control-Expose-Headers",
+            "X-Request-Id, Location, Tus-Resumable, Tus-Version, Tus-Extension, Tus-Max-Size, \
+             Upload-Length, Upload-Offset, Upload-Expires, X-File-Id, ETag, Accept-Ranges, \
+             Content-Range",
         ));
     }
 }
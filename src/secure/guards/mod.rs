@@ -1,12 +1,21 @@
 pub mod auth;
 pub mod client;
 pub(crate) mod cors;
+pub mod request_id;
 
-pub use auth::ApiKeyGuard;
+pub use auth::{AdminGuard, ApiKeyGuard, AuthGuard, SessionGuard, WriteGuard};
+pub use client::{ClientInfo, RequestInfo};
+pub use request_id::RequestId;
 
-use crate::{db::SqliteBackend, error::Error, models::UserInfo};
+use crate::{
+    db::Backend,
+    error::{Error, Problem},
+    models::UserInfo,
+    secure::token::{validate_api_key, verify_access_token, API_KEY_PREFIX},
+};
+use chrono::Utc;
 use derive_more::Deref;
-use rocket::request::Request;
+use rocket::request::{Outcome, Request};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
 
@@ -17,9 +26,14 @@ pub struct GuardedData<T> {
     pub ip: IpAddr,
 }
 
+/// Authenticates a request from its `x-api-key` header.
+///
+/// Keys with a bad checksum are rejected without hitting the database. Revoked and
+/// expired keys are rejected, and the last use of the key is recorded.
 pub(crate) async fn get_user_from_request(
     request: &Request<'_>,
-    backend: &SqliteBackend,
+    backend: &dyn Backend,
+    salt: &str,
 ) -> Result<GuardedData<UserInfo>, Error> {
     let api_key = request
         .headers()
@@ -27,11 +41,93 @@ pub(crate) async fn get_user_from_request(
         .map(|header| header.trim())
         .ok_or(Error::UnauthenticatedUser)?;
 
+    // Keys issued before the `ak_live_` format have no checksum to verify
+    if api_key.starts_with(API_KEY_PREFIX) && !validate_api_key(api_key) {
+        return Err(Error::UnauthenticatedUser);
+    }
+
+    let key = backend
+        .get_api_key(api_key, salt)
+        .await
+        .map_err(|e| match e {
+            Error::NotFound(_) => Error::UnauthenticatedUser,
+            e => e,
+        })?;
+    if key.revoked {
+        return Err(Error::ApiKeyRevoked);
+    }
+    if key.is_expired() {
+        return Err(Error::ApiKeyExpired);
+    }
+    let user = get_active_user(backend, &key.user_id).await?;
+    backend.touch_api_key(key.id).await?;
+
+    with_client_ip(request, user)
+}
+
+/// Returns the token of the `Authorization: Bearer` header, if any.
+pub(crate) fn bearer_token<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.trim().strip_prefix("Bearer "))
+        .map(|token| token.trim())
+}
+
+/// Authenticates a request from the access token of its `Authorization: Bearer` header.
+///
+/// The signature and expiry of the token are checked before the session is looked up,
+/// so that logged out sessions are rejected.
+///
+/// # Returns
+///
+/// The ID of the session along with its user.
+pub(crate) async fn get_session_from_request(
+    request: &Request<'_>,
+    backend: &dyn Backend,
+    secret: &str,
+) -> Result<(String, GuardedData<UserInfo>), Error> {
+    let token = bearer_token(request).ok_or(Error::UnauthenticatedUser)?;
+    let (session_id, expires_at) =
+        verify_access_token(token, secret).ok_or(Error::UnauthenticatedUser)?;
+    if expires_at <= Utc::now().timestamp() {
+        return Err(Error::SessionExpired);
+    }
+
+    let session = backend
+        .get_session(&session_id)
+        .await
+        .map_err(|_| Error::UnauthenticatedUser)?;
+    if session.expires_at <= Utc::now() {
+        return Err(Error::SessionExpired);
+    }
+
+    get_active_user(backend, &session.user_id)
+        .await
+        .and_then(|user| with_client_ip(request, user))
+        .map(|user| (session.id, user))
+}
+
+/// Retrieves the user owning a key or session, which must not have been deleted.
+async fn get_active_user(backend: &dyn Backend, user_id: &str) -> Result<UserInfo, Error> {
     backend
-        .get_user_with_apikey(api_key)
+        .get_user_with_id(user_id)
         .await
-        .and_then(|user| match (true, request.client_ip()) {
-            (true, Some(ip)) => Ok(GuardedData { inner: user, ip }),
-            _ => Err(Error::ForbiddenAccess),
+        .map_err(|e| match e {
+            Error::NotFound(_) => Error::UnauthenticatedUser,
+            e => e,
         })
 }
+
+/// Fails a request guard with `error`, keeping its problem for the catcher to report.
+pub(crate) fn guard_error<T>(request: &Request<'_>, error: Error) -> Outcome<T, Error> {
+    request.local_cache(|| Some(Problem::from_error(&error, request)));
+    Outcome::Error((error.to_status(), error))
+}
+
+fn with_client_ip(request: &Request<'_>, user: UserInfo) -> Result<GuardedData<UserInfo>, Error> {
+    match request.client_ip() {
+        Some(ip) => Ok(GuardedData { inner: user, ip }),
+        None => Err(Error::ForbiddenAccess),
+    }
+}
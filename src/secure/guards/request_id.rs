@@ -0,0 +1,68 @@
+use crate::secure::token::generate_random_string;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Header,
+    request::{FromRequest, Outcome, Request},
+    Response,
+};
+use rocket_okapi::OpenApiFromRequest;
+use std::convert::Infallible;
+
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+const REQUEST_ID_MAX_LEN: usize = 64;
+
+/// ID of the current request: the `X-Request-Id` header sent by the client when it
+/// looks sane, a new random ID otherwise. It is sent back in the `X-Request-Id`
+/// response header by the `RequestIdHeader` fairing.
+#[derive(Clone, Debug, OpenApiFromRequest)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub(crate) fn of<'r>(request: &'r Request<'_>) -> &'r Self {
+        request.local_cache(|| {
+            let id = request
+                .headers()
+                .get_one(REQUEST_ID_HEADER)
+                .filter(|id| {
+                    !id.is_empty()
+                        && id.len() <= REQUEST_ID_MAX_LEN
+                        && id
+                            .bytes()
+                            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+                })
+                .map(str::to_string)
+                .unwrap_or_else(|| generate_random_string(20));
+
+            Self(id)
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self::of(request).clone())
+    }
+}
+
+/// Adds the `X-Request-Id` header to every response.
+pub struct RequestIdHeader;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdHeader {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add X-Request-Id header to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_header(Header::new(
+            REQUEST_ID_HEADER,
+            RequestId::of(request).0.clone(),
+        ));
+    }
+}
@@ -1,50 +1,112 @@
-use crate::Result;
+use crate::{server::config::Argon2Config, Result};
 use argon2::{self, Config, Variant, Version};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
+use rand::{rngs::OsRng, RngCore};
+use std::sync::{Arc, OnceLock};
 
-/// Computes the hashed value of a password using Argon2id algorithm.
-///
-/// # Arguments
-///
-/// * `password` - The password string to be hashed.
-/// * `salt` - The salt string used in the hashing process.
-///
-/// # Returns
+/// Length in bytes of the random salt generated for every password.
+const SALT_LEN: usize = 16;
+
+/// Hashes and verifies passwords using the Argon2id algorithm.
 ///
-/// Returns the hashed value of the password as a String.
-pub fn hash_password(password: &str, salt: &str) -> Result<String> {
-    let config = Config {
-        variant: Variant::Argon2id,
-        version: Version::Version13,
-        mem_cost: 65536,
-        time_cost: 10,
-        lanes: 4,
-        secret: &[],
-        ad: &[],
-        hash_length: 32,
-    };
-
-    argon2::hash_encoded(password.as_bytes(), salt.as_bytes(), &config).map_err(|e| e.into())
+/// Every password gets its own random salt, while the server secret key is used as
+/// the Argon2 `secret` (pepper), so a leaked database alone is not enough to brute
+/// force the passwords.
+#[derive(Clone, Debug)]
+pub struct PasswordHasher {
+    /// Server secret key, mixed into every hash
+    pepper: String,
+    /// Cost parameters of newly computed hashes
+    params: Argon2Config,
+    /// Hash of no password, computed on first use
+    dummy_hash: Arc<OnceLock<String>>,
 }
 
-/// Verify a password against its hash.
-///
-/// This function takes a password and its corresponding hash and verifies
-/// whether the password matches the hash. It makes use of the Argon2 password
-/// hashing algorithm.
-///
-/// # Arguments
-///
-/// * `password` - The password to be verified as a string reference.
-/// * `hash` - The hash of the password to be compared against, as a string reference.
-///
-/// # Returns
-///
-/// A boolean value indicating whether the password matches the provided hash.
-///
-/// # Panics
-///
-/// This function panics if the Argon2 library fails to verify the password
-/// against the hash.
-pub fn verify_password(password: &str, hash: &str) -> Result<bool> {
-    argon2::verify_encoded(hash, password.as_bytes()).map_err(|e| e.into())
+impl PasswordHasher {
+    pub fn new(pepper: String, params: Argon2Config) -> Self {
+        Self {
+            pepper,
+            params,
+            dummy_hash: Arc::default(),
+        }
+    }
+
+    fn config(&self) -> Config<'_> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.params.mem_cost,
+            time_cost: self.params.time_cost,
+            lanes: self.params.lanes,
+            secret: self.pepper.as_bytes(),
+            ad: &[],
+            hash_length: 32,
+        }
+    }
+
+    /// Computes the hashed value of a password with a new random salt.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password string to be hashed.
+    ///
+    /// # Returns
+    ///
+    /// Returns the hashed value of the password, in the PHC string format.
+    pub fn hash(&self, password: &str) -> Result<String> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        argon2::hash_encoded(password.as_bytes(), &salt, &self.config()).map_err(|e| e.into())
+    }
+
+    /// Verify a password against its hash.
+    ///
+    /// Hashes computed before per-user salts, which used the server secret key as
+    /// salt and no pepper, are still verified.
+    ///
+    /// # Arguments
+    ///
+    /// * `password` - The password to be verified as a string reference.
+    /// * `hash` - The hash of the password to be compared against, as a string reference.
+    ///
+    /// # Returns
+    ///
+    /// A boolean value indicating whether the password matches the provided hash.
+    pub fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        if self.is_legacy(hash) {
+            argon2::verify_encoded(hash, password.as_bytes()).map_err(|e| e.into())
+        } else {
+            argon2::verify_encoded_ext(hash, password.as_bytes(), self.pepper.as_bytes(), &[])
+                .map_err(|e| e.into())
+        }
+    }
+
+    /// Verifies a password against a hash of no password, which takes as long as
+    /// verifying it against the hash of a user and always fails.
+    pub fn verify_dummy(&self, password: &str) {
+        let hash = self
+            .dummy_hash
+            .get_or_init(|| self.hash("").unwrap_or_default());
+        let _ = self.verify(password, hash);
+    }
+
+    /// Tells whether a hash should be recomputed, because it is a legacy hash or was
+    /// computed with other parameters than the configured ones.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let params = format!(
+            "m={},t={},p={}",
+            self.params.mem_cost, self.params.time_cost, self.params.lanes
+        );
+
+        match hash.split('$').collect::<Vec<_>>()[..] {
+            ["", "argon2id", "v=19", p, _, _] => p != params || self.is_legacy(hash),
+            _ => true,
+        }
+    }
+
+    /// Legacy hashes all share the server secret key as salt.
+    fn is_legacy(&self, hash: &str) -> bool {
+        hash.split('$').nth(4) == Some(STANDARD_NO_PAD.encode(&self.pepper).as_str())
+    }
 }
@@ -1,3 +1,3 @@
-pub(crate) mod guards;
-pub(crate) mod hash_pass;
+pub mod guards;
+pub mod hash_pass;
 pub(crate) mod token;
@@ -1,8 +1,160 @@
-use uuid::Uuid;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
+use sha2::Sha256;
 
-/// Generate the API Key
-pub fn generate_api_string(salt: &str) -> String {
-    Uuid::new_v5(&Uuid::NAMESPACE_OID, salt.as_bytes())
-        .as_simple()
-        .to_string()
+/// Prefix of every generated API key, so that keys are easy to recognize.
+pub const API_KEY_PREFIX: &str = "ak_live_";
+
+/// Number of leading characters of an API key stored in clear, used to look
+/// keys up and to let users tell them apart.
+pub const API_KEY_PREFIX_LEN: usize = 12;
+
+/// Bounds of the configurable number of random characters of an API key.
+pub const API_KEY_MIN_LENGTH: usize = 24;
+pub const API_KEY_MAX_LENGTH: usize = 128;
+
+/// Number of characters of the checksum ending an API key.
+const API_KEY_CHECKSUM_LEN: usize = 6;
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Generates a new API key.
+///
+/// Keys are made of the `ak_live_` prefix, `length` random alphanumeric characters
+/// drawn from the OS random number generator, and a 6 characters checksum.
+///
+/// # Arguments
+///
+/// * `length` - The number of random characters of the key.
+///
+/// # Returns
+///
+/// The API key, e.g. `ak_live_<random><checksum>`.
+pub fn generate_api_key(length: usize) -> String {
+    let mut api_key = String::with_capacity(API_KEY_PREFIX.len() + length + API_KEY_CHECKSUM_LEN);
+    api_key.push_str(API_KEY_PREFIX);
+    api_key.push_str(&generate_random_string(length));
+    let checksum = api_key_checksum(&api_key);
+    api_key.push_str(&checksum);
+
+    api_key
+}
+
+/// Generates `length` random alphanumeric characters from the OS random number generator.
+pub fn generate_random_string(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Checks offline that a string is a well-formed API key, i.e. that it has the
+/// `ak_live_` prefix and a valid checksum. This does not tell whether the key exists.
+pub fn validate_api_key(api_key: &str) -> bool {
+    let Some(random) = api_key.strip_prefix(API_KEY_PREFIX) else {
+        return false;
+    };
+    if random.len() <= API_KEY_CHECKSUM_LEN || !random.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return false;
+    }
+
+    let (body, checksum) = api_key.split_at(api_key.len() - API_KEY_CHECKSUM_LEN);
+    api_key_checksum(body) == checksum
+}
+
+/// CRC32 of the key, base62 encoded on `API_KEY_CHECKSUM_LEN` characters.
+fn api_key_checksum(body: &str) -> String {
+    let mut crc = crc32fast::hash(body.as_bytes());
+    let mut checksum = [b'0'; API_KEY_CHECKSUM_LEN];
+    for c in checksum.iter_mut().rev() {
+        *c = BASE62[(crc % 62) as usize];
+        crc /= 62;
+    }
+
+    checksum.iter().map(|&c| c as char).collect()
+}
+
+/// Returns the non secret prefix of an API key.
+pub fn api_key_prefix(api_key: &str) -> String {
+    api_key.chars().take(API_KEY_PREFIX_LEN).collect()
+}
+
+/// Computes the keyed hash (HMAC-SHA256, hex encoded) under which an API key is stored.
+///
+/// The server secret key is the HMAC key, so changing it invalidates every stored API key.
+pub fn hash_api_key(api_key: &str, secret: &str) -> String {
+    let mut mac = new_mac(secret);
+    mac.update(api_key.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Signs a session access token: `<session_id>.<expiry timestamp>.<signature>`.
+///
+/// # Arguments
+///
+/// * `session_id` - The ID of the session the token belongs to.
+/// * `expires_at` - Unix timestamp after which the token is rejected.
+/// * `secret` - The server secret key.
+pub fn sign_access_token(session_id: &str, expires_at: i64, secret: &str) -> String {
+    let payload = format!("{}.{}", session_id, expires_at);
+    let mut mac = new_mac(secret);
+    mac.update(b"session.");
+    mac.update(payload.as_bytes());
+
+    format!("{}.{}", payload, hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Checks the signature of an access token, without checking its expiry.
+///
+/// # Returns
+///
+/// The session ID and expiry timestamp of the token, or `None` if the token is
+/// malformed or its signature does not match.
+pub fn verify_access_token(token: &str, secret: &str) -> Option<(String, i64)> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let (session_id, expires_at) = payload.split_once('.')?;
+    let expires_at = expires_at.parse().ok()?;
+
+    let mut mac = new_mac(secret);
+    mac.update(b"session.");
+    mac.update(payload.as_bytes());
+    mac.verify_slice(&hex::decode(signature).ok()?).ok()?;
+
+    Some((session_id.to_string(), expires_at))
+}
+
+/// Signs a download link to a file, given the query parameters of the link besides
+/// its signature, e.g. `expires=<timestamp>&once=<nonce>`.
+///
+/// # Returns
+///
+/// The hex encoded signature, to add to the query of the link.
+pub fn sign_download_link(file_id: i64, query: &str, secret: &str) -> String {
+    hex::encode(
+        download_link_mac(file_id, query, secret)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+/// Checks the signature of a download link, as returned by `sign_download_link`.
+pub fn verify_download_link(file_id: i64, query: &str, signature: &str, secret: &str) -> bool {
+    hex::decode(signature).is_ok_and(|signature| {
+        download_link_mac(file_id, query, secret)
+            .verify_slice(&signature)
+            .is_ok()
+    })
+}
+
+fn download_link_mac(file_id: i64, query: &str, secret: &str) -> Hmac<Sha256> {
+    let mut mac = new_mac(secret);
+    mac.update(b"download.");
+    mac.update(format!("{}?{}", file_id, query).as_bytes());
+    mac
+}
+
+fn new_mac(secret: &str) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size")
 }
@@ -1,60 +1,23 @@
-use rocket::{http::Status, request::Request, serde::json::Value};
+use crate::error::{Error, Problem};
+use rocket::{http::Status, request::Request};
 
-#[catch(400)]
-pub async fn bad_request(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        400,
-        "request not understood",
-        "request_uri" => req.uri().to_string()
-    )
-}
-
-#[catch(401)]
-pub async fn not_authorized(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        401,
-        "not authorized",
-        "request_uri" => req.uri().to_string()
-    )
-}
-
-#[catch(403)]
-pub async fn forbidden(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        403,
-        "forbidden",
-        "request_uri" => req.uri().to_string()
-    )
-}
-
-#[catch(404)]
-pub async fn not_found(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        404,
-        "not found",
-        "request_uri" => req.uri().to_string()
-    )
-}
-
-#[catch(422)]
-pub async fn unprocessed_entity(_req: &Request<'_>) -> (Status, Value) {
-    json_response!(422, "Check your input data".to_string())
-}
-
-#[catch(429)]
-pub async fn too_many_requests(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        429,
-        "too many requests",
-        "request_uri" => req.uri().to_string()
-    )
-}
+/// Describes every error that did not come from an endpoint as a problem: the
+/// problem of the failed request guard if there is one, or a problem telling what
+/// went wrong from the status.
+#[catch(default)]
+pub async fn default_catcher(status: Status, req: &Request<'_>) -> Problem {
+    if let Some(problem) = req.local_cache(|| None::<Problem>) {
+        if problem.status == status.code {
+            return problem.to_owned();
+        }
+    }
 
-#[catch(500)]
-pub async fn internal_server_error(req: &Request<'_>) -> (Status, Value) {
-    json_response!(
-        500,
-        "internal server error",
-        "request_uri" => req.uri().to_string()
-    )
+    match status.code {
+        404 => Problem::from_error(&Error::UnknownRoute, req),
+        422 => Problem {
+            detail: Some("Check your input data".to_string()),
+            ..Problem::new(status, req)
+        },
+        _ => Problem::new(status, req),
+    }
 }
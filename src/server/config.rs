@@ -10,6 +10,20 @@ const SRV_JSON_LIMIT: usize = 1024 * 256;
 const SRV_SECRET_KEY: &str = "t/xZkYvxfC8CSfTSH9ANiIR9t1SvLHqOYZ7vH4fp11s=";
 const SRV_LOG_LEVEL: &str = "info";
 const SVR_STORAGE_PATH: &str = "/tmp/test";
+const SRV_API_KEY_LENGTH: usize = 32;
+const SRV_ACCESS_TOKEN_TTL: u32 = 15 * 60;
+const SRV_REFRESH_TOKEN_TTL: u32 = 7 * 24 * 60 * 60;
+const SRV_UPLOAD_TTL: u32 = 24 * 60 * 60;
+const SRV_MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+const SRV_MAX_DOWNLOAD_LINK_TTL: u32 = 7 * 24 * 60 * 60;
+/// Types browsers run scripts from, which would run on the origin of the API
+const DENIED_CONTENT_TYPES: [&str; 3] = ["text/html", "image/svg+xml", "application/xhtml+xml"];
+const ARGON2_MEM_COST: u32 = 65536;
+const ARGON2_TIME_COST: u32 = 10;
+const ARGON2_LANES: u32 = 4;
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 128;
+const S3_REGION: &str = "us-east-1";
 
 /// Rocket API Server parameters
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -78,8 +92,43 @@ pub struct ServerConfig {
     pub allow_cors: bool,
     #[serde(default = "default_server_log_level")]
     pub log_level: String,
+    /// Directory of the uploaded files, with the `local` storage backend
     #[serde(default = "default_server_storage_path")]
     pub storage_path: String,
+    /// Where uploaded files are stored
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Number of random characters of generated API keys
+    #[serde(default = "default_server_api_key_length")]
+    pub api_key_length: usize,
+    /// Cost parameters used to hash passwords
+    #[serde(default)]
+    pub argon2: Argon2Config,
+    /// Rules new passwords must follow
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    /// Lifetime in seconds of the access tokens issued on login
+    #[serde(default = "default_server_access_token_ttl")]
+    pub access_token_ttl: u32,
+    /// Lifetime in seconds of a login session, extended on every refresh
+    #[serde(default = "default_server_refresh_token_ttl")]
+    pub refresh_token_ttl: u32,
+    /// Lifetime in seconds of a resumable upload, counted from its creation
+    #[serde(default = "default_server_upload_ttl")]
+    pub upload_ttl: u32,
+    /// Maximum size in bytes of a file, whether sent at once with `POST /upload` or
+    /// as a resumable upload
+    #[serde(default = "default_server_max_upload_size")]
+    pub max_upload_size: u64,
+    /// Limits on the files users can store
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    /// Types of the files users can upload
+    #[serde(default)]
+    pub content_types: ContentTypeConfig,
+    /// Maximum lifetime in seconds of a signed download link
+    #[serde(default = "default_server_max_download_link_ttl")]
+    pub max_download_link_ttl: u32,
 }
 
 impl Default for ServerConfig {
@@ -94,21 +143,278 @@ impl Default for ServerConfig {
             allow_cors: false,
             log_level: SRV_LOG_LEVEL.into(),
             storage_path: SVR_STORAGE_PATH.into(),
+            storage: StorageConfig::default(),
+            api_key_length: SRV_API_KEY_LENGTH,
+            argon2: Argon2Config::default(),
+            password_policy: PasswordPolicy::default(),
+            access_token_ttl: SRV_ACCESS_TOKEN_TTL,
+            refresh_token_ttl: SRV_REFRESH_TOKEN_TTL,
+            upload_ttl: SRV_UPLOAD_TTL,
+            max_upload_size: SRV_MAX_UPLOAD_SIZE,
+            max_download_link_ttl: SRV_MAX_DOWNLOAD_LINK_TTL,
+            quota: QuotaConfig::default(),
+            content_types: ContentTypeConfig::default(),
+        }
+    }
+}
+
+/// Storage quotas, unlimited by default. Admins can give users their own quota,
+/// which replaces the user defaults.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuotaConfig {
+    /// Bytes each user can store
+    #[serde(default)]
+    pub user_bytes: Option<u64>,
+    /// Number of files each user can store
+    #[serde(default)]
+    pub user_files: Option<u64>,
+    /// Bytes all users can store together
+    #[serde(default)]
+    pub total_bytes: Option<u64>,
+    /// Number of files all users can store together
+    #[serde(default)]
+    pub total_files: Option<u64>,
+}
+
+/// Media types of the files users can upload, as detected from their content, e.g.
+/// `image/png`, or `image/*` for a whole top-level type. Any type is allowed by
+/// default, except documents browsers run scripts from.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ContentTypeConfig {
+    /// The only types allowed, when not empty
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Types rejected, even when allowed
+    #[serde(default = "default_denied_content_types")]
+    pub denied: Vec<String>,
+}
+
+impl Default for ContentTypeConfig {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            denied: default_denied_content_types(),
+        }
+    }
+}
+
+impl ContentTypeConfig {
+    /// Checks every pattern is a media type, or a top-level type followed by `/*`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidContentTypeConfig` naming the first invalid pattern.
+    pub fn validate(&self) -> Result<()> {
+        for pattern in self.allowed.iter().chain(&self.denied) {
+            let valid = match pattern.split_once('/') {
+                Some((top, sub)) => {
+                    !top.is_empty() && !sub.is_empty() && !sub.contains('/') && top != "*"
+                }
+                None => false,
+            };
+            if !valid {
+                return Err(Error::InvalidContentTypeConfig(format!(
+                    "{} is not a media type",
+                    pattern
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether files of `content_type` can be uploaded.
+    pub fn allows(&self, content_type: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(top) => content_type
+                .split_once('/')
+                .is_some_and(|(content_top, _)| content_top.eq_ignore_ascii_case(top)),
+            None => pattern.eq_ignore_ascii_case(content_type),
+        };
+
+        !self.denied.iter().any(matches)
+            && (self.allowed.is_empty() || self.allowed.iter().any(matches))
+    }
+}
+
+/// Argon2id cost parameters. Changing them only affects new hashes, existing
+/// passwords are rehashed on the next successful login.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Argon2Config {
+    /// Memory cost in KiB, at least 8 times `lanes`
+    #[serde(default = "default_argon2_mem_cost")]
+    pub mem_cost: u32,
+    /// Number of passes
+    #[serde(default = "default_argon2_time_cost")]
+    pub time_cost: u32,
+    /// Degree of parallelism
+    #[serde(default = "default_argon2_lanes")]
+    pub lanes: u32,
+}
+
+impl Argon2Config {
+    /// Checks the parameters are accepted by Argon2.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgon2Config` describing the first invalid parameter.
+    pub fn validate(&self) -> Result<()> {
+        if self.lanes == 0 || self.lanes > 0xFF_FFFF {
+            return Err(Error::InvalidArgon2Config(
+                "lanes must be between 1 and 16777215".to_string(),
+            ));
+        }
+        if self.time_cost == 0 {
+            return Err(Error::InvalidArgon2Config(
+                "time_cost must be at least 1".to_string(),
+            ));
+        }
+        if self.mem_cost < 8 * self.lanes {
+            return Err(Error::InvalidArgon2Config(
+                "mem_cost must be at least 8 times lanes".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Self {
+            mem_cost: ARGON2_MEM_COST,
+            time_cost: ARGON2_TIME_COST,
+            lanes: ARGON2_LANES,
         }
     }
 }
 
+/// Rules new passwords must follow. Existing passwords keep working until changed.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters
+    #[serde(default = "default_password_min_length")]
+    pub min_length: usize,
+    /// Maximum number of characters, as hashing is slow on purpose
+    #[serde(default = "default_password_max_length")]
+    pub max_length: usize,
+    /// Require at least one lowercase letter
+    #[serde(default)]
+    pub require_lowercase: bool,
+    /// Require at least one uppercase letter
+    #[serde(default)]
+    pub require_uppercase: bool,
+    /// Require at least one digit
+    #[serde(default)]
+    pub require_digit: bool,
+    /// Require at least one character that is neither a letter nor a digit
+    #[serde(default)]
+    pub require_symbol: bool,
+}
+
+impl PasswordPolicy {
+    /// Checks the policy can be satisfied.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidPasswordPolicy` describing the problem.
+    pub fn validate(&self) -> Result<()> {
+        if self.min_length == 0 {
+            return Err(Error::InvalidPasswordPolicy(
+                "min_length must be at least 1".to_string(),
+            ));
+        }
+        if self.min_length > self.max_length {
+            return Err(Error::InvalidPasswordPolicy(
+                "min_length must not be greater than max_length".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: PASSWORD_MIN_LENGTH,
+            max_length: PASSWORD_MAX_LENGTH,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+/// Storage backend of the uploaded files, picked by its `backend` key.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// Files in `server.storage_path` on the local filesystem
+    #[default]
+    Local,
+    /// Objects in a bucket of an S3-compatible object store, such as MinIO.
+    /// Requires the `s3` feature.
+    S3(S3Config),
+}
+
+/// Location and credentials of an S3-compatible bucket.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// URL of the object store, e.g. `http://localhost:9000` for a local MinIO.
+    /// Defaults to AWS
+    pub endpoint: Option<String>,
+    /// Address buckets in the path rather than in the host name, as MinIO expects
+    #[serde(default)]
+    pub path_style: bool,
+    /// Static credentials. When unset, they are looked up like the AWS CLI does,
+    /// from the environment, profile files or instance metadata
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+impl StorageConfig {
+    /// Checks the storage backend is fully configured.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidStorageConfig` describing the problem.
+    pub fn validate(&self) -> Result<()> {
+        let Self::S3(s3) = self else {
+            return Ok(());
+        };
+        if s3.bucket.is_empty() {
+            return Err(Error::InvalidStorageConfig(
+                "bucket must not be empty".to_string(),
+            ));
+        }
+        if s3.access_key_id.is_some() != s3.secret_access_key.is_some() {
+            return Err(Error::InvalidStorageConfig(
+                "access_key_id and secret_access_key must be set together".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 /// Application related parameters
 #[derive(Deserialize, Clone, Debug)]
 pub struct App {
-    #[serde(default = "default_db_path")]
-    pub db_path: String,
+    /// Database to connect to. The scheme selects the backend
+    /// (`sqlite://`, `postgres://`); a plain path is treated as a SQLite file.
+    #[serde(default = "default_db_url", alias = "db_path")]
+    pub db_url: String,
 }
 
 impl Default for App {
     fn default() -> Self {
         Self {
-            db_path: default_db_path(),
+            db_url: default_db_url(),
         }
     }
 }
@@ -145,7 +451,62 @@ fn default_server_log_level() -> String {
 fn default_server_storage_path() -> String {
     SVR_STORAGE_PATH.into()
 }
+
+fn default_server_api_key_length() -> usize {
+    SRV_API_KEY_LENGTH
+}
+
+fn default_server_access_token_ttl() -> u32 {
+    SRV_ACCESS_TOKEN_TTL
+}
+
+fn default_server_refresh_token_ttl() -> u32 {
+    SRV_REFRESH_TOKEN_TTL
+}
+
+fn default_server_upload_ttl() -> u32 {
+    SRV_UPLOAD_TTL
+}
+
+fn default_server_max_upload_size() -> u64 {
+    SRV_MAX_UPLOAD_SIZE
+}
+
+fn default_server_max_download_link_ttl() -> u32 {
+    SRV_MAX_DOWNLOAD_LINK_TTL
+}
+
+fn default_denied_content_types() -> Vec<String> {
+    DENIED_CONTENT_TYPES
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn default_argon2_mem_cost() -> u32 {
+    ARGON2_MEM_COST
+}
+
+fn default_argon2_time_cost() -> u32 {
+    ARGON2_TIME_COST
+}
+
+fn default_argon2_lanes() -> u32 {
+    ARGON2_LANES
+}
+
+fn default_password_min_length() -> usize {
+    PASSWORD_MIN_LENGTH
+}
+
+fn default_password_max_length() -> usize {
+    PASSWORD_MAX_LENGTH
+}
+fn default_s3_region() -> String {
+    S3_REGION.into()
+}
+
 // All Application defaults
-fn default_db_path() -> String {
+fn default_db_url() -> String {
     "db.sqlite".into()
 }
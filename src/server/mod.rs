@@ -1,5 +1,17 @@
-use crate::{controllers, db::SqliteBackend, error::Error, secure::guards::cors::Cors, Result};
-use clap::Parser;
+use crate::{
+    controllers,
+    db::{self, Backend, DynBackend},
+    error::Error,
+    models::{AuditContext, NewUser, Role, UpdateUser},
+    secure::{
+        guards::{cors::Cors, request_id::RequestIdHeader},
+        hash_pass::PasswordHasher,
+        token::{generate_random_string, API_KEY_MAX_LENGTH, API_KEY_MIN_LENGTH},
+    },
+    storage::{self, DynStorage, PartialUploads},
+    Result,
+};
+use clap::{Parser, Subcommand};
 use log::{info, LevelFilter};
 use rocket::{data::Limits, Build, Config, Rocket};
 use rocket_okapi::{
@@ -11,14 +23,19 @@ use rocket_okapi::{
     settings::UrlObject,
 };
 use simple_logger::SimpleLogger;
+use std::io::{self, IsTerminal};
 use std::path::Path;
+use validator::ValidateArgs;
+
+/// Environment variable the password of the `bootstrap` command is read from
+const BOOTSTRAP_PASSWORD_VAR: &str = "BOOTSTRAP_PASSWORD";
 
 /// Server & App Configurations
 //pub mod config;
-use self::config::Settings;
+use self::config::{ServerConfig, Settings};
 /// Catchers like 500, 501, 404, etc
 mod catchers;
-pub(crate) mod config;
+pub mod config;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -26,12 +43,36 @@ struct CliOpts {
     /// loads the server configurations
     #[clap(short = 'c', long)]
     config: String,
+
+    /// runs a maintenance command instead of starting the server
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Shows applied and pending database migrations
+    Migrations,
+    /// Creates the first admin user on an empty database and prints its API key.
+    /// The password is read from BOOTSTRAP_PASSWORD, or else from the terminal or stdin
+    Bootstrap {
+        /// user id of the admin user
+        #[clap(long)]
+        user_id: String,
+        /// email id of the admin user
+        #[clap(long)]
+        email_id: String,
+    },
+    /// Gives the admin role to an existing user
+    PromoteAdmin {
+        /// user id of the user to promote
+        #[clap(long)]
+        user_id: String,
+    },
 }
 
 /// Parse the settings from the command line arguments
-fn parse_settings_from_cli() -> Result<Settings> {
-    // parse the cli options
-    let cli_opts = CliOpts::parse();
+fn parse_settings_from_cli(cli_opts: &CliOpts) -> Result<Settings> {
     let cfg_file = &cli_opts.config;
 
     if cfg_file.is_empty() {
@@ -53,8 +94,12 @@ fn parse_settings_from_cli() -> Result<Settings> {
 }
 
 /// Initialise the Rocket Server app
-pub async fn init_server() -> Result<Rocket<Build>> {
-    let settings = parse_settings_from_cli()?;
+///
+/// Returns `None` when a maintenance subcommand was run instead of the server.
+pub async fn init_server() -> Result<Option<Rocket<Build>>> {
+    // parse the cli options
+    let cli_opts = CliOpts::parse();
+    let settings = parse_settings_from_cli(&cli_opts)?;
     SimpleLogger::new()
         .with_level(to_level_filter(&settings.clone().server.log_level))
         .with_colors(true)
@@ -67,7 +112,7 @@ pub async fn init_server() -> Result<Rocket<Build>> {
     );
     info!("Server CORS enabled: {:?}", settings.server.allow_cors);
     let db_url = if let Some(a) = settings.clone().app {
-        a.db_path
+        a.db_url
     } else {
         return Err(Error::AppConfigurationError);
     };
@@ -81,6 +126,65 @@ pub async fn init_server() -> Result<Rocket<Build>> {
     // secret key is lost/changed, the password cannot be decrypted.
     let salt = server_settings.secret_key.to_owned();
 
+    if !(API_KEY_MIN_LENGTH..=API_KEY_MAX_LENGTH).contains(&server_settings.api_key_length) {
+        return Err(Error::InvalidApiKeyLength(server_settings.api_key_length));
+    }
+    server_settings.argon2.validate()?;
+    server_settings.password_policy.validate()?;
+    server_settings.storage.validate()?;
+    server_settings.content_types.validate()?;
+    let hasher = PasswordHasher::new(salt.to_owned(), server_settings.argon2.to_owned());
+
+    let db_backend = db::connect(&db_url).await?;
+
+    if let Some(Command::Migrations) = cli_opts.command {
+        for m in db_backend.migration_status().await? {
+            match m.applied_at {
+                Some(at) => println!("{:04}_{}\tapplied at {}", m.version, m.name, at),
+                None => println!("{:04}_{}\tpending", m.version, m.name),
+            }
+        }
+        return Ok(None);
+    }
+
+    // Bring the schema up to date before serving any request
+    db_backend.migrate().await?;
+    let hashed = db_backend.hash_legacy_api_keys(&salt).await?;
+    if hashed > 0 {
+        info!("Hashed {} API keys stored in plaintext", hashed);
+    }
+
+    if let Some(Command::Bootstrap { user_id, email_id }) = cli_opts.command {
+        let user = NewUser {
+            user_id,
+            password: read_bootstrap_password()?,
+            email_id,
+            role: Role::Admin,
+        };
+        bootstrap_admin(db_backend.as_ref(), user, &hasher, &salt, &server_settings).await?;
+        return Ok(None);
+    }
+    if let Some(Command::PromoteAdmin { user_id }) = cli_opts.command {
+        promote_admin(db_backend.as_ref(), &user_id, &hasher).await?;
+        return Ok(None);
+    }
+
+    let storage = storage::connect(&server_settings).await?;
+
+    Ok(Some(build_server(settings, db_backend, storage)))
+}
+
+/// Assembles the Rocket app serving the API out of validated settings, a migrated
+/// database backend and the storage of the uploaded files.
+pub fn build_server(
+    settings: Settings,
+    db_backend: DynBackend,
+    storage: DynStorage,
+) -> Rocket<Build> {
+    let server_settings = settings.server.to_owned();
+    let salt = server_settings.secret_key.to_owned();
+    let hasher = PasswordHasher::new(salt.to_owned(), server_settings.argon2.to_owned());
+
     let limits = Limits::new()
         .limit("forms", server_settings.forms_limit.into())
         .limit("json", server_settings.json_limit.into());
@@ -92,25 +196,11 @@ pub async fn init_server() -> Result<Rocket<Build>> {
         .merge(("secret_key", (server_settings.secret_key.as_str())))
         .merge(("keep_alive", server_settings.keep_alive as u32));
 
-    let db_backend = SqliteBackend::new_connection(&db_url).await?;
-    db_backend.check_and_create_table().await?;
-
     // Configure the Rocket server with configured settings
     let app = rocket::custom(rocket_cfg);
 
     // Catchers
-    let app = app.register(
-        "/",
-        rocket::catchers![
-            catchers::bad_request,
-            catchers::forbidden,
-            catchers::not_authorized,
-            catchers::not_found,
-            catchers::unprocessed_entity,
-            catchers::internal_server_error,
-            catchers::too_many_requests,
-        ],
-    );
+    let app = app.register("/", rocket::catchers![catchers::default_catcher]);
 
     // Add the routes with openapi specs
     let app = app
@@ -120,11 +210,36 @@ pub async fn init_server() -> Result<Rocket<Build>> {
                 controllers::users::create_user_endpoint,
                 controllers::users::update_user_endpoint,
                 controllers::users::delete_user_endpoint,
+                controllers::users::restore_user_endpoint,
+                controllers::users::purge_user_endpoint,
                 controllers::users::list_all_users_endpoint,
                 controllers::users::get_user_by_id_endpoint,
-                controllers::users::get_user_by_api_key_endpoint,
+                controllers::me::get_me_endpoint,
+                controllers::me::update_me_endpoint,
+                controllers::me::delete_me_endpoint,
+                controllers::api_keys::list_api_keys_endpoint,
+                controllers::api_keys::create_api_key_endpoint,
+                controllers::api_keys::revoke_api_key_endpoint,
+                controllers::api_keys::rotate_api_key_endpoint,
+                controllers::audit::list_audit_events_endpoint,
+                controllers::auth::login_endpoint,
+                controllers::auth::refresh_endpoint,
+                controllers::auth::logout_endpoint,
                 controllers::files::upload_file,
-                controllers::files::download_file
+                controllers::files::download_file,
+                controllers::files::head_file,
+                controllers::links::create_download_link,
+                controllers::links::download_signed_file,
+                controllers::links::head_signed_file,
+                controllers::quotas::get_my_usage,
+                controllers::quotas::get_user_usage,
+                controllers::quotas::set_user_quota,
+                controllers::files::list_files_endpoint,
+                controllers::uploads::upload_options,
+                controllers::uploads::create_upload,
+                controllers::uploads::get_upload_offset,
+                controllers::uploads::append_upload,
+                controllers::uploads::terminate_upload
             ],
         )
         .mount(
@@ -177,9 +292,15 @@ pub async fn init_server() -> Result<Rocket<Build>> {
     let app = app
         // Add Db pool to the state
         .manage(db_backend)
+        .manage(storage)
+        .manage(PartialUploads::new(&server_settings.storage_path))
         // Sending the salt key to the state
         .manage(salt)
-        .manage(settings);
+        .manage(hasher)
+        .manage(settings)
+        // Tag every response with the ID of its request
+        .attach(RequestIdHeader)
+        .attach(controllers::uploads::TusHeaders);
     // Attach Cors if disabled
     let app = if server_settings.allow_cors {
         app.attach(Cors)
@@ -188,7 +309,84 @@ pub async fn init_server() -> Result<Rocket<Build>> {
     };
 
     // Return the configured Rocket App
-    Ok(app)
+    app
+}
+
+/// Reads the password of the bootstrapped admin from `BOOTSTRAP_PASSWORD`, or else
+/// from the terminal without echoing it, or from the first line of stdin when it is
+/// not a terminal. It is never taken as an argument, which would leave it in the
+/// process list and the shell history.
+fn read_bootstrap_password() -> Result<String> {
+    if let Ok(password) = std::env::var(BOOTSTRAP_PASSWORD_VAR) {
+        return Ok(password);
+    }
+
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        let password = rpassword::prompt_password("Password of the admin user: ")?;
+        if rpassword::prompt_password("Password again: ")? != password {
+            return Err(Error::PasswordMismatch);
+        }
+        Ok(password)
+    } else {
+        let mut password = String::new();
+        stdin.read_line(&mut password)?;
+        Ok(password.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Creates the first user of a fresh database and prints its API key.
+///
+/// The key is only ever shown here, so it must be copied from the output.
+/// Refuses to run once any user exists.
+async fn bootstrap_admin(
+    backend: &dyn Backend,
+    user: NewUser,
+    hasher: &PasswordHasher,
+    salt: &str,
+    server_settings: &ServerConfig,
+) -> Result<()> {
+    user.validate_with_args(&server_settings.password_policy)?;
+    let admin = backend
+        .create_first_user(user, hasher, salt, server_settings.api_key_length)
+        .await?;
+    info!("Bootstrapped admin user: {}", admin.user_id);
+    println!("user_id: {}", admin.user_id);
+    println!("api_key: {}", admin.api_key);
+
+    Ok(())
+}
+
+/// Gives the admin role to an existing user, recording the change in the audit log
+/// as made from the command line.
+async fn promote_admin(
+    backend: &dyn Backend,
+    user_id: &str,
+    hasher: &PasswordHasher,
+) -> Result<()> {
+    let user = backend.get_user_with_id(user_id).await?;
+    if user.role == Role::Admin {
+        println!("{} is already an admin", user.user_id);
+        return Ok(());
+    }
+
+    let update = UpdateUser {
+        password: None,
+        role: Some(Role::Admin),
+    };
+    let context = AuditContext {
+        actor: "cli".to_string(),
+        ip: String::new(),
+        user_agent: "api-server promote-admin".to_string(),
+        request_id: generate_random_string(20),
+    };
+    backend
+        .update_user(update, user_id, hasher, &context)
+        .await?;
+    info!("Promoted user to admin: {}", user_id);
+    println!("{} is now an admin", user_id);
+
+    Ok(())
 }
 
 /// Convert LevelFilter from string
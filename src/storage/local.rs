@@ -0,0 +1,148 @@
+use super::{ObjectMeta, ObjectReader, Storage};
+use crate::error::{Error, Resource};
+use rocket::tokio::{
+    fs::{self, File},
+    io::{self, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+/// Storage in a directory of the local filesystem, where keys are relative paths.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Maps `key` to its path under the root, refusing keys that are not plain
+    /// relative paths and could point outside of it.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        let relative = Path::new(key);
+        let plain = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+
+        if key.is_empty() || !plain {
+            return Err(Error::StorageError(format!("Invalid key: {}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for LocalStorage {
+    async fn put(
+        &self,
+        key: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<u64, Error> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+
+        let mut file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        let written = async {
+            let size = io::copy(body, &mut file).await?;
+            file.flush().await?;
+            Ok::<_, io::Error>(size)
+        }
+        .await;
+
+        if written.is_err() {
+            drop(file);
+            fs::remove_file(&path).await?;
+        }
+        Ok(written?)
+    }
+
+    async fn get(&self, key: &str) -> Result<(ObjectMeta, ObjectReader), Error> {
+        let file = File::open(self.path(key)?).await.map_err(not_found)?;
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            size: file.metadata().await?.len(),
+        };
+
+        Ok((meta, Box::pin(file)))
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<ObjectReader, Error> {
+        let mut file = File::open(self.path(key)?).await.map_err(not_found)?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+
+        Ok(Box::pin(file.take(range.end - range.start)))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, Error> {
+        let metadata = fs::metadata(self.path(key)?).await.map_err(not_found)?;
+        if !metadata.is_file() {
+            return Err(Error::NotFound(Resource::File));
+        }
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: metadata.len(),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, Error> {
+        let mut objects = Vec::new();
+        let mut dirs = vec![(self.root.to_owned(), String::new())];
+
+        while let Some((dir, dir_key)) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+                    continue;
+                };
+                let key = format!("{}{}", dir_key, name);
+                let file_type = entry.file_type().await?;
+
+                if file_type.is_dir() {
+                    // Only walk the directories that can hold keys with the prefix
+                    let dir_key = format!("{}/", key);
+                    if dir_key.starts_with(prefix) || prefix.starts_with(&dir_key) {
+                        dirs.push((entry.path(), dir_key));
+                    }
+                } else if file_type.is_file() && key.starts_with(prefix) {
+                    let size = entry.metadata().await?.len();
+                    objects.push(ObjectMeta { key, size });
+                }
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(objects)
+    }
+}
+
+/// Maps a missing file to `Error::NotFound`.
+fn not_found(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        Error::NotFound(Resource::File)
+    } else {
+        e.into()
+    }
+}
@@ -0,0 +1,167 @@
+mod local;
+mod partial;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub use self::local::LocalStorage;
+pub use self::partial::{PartialLock, PartialUploads};
+#[cfg(feature = "s3")]
+pub use self::s3::S3Storage;
+
+use crate::{
+    error::Error,
+    server::config::{ServerConfig, StorageConfig},
+};
+use rocket::tokio::io::{AsyncRead, ReadBuf};
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    ops::Range,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// Storage shared with the Rocket state, selected at startup from `server.storage`
+pub type DynStorage = Box<dyn Storage>;
+
+/// The content of a stored object, read as it is downloaded.
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Description of a stored object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectMeta {
+    pub key: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// Object store keeping the content of uploaded files.
+///
+/// Keys are relative, `/` separated paths made of plain names, such as
+/// `2024/05/<id>`. They are generated by the server and never reused.
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores everything read from `body` under `key`, without buffering it whole.
+    ///
+    /// Nothing is left under `key` when reading `body` or writing it fails.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes stored.
+    async fn put(&self, key: &str, body: &mut (dyn AsyncRead + Send + Unpin))
+        -> Result<u64, Error>;
+
+    /// Opens the object stored under `key`.
+    ///
+    /// # Returns
+    ///
+    /// The object along with its content, or `Err(Error::NotFound)` if there is none.
+    async fn get(&self, key: &str) -> Result<(ObjectMeta, ObjectReader), Error>;
+
+    /// Opens the bytes of `range` of the object stored under `key`, which must be
+    /// within the object.
+    ///
+    /// # Returns
+    ///
+    /// The content of the range, or `Err(Error::NotFound)` if there is no such object.
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<ObjectReader, Error>;
+
+    /// Describes the object stored under `key`.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if there is no such object.
+    async fn head(&self, key: &str) -> Result<ObjectMeta, Error>;
+
+    /// Deletes the object stored under `key`. Deleting a missing object succeeds.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+
+    /// Lists the objects whose key starts with `prefix`, sorted by key.
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, Error>;
+}
+
+/// Opens the storage backend configured in `server.storage`.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidStorageConfig` when the backend is not fully configured or
+/// was not compiled in.
+pub async fn connect(config: &ServerConfig) -> Result<DynStorage, Error> {
+    config.storage.validate()?;
+
+    match &config.storage {
+        StorageConfig::Local => Ok(Box::new(LocalStorage::new(&config.storage_path))),
+        #[cfg(feature = "s3")]
+        StorageConfig::S3(s3) => Ok(Box::new(S3Storage::new(s3).await)),
+        #[cfg(not(feature = "s3"))]
+        StorageConfig::S3(_) => Err(Error::InvalidStorageConfig(
+            "the `s3` backend requires the `s3` feature".to_string(),
+        )),
+    }
+}
+
+/// Reads through another reader, keeping track of the size and SHA-256 digest of
+/// what has been read so far, up to an optional size limit.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+    limit: u64,
+    limit_exceeded: bool,
+}
+
+impl<R> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+            limit: u64::MAX,
+            limit_exceeded: false,
+        }
+    }
+
+    /// Fails reads past `limit` bytes, so that oversized content is not read through.
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Returns `true` if reading failed because the content is larger than the limit.
+    pub fn limit_exceeded(&self) -> bool {
+        self.limit_exceeded
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the number of bytes read along with their hex encoded SHA-256 digest.
+    pub fn finish(self) -> (u64, String) {
+        (self.size, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+        let read = &buf.filled()[before..];
+        if this.size + read.len() as u64 > this.limit {
+            this.limit_exceeded = true;
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "content is larger than the size limit",
+            )));
+        }
+        this.hasher.update(read);
+        this.size += read.len() as u64;
+        Poll::Ready(Ok(()))
+    }
+}
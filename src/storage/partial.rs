@@ -0,0 +1,135 @@
+use crate::error::{Error, Resource};
+use rocket::tokio::{
+    fs::{self, File},
+    io::{self, AsyncRead, AsyncWriteExt},
+};
+use std::{collections::HashSet, path::PathBuf, sync::Mutex};
+
+/// Directory of the partial uploads, under `server.storage_path`
+const PARTIAL_DIR: &str = "uploads";
+
+/// The content received so far by resumable uploads, kept on the local filesystem
+/// until they are complete, whatever the storage backend.
+///
+/// Each upload has a file named by its ID that only ever grows: its size is the
+/// offset of the upload, so that uploads resume where they stopped, even after a
+/// restart.
+pub struct PartialUploads {
+    dir: PathBuf,
+    locked: Mutex<HashSet<String>>,
+}
+
+/// Exclusive access to a partial upload, released when dropped.
+pub struct PartialLock<'a> {
+    uploads: &'a PartialUploads,
+    id: String,
+}
+
+impl Drop for PartialLock<'_> {
+    fn drop(&mut self) {
+        self.uploads.locked.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl PartialUploads {
+    pub fn new(storage_path: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: storage_path.into().join(PARTIAL_DIR),
+            locked: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Upload IDs are generated by the server as plain alphanumeric strings
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Creates the empty content of a new upload.
+    pub async fn create(&self, id: &str) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir).await?;
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(self.path(id))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes received so far.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::NotFound)` if the upload has no content, e.g. once it is complete.
+    pub async fn offset(&self, id: &str) -> Result<u64, Error> {
+        match fs::metadata(self.path(id)).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::NotFound(Resource::Upload)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Takes exclusive access to an upload, for the time of a request.
+    ///
+    /// # Returns
+    ///
+    /// `Err(Error::UploadLocked)` if another request already has it.
+    pub fn lock(&self, id: &str) -> Result<PartialLock<'_>, Error> {
+        if !self.locked.lock().unwrap().insert(id.to_string()) {
+            return Err(Error::UploadLocked);
+        }
+
+        Ok(PartialLock {
+            uploads: self,
+            id: id.to_string(),
+        })
+    }
+
+    /// Appends everything read from `body` to the content of an upload.
+    ///
+    /// What was written before `body` failed, e.g. because the client went away,
+    /// is kept, for the upload to resume from there.
+    ///
+    /// # Returns
+    ///
+    /// The new offset of the upload.
+    pub async fn append(
+        &self,
+        lock: &PartialLock<'_>,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<u64, Error> {
+        let mut file = File::options()
+            .append(true)
+            .open(self.path(&lock.id))
+            .await?;
+        let copied = io::copy(body, &mut file).await;
+        file.flush().await?;
+        copied?;
+
+        Ok(file.metadata().await?.len())
+    }
+
+    /// Drops the content of an upload after `offset`.
+    pub async fn truncate(&self, lock: &PartialLock<'_>, offset: u64) -> Result<(), Error> {
+        let file = File::options()
+            .write(true)
+            .open(self.path(&lock.id))
+            .await?;
+        file.set_len(offset).await?;
+
+        Ok(())
+    }
+
+    /// Opens the content of an upload, to read it from the start.
+    pub async fn open(&self, lock: &PartialLock<'_>) -> Result<File, Error> {
+        Ok(File::open(self.path(&lock.id)).await?)
+    }
+
+    /// Deletes the content of an upload. Deleting a missing upload succeeds.
+    pub async fn remove(&self, id: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(id)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use super::{ObjectMeta, ObjectReader, Storage};
+use crate::{
+    error::{Error, Resource},
+    server::config::S3Config,
+};
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{
+    config::{Builder, Credentials, Region},
+    error::DisplayErrorContext,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+    Client,
+};
+use rocket::tokio::io::{AsyncRead, AsyncReadExt};
+use std::ops::Range;
+
+/// Size of the parts of multipart uploads. S3 requires at least 5 MiB, except
+/// for the last part.
+const PART_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Storage in a bucket of an S3-compatible object store.
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    /// Creates a client of the bucket. Credentials that are not configured are
+    /// looked up the same way as the AWS CLI does.
+    pub async fn new(config: &S3Config) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(config.region.to_owned()));
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            loader = loader.credentials_provider(Credentials::new(
+                access_key_id,
+                secret_access_key,
+                None,
+                None,
+                "api-server",
+            ));
+        }
+
+        let mut builder = Builder::from(&loader.load().await).force_path_style(config.path_style);
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+
+        Self {
+            client: Client::from_conf(builder.build()),
+            bucket: config.bucket.to_owned(),
+        }
+    }
+
+    /// Uploads `body` in parts, starting with the already read `first_part`.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        first_part: Vec<u8>,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<u64, Error> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(storage_error)?;
+        let upload_id = upload.upload_id().unwrap_or_default();
+
+        let uploaded = async {
+            let mut parts = Vec::new();
+            let mut size = 0;
+            let mut part = first_part;
+            while !part.is_empty() {
+                let part_number = parts.len() as i32 + 1;
+                size += part.len() as u64;
+                let uploaded = self
+                    .client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(part))
+                    .send()
+                    .await
+                    .map_err(storage_error)?;
+                parts.push(
+                    CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(uploaded.e_tag().map(str::to_string))
+                        .build(),
+                );
+                part = read_part(body).await?;
+            }
+
+            self.client
+                .complete_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await
+                .map_err(storage_error)?;
+            Ok(size)
+        }
+        .await;
+
+        if uploaded.is_err() {
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await
+                .map_err(storage_error)?;
+        }
+        uploaded
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for S3Storage {
+    async fn put(
+        &self,
+        key: &str,
+        body: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<u64, Error> {
+        let first_part = read_part(body).await?;
+        if (first_part.len() as u64) == PART_SIZE {
+            return self.put_multipart(key, first_part, body).await;
+        }
+
+        let size = first_part.len() as u64;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(first_part))
+            .send()
+            .await
+            .map_err(storage_error)?;
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str) -> Result<(ObjectMeta, ObjectReader), Error> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(service_error) if service_error.is_no_such_key() => {
+                    Error::NotFound(Resource::File)
+                }
+                _ => storage_error(e),
+            })?;
+        let meta = ObjectMeta {
+            key: key.to_string(),
+            size: object.content_length().unwrap_or_default() as u64,
+        };
+
+        Ok((meta, Box::pin(object.body.into_async_read())))
+    }
+
+    async fn get_range(&self, key: &str, range: Range<u64>) -> Result<ObjectReader, Error> {
+        let object = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(service_error) if service_error.is_no_such_key() => {
+                    Error::NotFound(Resource::File)
+                }
+                _ => storage_error(e),
+            })?;
+
+        Ok(Box::pin(object.body.into_async_read()))
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, Error> {
+        let object = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| match e.as_service_error() {
+                Some(service_error) if service_error.is_not_found() => {
+                    Error::NotFound(Resource::File)
+                }
+                _ => storage_error(e),
+            })?;
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: object.content_length().unwrap_or_default() as u64,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(storage_error)?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, Error> {
+        let mut objects = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let page = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(storage_error)?;
+            objects.extend(page.contents().iter().filter_map(|object| {
+                Some(ObjectMeta {
+                    key: object.key()?.to_string(),
+                    size: object.size().unwrap_or_default() as u64,
+                })
+            }));
+
+            continuation_token = page.next_continuation_token().map(str::to_string);
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        // Keys are listed in UTF-8 binary order, which is the order of `str`
+        Ok(objects)
+    }
+}
+
+/// Reads the next `PART_SIZE` bytes of `body`, fewer at the end of it.
+async fn read_part(body: &mut (dyn AsyncRead + Send + Unpin)) -> Result<Vec<u8>, Error> {
+    let mut part = Vec::new();
+    body.take(PART_SIZE).read_to_end(&mut part).await?;
+
+    Ok(part)
+}
+
+fn storage_error(e: impl std::error::Error + 'static) -> Error {
+    Error::StorageError(DisplayErrorContext(e).to_string())
+}
@@ -0,0 +1,200 @@
+//! API keys stored as keyed hashes, including the plaintext keys of databases created
+//! before they were hashed, and labelled keys that expire or get revoked.
+
+mod common;
+
+use api_server::server::config::Settings;
+use chrono::{DateTime, Duration, Utc};
+use common::{create_legacy_database, TestServer};
+use rocket::{
+    http::{Method, Status},
+    serde::json::{
+        serde_json::{from_value, json},
+        Value,
+    },
+};
+
+impl TestServer {
+    /// Lists the stored keys of `user_id` as `(key_prefix, key_hash, legacy_key)`.
+    async fn stored_keys(&self, user_id: &str) -> Vec<(String, Option<String>, Option<String>)> {
+        sqlx::query_as("SELECT key_prefix, key_hash, legacy_key FROM api_keys WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_all(&self.pool().await)
+            .await
+            .unwrap()
+    }
+
+    /// Creates a key for alice expiring at `expires_at`, as `(key_id, api_key)`.
+    async fn create_key(&self, expires_at: Option<DateTime<Utc>>) -> (i64, String) {
+        let (status, created) = self
+            .send(
+                Method::Post,
+                "/users/alice/keys",
+                &self.api_key,
+                Some(json!({ "label": "ci", "expires_at": expires_at })),
+            )
+            .await;
+        assert_eq!(status, Status::Ok);
+        let api_key = created["api_key"].as_str().unwrap().to_string();
+        (created["id"].as_i64().unwrap(), api_key)
+    }
+
+    /// Finds the key `key_id` of alice in her list of keys.
+    async fn listed_key(&self, key_id: i64) -> Value {
+        let (_, keys) = self
+            .send(Method::Get, "/users/alice/keys", &self.api_key, None)
+            .await;
+        keys.as_array()
+            .unwrap()
+            .iter()
+            .find(|key| key["id"] == key_id)
+            .unwrap()
+            .clone()
+    }
+}
+
+/// Tells whether `hash` looks like a hex encoded HMAC-SHA256.
+fn is_hmac(hash: Option<&str>) -> bool {
+    hash.is_some_and(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[rocket::async_test]
+async fn stores_keys_as_keyed_hashes() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let (status, me) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "alice");
+
+    // Only the prefix is kept in clear
+    let keys = server.stored_keys("alice").await;
+    assert_eq!(keys.len(), 1);
+    let (prefix, hash, legacy) = &keys[0];
+    assert_eq!(prefix, &server.api_key[..12]);
+    assert!(is_hmac(hash.as_deref()));
+    assert_eq!(legacy, &None);
+
+    // The hash is keyed with the server secret key, a copy of the database is not enough
+    let server = TestServer::start_in(dir.path(), |settings| {
+        settings.server.secret_key = "0123456789abcdef".repeat(4)
+    })
+    .await;
+    let (status, problem) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "UNAUTHENTICATED");
+}
+
+#[rocket::async_test]
+async fn hashes_legacy_plaintext_keys() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret_key = Settings::default().server.secret_key;
+    create_legacy_database(dir.path(), &secret_key, &[("old", "plaintext-key-of-old")]).await;
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+
+    // Copied aside by the migrations, not usable until hashed
+    assert_eq!(
+        server.stored_keys("old").await,
+        [(
+            "plaintext-ke".to_string(),
+            None,
+            Some("plaintext-key-of-old".to_string())
+        )]
+    );
+    let (status, _) = server
+        .send(Method::Get, "/me", "plaintext-key-of-old", None)
+        .await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let backend = server.backend().await;
+    assert_eq!(backend.hash_legacy_api_keys(&secret_key).await.unwrap(), 1);
+    let keys = server.stored_keys("old").await;
+    assert_eq!(keys.len(), 1);
+    assert!(is_hmac(keys[0].1.as_deref()));
+    assert_eq!(keys[0].2, None);
+    let (status, me) = server
+        .send(Method::Get, "/me", "plaintext-key-of-old", None)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "old");
+
+    // Nothing left to hash on the next start
+    assert_eq!(backend.hash_legacy_api_keys(&secret_key).await.unwrap(), 0);
+}
+
+#[rocket::async_test]
+async fn rejects_keys_with_a_bad_checksum_offline() {
+    let server = TestServer::start().await;
+    let api_key = &server.api_key;
+    assert!(api_key.starts_with("ak_live_"));
+    assert_eq!(
+        api_key.len(),
+        "ak_live_".len() + server.settings.server.api_key_length + 6
+    );
+
+    // A typo in the random part or in the checksum
+    let mut mistyped = api_key.clone().into_bytes();
+    mistyped[10] = if mistyped[10] == b'a' { b'b' } else { b'a' };
+    let mistyped = String::from_utf8(mistyped).unwrap();
+    let mut bad_checksum = api_key[..api_key.len() - 1].to_string();
+    bad_checksum.push(if api_key.ends_with('0') { '1' } else { '0' });
+
+    // Without the keys table, only the lookup of well-formed keys fails
+    sqlx::query("DROP TABLE api_keys")
+        .execute(&server.pool().await)
+        .await
+        .unwrap();
+    for api_key in [&mistyped, &bad_checksum] {
+        let (status, problem) = server.send(Method::Get, "/me", api_key, None).await;
+        assert_eq!(status, Status::Unauthorized, "{api_key}");
+        assert_eq!(problem["code"], "UNAUTHENTICATED");
+    }
+    let (status, _) = server.send(Method::Get, "/me", api_key, None).await;
+    assert_eq!(status, Status::InternalServerError);
+}
+
+#[rocket::async_test]
+async fn rejects_expired_and_revoked_keys() {
+    let server = TestServer::start().await;
+
+    let (expired_id, expired) = server
+        .create_key(Some(Utc::now() - Duration::minutes(1)))
+        .await;
+    let (status, problem) = server.send(Method::Get, "/me", &expired, None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "API_KEY_EXPIRED");
+    assert_eq!(
+        server.listed_key(expired_id).await["last_used_at"],
+        Value::Null
+    );
+
+    // Keys are marked as used on every request
+    let (key_id, api_key) = server
+        .create_key(Some(Utc::now() + Duration::hours(1)))
+        .await;
+    assert_eq!(server.listed_key(key_id).await["last_used_at"], Value::Null);
+    let before = Utc::now();
+    let (status, me) = server.send(Method::Get, "/me", &api_key, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "alice");
+    let last_used_at: DateTime<Utc> =
+        from_value(server.listed_key(key_id).await["last_used_at"].clone()).unwrap();
+    assert!(last_used_at >= before && last_used_at <= Utc::now());
+
+    let (status, _) = server
+        .send(
+            Method::Delete,
+            &format!("/users/alice/keys/{}", key_id),
+            &server.api_key,
+            None,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let (status, problem) = server.send(Method::Get, "/me", &api_key, None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "API_KEY_REVOKED");
+    assert_eq!(server.listed_key(key_id).await["revoked"], true);
+
+    // Other keys keep working
+    let (status, _) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Ok);
+}
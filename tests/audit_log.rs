@@ -0,0 +1,138 @@
+//! Actions recorded in the audit log, successful or not, as listed by admins and never
+//! changed afterwards.
+
+mod common;
+
+use api_server::models::Role;
+use common::{json, TestServer, CLIENT_ADDR, PASSWORD};
+use rocket::{
+    http::{Header, Method, Status},
+    serde::json::{serde_json::json, Value},
+};
+
+impl TestServer {
+    /// Lists the events of the audit log matching `query`, as `api_key`.
+    async fn audit_events(&self, api_key: &str, query: &str) -> Vec<Value> {
+        let response = self
+            .client
+            .get(format!("/audit?{}", query))
+            .header(Header::new("X-API-KEY", api_key.to_string()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+        let (status, page) = json(response).await;
+        assert_eq!(status, Status::Ok, "{page}");
+        page["items"].as_array().unwrap().to_owned()
+    }
+}
+
+/// The fields of an event that do not change from one run to the other.
+fn summary(event: &Value) -> Value {
+    assert!(event["request_id"].is_string());
+    assert_eq!(event["ip"], "127.0.0.1");
+    json!({
+        "actor": event["actor"],
+        "action": event["action"],
+        "target": event["target"],
+        "field": event["field"],
+        "outcome": event["outcome"],
+        "detail": event["detail"],
+    })
+}
+
+#[rocket::async_test]
+async fn records_successes_and_failures() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+    server.create_user("bob", Role::User).await;
+
+    let carol =
+        json!({ "user_id": "carol", "password": PASSWORD, "email_id": "carol@example.com" });
+    for expected in [Status::Ok, Status::Conflict] {
+        let (status, _) = server
+            .send(Method::Post, "/users", &admin_key, Some(carol.clone()))
+            .await;
+        assert_eq!(status, expected);
+    }
+    for (api_key, expected) in [
+        (&server.api_key, Status::Forbidden),
+        (&admin_key, Status::Ok),
+    ] {
+        let (status, _) = server
+            .send(
+                Method::Put,
+                "/users/bob",
+                api_key,
+                Some(json!({ "password": "Another#123" })),
+            )
+            .await;
+        assert_eq!(status, expected);
+    }
+
+    // Most recent first
+    let events = server.audit_events(&admin_key, "action=user.create").await;
+    assert_eq!(
+        events.iter().map(summary).collect::<Vec<_>>(),
+        [
+            json!({ "actor": "root", "action": "user.create", "target": "carol", "field": null,
+                    "outcome": "failure", "detail": "User conflict" }),
+            json!({ "actor": "root", "action": "user.create", "target": "carol", "field": null,
+                    "outcome": "success", "detail": null }),
+        ]
+    );
+    let events = server.audit_events(&admin_key, "action=user.update").await;
+    assert_eq!(
+        events.iter().map(summary).collect::<Vec<_>>(),
+        [
+            json!({ "actor": "root", "action": "user.update", "target": "bob",
+                    "field": "password", "outcome": "success", "detail": null }),
+            json!({ "actor": "alice", "action": "user.update", "target": "bob", "field": null,
+                    "outcome": "failure", "detail": "User does not have access rights" }),
+        ]
+    );
+}
+
+#[rocket::async_test]
+async fn keeps_events_from_changing() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+    let (status, _) = server
+        .send(Method::Delete, "/users/alice", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    // Not even with direct access to the database
+    let pool = server.pool().await;
+    for statement in [
+        "UPDATE audit_log SET actor = 'alice'",
+        "DELETE FROM audit_log",
+    ] {
+        let error = sqlx::query(statement).execute(&pool).await.unwrap_err();
+        assert!(
+            error.to_string().contains("audit_log is append-only"),
+            "{statement}: {error}"
+        );
+    }
+    let events = server.audit_events(&admin_key, "").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["actor"], "root");
+    assert_eq!(events[0]["action"], "user.delete");
+}
+
+#[rocket::async_test]
+async fn records_only_the_code_of_server_errors() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    // The storage backend cannot create files
+    let _ = std::fs::remove_dir_all(server.storage_dir());
+    std::fs::write(server.storage_dir(), "not a directory").unwrap();
+    let (status, problem) = server.upload("notes.txt", "text/plain", b"notes").await;
+    assert_eq!(status, Status::InternalServerError);
+    let code = problem["code"].as_str().unwrap().to_string();
+
+    let events = server.audit_events(&admin_key, "action=file.upload").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["outcome"], "failure");
+    assert_eq!(events[0]["detail"], code.as_str());
+}
@@ -0,0 +1,70 @@
+//! Creation of the first user of a fresh database.
+
+mod common;
+
+use api_server::{
+    db::{self, DynBackend},
+    models::{NewUser, Role},
+    secure::hash_pass::PasswordHasher,
+    server::config::Settings,
+};
+use common::PASSWORD;
+
+fn new_admin(user_id: &str) -> NewUser {
+    NewUser {
+        user_id: user_id.to_string(),
+        password: PASSWORD.to_string(),
+        email_id: format!("{}@example.com", user_id),
+        role: Role::Admin,
+    }
+}
+
+async fn create_first_user(
+    backend: &DynBackend,
+    settings: &Settings,
+    user_id: &str,
+) -> Result<String, String> {
+    let hasher = PasswordHasher::new(
+        settings.server.secret_key.to_owned(),
+        settings.server.argon2.to_owned(),
+    );
+    backend
+        .create_first_user(
+            new_admin(user_id),
+            &hasher,
+            &settings.server.secret_key,
+            settings.server.api_key_length,
+        )
+        .await
+        .map(|user| user.user_id)
+        .map_err(|e| e.to_string())
+}
+
+const ALREADY_BOOTSTRAPPED: &str = "Users already exist, refusing to bootstrap";
+
+#[rocket::async_test]
+async fn creates_only_one_first_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let backend = db::connect(dir.path().join("api.db").to_str().unwrap())
+        .await
+        .unwrap();
+    backend.migrate().await.unwrap();
+    let mut settings = Settings::default();
+    settings.server.argon2.mem_cost = 4096;
+    settings.server.argon2.time_cost = 1;
+
+    // Concurrent attempts cannot both find the database empty
+    let results = rocket::tokio::join!(
+        create_first_user(&backend, &settings, "first"),
+        create_first_user(&backend, &settings, "second"),
+        create_first_user(&backend, &settings, "third"),
+    );
+    let results = [results.0, results.1, results.2];
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    for error in results.iter().filter_map(|result| result.as_ref().err()) {
+        assert_eq!(error, ALREADY_BOOTSTRAPPED);
+    }
+
+    let result = create_first_user(&backend, &settings, "fourth").await;
+    assert_eq!(result.unwrap_err(), ALREADY_BOOTSTRAPPED);
+}
@@ -0,0 +1,312 @@
+//! Fixture shared by the integration tests: a server on its own database and storage
+//! directory, with a regular user to send requests as.
+#![allow(dead_code)]
+
+use api_server::{
+    db::{self, DynBackend},
+    models::{NewUser, Role},
+    secure::hash_pass::PasswordHasher,
+    server::{build_server, config::Settings},
+    storage,
+};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    local::asynchronous::{Client, LocalResponse},
+    serde::json::{serde_json, Value},
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Address the requests come from, unless a test sets another
+pub const CLIENT_ADDR: &str = "127.0.0.1:50000";
+/// Password of the users created by the fixture
+pub const PASSWORD: &str = "Secret#123";
+const BOUNDARY: &str = "integration-test";
+
+pub struct TestServer {
+    pub client: Client,
+    /// API key of `alice`, a regular user
+    pub api_key: String,
+    /// Settings the server was started with
+    pub settings: Settings,
+    /// Directory of the database and of the storage
+    pub path: PathBuf,
+    _dir: Option<TempDir>,
+}
+
+impl TestServer {
+    /// Starts a server on a fresh database and storage directory.
+    pub async fn start() -> Self {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Starts a server on a fresh database and storage directory, with `settings`
+    /// applied to the test defaults.
+    pub async fn start_with(settings: impl FnOnce(&mut Settings)) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Self::start_in(dir.path(), settings).await;
+        server._dir = Some(dir);
+        server
+    }
+
+    /// Starts a server on the database and storage directory in `dir`, kept by the
+    /// caller across restarts. `alice` is created on the first start only.
+    pub async fn start_in(dir: &Path, settings: impl FnOnce(&mut Settings)) -> Self {
+        let mut config = Settings::default();
+        config.server.storage_path = dir.join("store").to_string_lossy().into();
+        config.server.argon2.mem_cost = 4096;
+        config.server.argon2.time_cost = 1;
+        settings(&mut config);
+
+        let backend = connect(dir).await;
+        backend.migrate().await.unwrap();
+        let key_file = dir.join("api.key");
+        let api_key = match std::fs::read_to_string(&key_file) {
+            Ok(api_key) => api_key,
+            Err(_) => {
+                let api_key = create_user(&backend, &config, "alice", Role::User).await;
+                std::fs::write(&key_file, &api_key).unwrap();
+                api_key
+            }
+        };
+
+        let storage = storage::connect(&config.server).await.unwrap();
+        let client = Client::tracked(build_server(config.clone(), backend, storage))
+            .await
+            .unwrap();
+        Self {
+            client,
+            api_key,
+            settings: config,
+            path: dir.to_path_buf(),
+            _dir: None,
+        }
+    }
+
+    /// Opens another connection to the database of the server.
+    pub async fn backend(&self) -> DynBackend {
+        connect(&self.path).await
+    }
+
+    /// Opens a plain connection pool to the database of the server, to look at what it
+    /// actually stores.
+    pub async fn pool(&self) -> SqlitePool {
+        pool(&self.path).await
+    }
+
+    /// Creates a user with `role`, returning its API key.
+    pub async fn create_user(&self, user_id: &str, role: Role) -> String {
+        create_user(&self.backend().await, &self.settings, user_id, role).await
+    }
+
+    /// Uploads `content` at once as `file_name` and `content_type`, as `alice`.
+    pub async fn upload(
+        &self,
+        file_name: &str,
+        content_type: &str,
+        content: &[u8],
+    ) -> (Status, Value) {
+        let mut body = format!(
+            "--{BOUNDARY}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+             Content-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend(format!("\r\n--{BOUNDARY}--\r\n").into_bytes());
+
+        let response = self
+            .client
+            .post("/upload")
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(body)
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    /// Sends a request authenticated with `api_key`, with `body` as JSON if any,
+    /// returning the status and JSON body of the response.
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        api_key: &str,
+        body: Option<Value>,
+    ) -> (Status, Value) {
+        let mut request = self
+            .client
+            .req(method, uri.to_string())
+            .header(Header::new("X-API-KEY", api_key.to_string()))
+            .remote(CLIENT_ADDR.parse().unwrap());
+        if let Some(body) = body {
+            request = request.header(ContentType::JSON).body(body.to_string());
+        }
+        json(request.dispatch().await).await
+    }
+
+    /// Logs `user_id` in with `password`, returning the status and the session tokens
+    /// or problem.
+    pub async fn login(&self, user_id: &str, password: &str) -> (Status, Value) {
+        let response = self
+            .client
+            .post("/auth/login")
+            .header(ContentType::JSON)
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(serde_json::json!({ "user_id": user_id, "password": password }).to_string())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    /// Sends a tus request as `alice`, with `headers` and `body`.
+    pub async fn tus(
+        &self,
+        method: Method,
+        uri: &str,
+        headers: &[(&'static str, &str)],
+        body: &[u8],
+    ) -> LocalResponse<'_> {
+        let mut request = self
+            .client
+            .req(method, uri.to_string())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(body);
+        if method == Method::Patch {
+            request = request.header(ContentType::new("application", "offset+octet-stream"));
+        }
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, value.to_string()));
+        }
+        request.dispatch().await
+    }
+
+    /// Directory of the storage backend.
+    pub fn storage_dir(&self) -> PathBuf {
+        self.path.join("store")
+    }
+
+    /// Lists the files in the storage backend, partial uploads aside.
+    pub fn stored_files(&self) -> Vec<PathBuf> {
+        let uploads = self.storage_dir().join("uploads");
+        files_below(&self.storage_dir())
+            .into_iter()
+            .filter(|path| !path.starts_with(&uploads))
+            .collect()
+    }
+}
+
+async fn connect(dir: &Path) -> DynBackend {
+    db::connect(dir.join("api.db").to_str().unwrap())
+        .await
+        .unwrap()
+}
+
+async fn pool(dir: &Path) -> SqlitePool {
+    let options = SqliteConnectOptions::new()
+        .filename(dir.join("api.db"))
+        .create_if_missing(true);
+    SqlitePool::connect_with(options).await.unwrap()
+}
+
+async fn create_user(
+    backend: &DynBackend,
+    settings: &Settings,
+    user_id: &str,
+    role: Role,
+) -> String {
+    let hasher = PasswordHasher::new(
+        settings.server.secret_key.to_owned(),
+        settings.server.argon2.to_owned(),
+    );
+    let user = NewUser {
+        user_id: user_id.to_string(),
+        password: PASSWORD.to_string(),
+        email_id: format!("{}@example.com", user_id),
+        role,
+    };
+    backend
+        .create_user(
+            user,
+            &hasher,
+            &settings.server.secret_key,
+            settings.server.api_key_length,
+        )
+        .await
+        .unwrap()
+        .api_key
+}
+
+/// Creates in `dir` the database of a server from before migrations, holding `users`
+/// as `(user_id, API key)`. Keys are stored in plaintext, and passwords hashed the
+/// legacy way, with `secret_key` as salt.
+pub async fn create_legacy_database(dir: &Path, secret_key: &str, users: &[(&str, &str)]) {
+    let pool = pool(dir).await;
+    sqlx::query(
+        "CREATE TABLE users (user_id TEXT PRIMARY KEY, password TEXT NOT NULL, \
+         api_key TEXT NOT NULL, email_id TEXT NOT NULL)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: 4096,
+        time_cost: 1,
+        ..argon2::Config::default()
+    };
+    let password =
+        argon2::hash_encoded(PASSWORD.as_bytes(), secret_key.as_bytes(), &config).unwrap();
+    for (user_id, api_key) in users {
+        sqlx::query("INSERT INTO users (user_id, password, api_key, email_id) VALUES (?, ?, ?, ?)")
+            .bind(user_id)
+            .bind(&password)
+            .bind(api_key)
+            .bind(format!("{}@example.com", user_id))
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    pool.close().await;
+}
+
+/// Lists every file below `dir`.
+pub fn files_below(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                files_below(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+}
+
+/// The status and JSON body of a response, `null` when it has none.
+pub async fn json(response: LocalResponse<'_>) -> (Status, Value) {
+    let status = response.status();
+    let body = response.into_string().await.unwrap_or_default();
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+/// The value of a header the response must have.
+pub fn header(response: &LocalResponse<'_>, name: &str) -> String {
+    response
+        .headers()
+        .get_one(name)
+        .unwrap_or_else(|| panic!("missing {name} header"))
+        .to_string()
+}
@@ -0,0 +1,220 @@
+//! Downloads through signed links, without credentials: expiry, tampering, single
+//! use and IP binding.
+
+mod common;
+
+use common::{json, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::LocalResponse,
+    serde::json::{serde_json, Value},
+};
+use std::time::Duration;
+
+const CONTENT: &str = "shared with a link";
+
+/// Starts a server whose links last at most an hour.
+async fn start() -> TestServer {
+    TestServer::start_with(|settings| settings.server.max_download_link_ttl = 3600).await
+}
+
+impl TestServer {
+    /// Uploads `CONTENT` as a text file, returning its ID.
+    async fn upload_shared(&self) -> i64 {
+        let (status, file) = self
+            .upload("shared.txt", "text/plain", CONTENT.as_bytes())
+            .await;
+        assert_eq!(status, Status::Ok);
+        file["id"].as_i64().unwrap()
+    }
+
+    /// Creates a link to a file with the options of `link`.
+    async fn create_link(&self, file_id: i64, link: Value) -> (Status, Value) {
+        let response = self
+            .client
+            .post(format!("/files/{}/links", file_id))
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(link.to_string())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    /// Creates a link to a file, returning its URL.
+    async fn link_url(&self, file_id: i64, link: Value) -> String {
+        let (status, link) = self.create_link(file_id, link).await;
+        assert_eq!(status, Status::Ok, "{link}");
+        link["url"].as_str().unwrap().to_string()
+    }
+
+    /// Follows a link without credentials, from `client_addr`.
+    async fn follow(&self, url: &str, client_addr: &str) -> LocalResponse<'_> {
+        self.client
+            .get(url.to_string())
+            .remote(client_addr.parse().unwrap())
+            .dispatch()
+            .await
+    }
+}
+
+#[rocket::async_test]
+async fn downloads_without_credentials() {
+    let server = start().await;
+    let file_id = server.upload_shared().await;
+
+    let url = server.link_url(file_id, serde_json::json!({})).await;
+    assert!(url.starts_with(&format!("/download/{}/signed?expires=", file_id)));
+
+    for _ in 0..2 {
+        let response = server.follow(&url, CLIENT_ADDR).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        assert_eq!(response.into_string().await.unwrap(), CONTENT);
+    }
+
+    let response = server
+        .client
+        .get(url.to_owned())
+        .header(Header::new("Range", "bytes=0-5"))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PartialContent);
+    assert_eq!(response.into_string().await.unwrap(), "shared");
+
+    let response = server
+        .client
+        .head(url.to_owned())
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    // Links only work with their signature, unchanged
+    let unsigned = url.split("&signature=").next().unwrap().to_string();
+    let tampered = [
+        url.replacen("expires=", "expires=1", 1),
+        url.replacen(&format!("/download/{}/", file_id), "/download/999/", 1),
+        format!("{unsigned}&signature=00"),
+        format!("{unsigned}&signature=zz"),
+    ];
+    for url in tampered {
+        let response = server.follow(&url, CLIENT_ADDR).await;
+        assert_eq!(response.status(), Status::Forbidden, "{url}");
+    }
+    let response = server.follow(&unsigned, CLIENT_ADDR).await;
+    assert!(response.status().class().is_client_error());
+
+    let response = server
+        .client
+        .post(format!("/files/{}/links", file_id))
+        .header(ContentType::JSON)
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .body("{}")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn rejects_invalid_links() {
+    let server = start().await;
+    let file_id = server.upload_shared().await;
+
+    let (status, _) = server.create_link(999, serde_json::json!({})).await;
+    assert_eq!(status, Status::NotFound);
+    for expires_in in [0, 3601] {
+        let (status, problem) = server
+            .create_link(file_id, serde_json::json!({ "expires_in": expires_in }))
+            .await;
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert_eq!(problem["code"], "INVALID_INPUT");
+    }
+
+    let url = server
+        .link_url(file_id, serde_json::json!({ "expires_in": 1 }))
+        .await;
+    rocket::tokio::time::sleep(Duration::from_millis(1100)).await;
+    let response = server.follow(&url, CLIENT_ADDR).await;
+    assert_eq!(response.status(), Status::Gone);
+    let problem: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(problem["code"], "DOWNLOAD_LINK_EXPIRED");
+}
+
+#[rocket::async_test]
+async fn uses_up_single_use_links() {
+    let server = start().await;
+    let file_id = server.upload_shared().await;
+    let url = server
+        .link_url(file_id, serde_json::json!({ "single_use": true }))
+        .await;
+    assert!(url.contains("&once="));
+
+    let response = server
+        .client
+        .head(url.to_owned())
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = server.follow(&url, CLIENT_ADDR).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.into_string().await.unwrap(), CONTENT);
+    assert_eq!(
+        server.follow(&url, CLIENT_ADDR).await.status(),
+        Status::Gone
+    );
+
+    // Other single use links to the same file are unaffected
+    let other = server
+        .link_url(file_id, serde_json::json!({ "single_use": true }))
+        .await;
+    assert_eq!(
+        server.follow(&other, CLIENT_ADDR).await.status(),
+        Status::Ok
+    );
+}
+
+#[rocket::async_test]
+async fn binds_links_to_addresses() {
+    let server = start().await;
+    let file_id = server.upload_shared().await;
+
+    let url = server
+        .link_url(file_id, serde_json::json!({ "ip": "10.1.2.3" }))
+        .await;
+    let response = server.follow(&url, "10.1.2.3:40000").await;
+    assert_eq!(response.status(), Status::Ok);
+    let response = server.follow(&url, CLIENT_ADDR).await;
+    assert_eq!(response.status(), Status::Forbidden);
+
+    // Forwarding headers set by the client are not trusted
+    for (name, value) in [
+        ("X-Real-IP", "10.1.2.3"),
+        ("X-Forwarded-For", "10.1.2.3"),
+        ("X-Forwarded-For", "10.1.2.3, 192.0.2.1"),
+    ] {
+        let response = server
+            .client
+            .get(url.to_owned())
+            .header(Header::new(name, value))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden, "{name}: {value}");
+    }
+
+    let url = server
+        .link_url(file_id, serde_json::json!({ "ip": "2001:db8::1" }))
+        .await;
+    let response = server.follow(&url, "[2001:db8:0::1]:40000").await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let (status, _) = server
+        .create_link(file_id, serde_json::json!({ "ip": "not an address" }))
+        .await;
+    assert!(status.class().is_client_error());
+}
@@ -0,0 +1,213 @@
+//! Downloads of byte ranges of files, conditional downloads and `HEAD` requests.
+
+mod common;
+
+use common::{header, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{Header, Status},
+    local::asynchronous::LocalResponse,
+};
+use sha2::{Digest, Sha256};
+
+const CONTENT: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
+
+impl TestServer {
+    /// Uploads `CONTENT` as a text file, returning its download URI.
+    async fn upload_alphabet(&self) -> String {
+        let (status, file) = self
+            .upload("alphabet.txt", "text/plain", CONTENT.as_bytes())
+            .await;
+        assert_eq!(status, Status::Ok);
+        format!("/download/{}", file["id"])
+    }
+
+    async fn get(&self, uri: &str, headers: &[(&'static str, &str)]) -> LocalResponse<'_> {
+        let mut request = self
+            .client
+            .get(uri.to_string())
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap());
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, value.to_string()));
+        }
+        request.dispatch().await
+    }
+
+    async fn head(&self, uri: &str, headers: &[(&'static str, &str)]) -> LocalResponse<'_> {
+        let mut request = self
+            .client
+            .head(uri.to_string())
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap());
+        for (name, value) in headers {
+            request = request.header(Header::new(*name, value.to_string()));
+        }
+        request.dispatch().await
+    }
+}
+
+#[rocket::async_test]
+async fn downloads_ranges() {
+    let server = TestServer::start().await;
+    let uri = server.upload_alphabet().await;
+
+    let response = server.get(&uri, &[]).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(header(&response, "Accept-Ranges"), "bytes");
+    assert_eq!(header(&response, "Content-Length"), "36");
+    assert_eq!(response.into_string().await.unwrap(), CONTENT);
+
+    for (range, content_range, content) in [
+        ("bytes=2-5", "bytes 2-5/36", "2345"),
+        ("bytes=30-", "bytes 30-35/36", "uvwxyz"),
+        ("bytes=-3", "bytes 33-35/36", "xyz"),
+        ("bytes=34-99", "bytes 34-35/36", "yz"),
+        ("bytes=-99", "bytes 0-35/36", CONTENT),
+        // Unsatisfiable ranges are skipped
+        ("bytes=50-60, 0-0", "bytes 0-0/36", "0"),
+    ] {
+        let response = server.get(&uri, &[("Range", range)]).await;
+        assert_eq!(response.status(), Status::PartialContent, "{range}");
+        assert_eq!(header(&response, "Content-Range"), content_range, "{range}");
+        assert_eq!(header(&response, "Content-Type"), "text/plain", "{range}");
+        assert_eq!(
+            header(&response, "Content-Length"),
+            content.len().to_string(),
+            "{range}"
+        );
+        assert_eq!(response.into_string().await.unwrap(), content, "{range}");
+    }
+
+    // Invalid ranges are ignored
+    for range in [
+        "bytes=5-2",
+        "bytes=a-b",
+        "bytes=",
+        "items=0-1",
+        "bytes=1-2,x",
+    ] {
+        let response = server.get(&uri, &[("Range", range)]).await;
+        assert_eq!(response.status(), Status::Ok, "{range}");
+        assert_eq!(response.into_string().await.unwrap(), CONTENT, "{range}");
+    }
+
+    for range in ["bytes=36-", "bytes=-0", "bytes=40-50,36-"] {
+        let response = server.get(&uri, &[("Range", range)]).await;
+        assert_eq!(response.status(), Status::RangeNotSatisfiable, "{range}");
+        assert_eq!(header(&response, "Content-Range"), "bytes */36", "{range}");
+    }
+}
+
+#[rocket::async_test]
+async fn downloads_multiple_ranges() {
+    let server = TestServer::start().await;
+    let uri = server.upload_alphabet().await;
+
+    let response = server.get(&uri, &[("Range", "bytes=0-1, -2")]).await;
+    assert_eq!(response.status(), Status::PartialContent);
+    assert!(response.headers().get_one("Content-Range").is_none());
+    let content_type = response.content_type().unwrap();
+    assert_eq!(
+        (content_type.top().as_str(), content_type.sub().as_str()),
+        ("multipart", "byteranges")
+    );
+    let boundary = content_type.param("boundary").unwrap().to_string();
+    let length: usize = header(&response, "Content-Length").parse().unwrap();
+
+    let body = response.into_string().await.unwrap();
+    assert_eq!(
+        body,
+        format!(
+            "--{boundary}\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Range: bytes 0-1/36\r\n\r\n\
+             01\r\n\
+             --{boundary}\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Range: bytes 34-35/36\r\n\r\n\
+             yz\r\n\
+             --{boundary}--\r\n"
+        )
+    );
+    assert_eq!(body.len(), length);
+}
+
+#[rocket::async_test]
+async fn handles_conditional_requests() {
+    let server = TestServer::start().await;
+    let uri = server.upload_alphabet().await;
+
+    let response = server.get(&uri, &[]).await;
+    let etag = header(&response, "ETag");
+    let last_modified = header(&response, "Last-Modified");
+    assert_eq!(
+        etag,
+        format!("\"{}\"", hex::encode(Sha256::digest(CONTENT)))
+    );
+
+    let weak_etag = format!("W/{etag}");
+    let etags = format!("\"other\", {etag}");
+    for headers in [
+        vec![("If-None-Match", etag.as_str())],
+        vec![("If-None-Match", weak_etag.as_str())],
+        vec![("If-None-Match", etags.as_str())],
+        vec![("If-None-Match", "*")],
+        vec![("If-Modified-Since", last_modified.as_str())],
+        vec![("If-Modified-Since", "Fri, 31 Dec 9999 23:59:59 GMT")],
+    ] {
+        let response = server.get(&uri, &headers).await;
+        assert_eq!(response.status(), Status::NotModified, "{headers:?}");
+        assert_eq!(header(&response, "ETag"), etag);
+        assert!(response.into_bytes().await.unwrap_or_default().is_empty());
+    }
+
+    for headers in [
+        vec![("If-None-Match", "\"other\"")],
+        vec![("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+        // If-None-Match takes precedence
+        vec![
+            ("If-None-Match", "\"other\""),
+            ("If-Modified-Since", last_modified.as_str()),
+        ],
+    ] {
+        let response = server.get(&uri, &headers).await;
+        assert_eq!(response.status(), Status::Ok, "{headers:?}");
+        assert_eq!(response.into_string().await.unwrap(), CONTENT);
+    }
+
+    // Ranges of a content that changed since are not sent
+    for (if_range, status) in [
+        (etag.as_str(), Status::PartialContent),
+        (last_modified.as_str(), Status::PartialContent),
+        (weak_etag.as_str(), Status::Ok),
+        ("\"other\"", Status::Ok),
+        ("Thu, 01 Jan 1970 00:00:00 GMT", Status::Ok),
+    ] {
+        let response = server
+            .get(&uri, &[("Range", "bytes=0-0"), ("If-Range", if_range)])
+            .await;
+        assert_eq!(response.status(), status, "{if_range}");
+    }
+}
+
+#[rocket::async_test]
+async fn describes_downloads() {
+    let server = TestServer::start().await;
+    let uri = server.upload_alphabet().await;
+
+    // Ranges only apply to GET requests
+    let response = server.head(&uri, &[("Range", "bytes=0-1")]).await;
+    assert_eq!(response.status(), Status::Ok);
+    // The server sends the size of the stripped body as the Content-Length
+    assert_eq!(response.body().preset_size(), Some(36));
+    assert_eq!(header(&response, "Content-Type"), "text/plain");
+    assert_eq!(header(&response, "Accept-Ranges"), "bytes");
+    let etag = header(&response, "ETag");
+    assert!(response.into_bytes().await.unwrap_or_default().is_empty());
+
+    let response = server.head(&uri, &[("If-None-Match", etag.as_str())]).await;
+    assert_eq!(response.status(), Status::NotModified);
+
+    let response = server.head("/download/999", &[]).await;
+    assert_eq!(response.status(), Status::NotFound);
+}
@@ -0,0 +1,178 @@
+//! Uploads and downloads with file names crafted to escape the storage directory,
+//! overwrite other files or hit names reserved by the operating system.
+
+mod common;
+
+use common::{files_below, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{Header, Status},
+    serde::json::Value,
+};
+
+/// Uploads `content` as a text file named `file_name`.
+async fn upload_named(server: &TestServer, file_name: &str, content: &str) -> (Status, Value) {
+    server
+        .upload(file_name, "text/plain", content.as_bytes())
+        .await
+}
+
+async fn download(server: &TestServer, uri: &str) -> (Status, String) {
+    let response = server
+        .client
+        .get(uri.to_string())
+        .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+
+    (
+        response.status(),
+        response.into_string().await.unwrap_or_default(),
+    )
+}
+
+#[rocket::async_test]
+async fn rejects_names_with_directories() {
+    let server = TestServer::start().await;
+
+    for file_name in [
+        "../escaped.txt",
+        "../../../../../../etc/passwd",
+        "..\\..\\windows\\win.ini",
+        "/etc/passwd",
+        "/tmp/escaped.txt",
+        "\\\\server\\share\\escaped.txt",
+        "C:\\Windows\\escaped.txt",
+        "C:escaped.txt",
+        "notes.txt:stream",
+        "nested/escaped.txt",
+        "./escaped.txt",
+        "..",
+        ".",
+        "...",
+        " .. ",
+    ] {
+        let (status, problem) = upload_named(&server, file_name, "evil").await;
+        assert_eq!(status, Status::UnprocessableEntity, "{file_name}");
+        assert_eq!(problem["code"], "INVALID_INPUT", "{file_name}");
+        assert_eq!(problem["errors"][0]["field"], "file_name", "{file_name}");
+        assert_eq!(problem["errors"][0]["code"], "path", "{file_name}");
+    }
+
+    // Nothing was written, in the storage directory or around it
+    for path in files_below(&server.path) {
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(
+            name.starts_with("api.db") || name == "api.key",
+            "{}",
+            path.display()
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn rejects_reserved_names() {
+    let server = TestServer::start().await;
+
+    for file_name in [
+        "CON",
+        "con",
+        "PRN.txt",
+        "aux.tar.gz",
+        "NUL",
+        "nul.",
+        "Com1",
+        "LPT9.log",
+        "lpt1 .txt",
+    ] {
+        let (status, problem) = upload_named(&server, file_name, "evil").await;
+        assert_eq!(status, Status::UnprocessableEntity, "{file_name}");
+        assert_eq!(problem["errors"][0]["code"], "reserved", "{file_name}");
+    }
+    assert!(server.stored_files().is_empty());
+}
+
+#[rocket::async_test]
+async fn rejects_empty_and_long_names() {
+    let server = TestServer::start().await;
+
+    for (file_name, code) in [
+        (String::new(), "required"),
+        ("   ".to_string(), "required"),
+        ("\t\t".to_string(), "required"),
+        ("a".repeat(256), "length"),
+        ("é".repeat(128), "length"),
+    ] {
+        let (status, problem) = upload_named(&server, &file_name, "evil").await;
+        assert_eq!(status, Status::UnprocessableEntity, "{file_name}");
+        assert_eq!(problem["errors"][0]["code"], code, "{file_name}");
+    }
+    assert!(server.stored_files().is_empty());
+}
+
+#[rocket::async_test]
+async fn keeps_sanitized_names_as_metadata_only() {
+    let server = TestServer::start().await;
+
+    for (file_name, sanitized) in [
+        ("report.csv", "report.csv"),
+        ("  padded.txt \t", "padded.txt"),
+        ("tab\tinside.txt", "tabinside.txt"),
+        ("..hidden", "..hidden"),
+        ("console.log", "console.log"),
+        ("résumé 2024.txt", "résumé 2024.txt"),
+        (&"a".repeat(255), &"a".repeat(255)),
+    ] {
+        let (status, file) = upload_named(&server, file_name, "content").await;
+        assert_eq!(status, Status::Ok, "{file_name}");
+        assert_eq!(file["file_name"], sanitized);
+        assert!(file.get("stored_path").is_none());
+    }
+
+    for path in server.stored_files() {
+        let stored_name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(stored_name.len(), 32, "{}", path.display());
+        assert!(stored_name.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
+
+#[rocket::async_test]
+async fn same_names_do_not_overwrite_each_other() {
+    let server = TestServer::start().await;
+
+    let (_, first) = upload_named(&server, "same.txt", "first").await;
+    let (_, second) = upload_named(&server, "same.txt", "second").await;
+    assert_ne!(first["id"], second["id"]);
+    assert_eq!(server.stored_files().len(), 2);
+
+    let (status, content) = download(&server, &format!("/download/{}", first["id"])).await;
+    assert_eq!((status, content.as_str()), (Status::Ok, "first"));
+    let (status, content) = download(&server, &format!("/download/{}", second["id"])).await;
+    assert_eq!((status, content.as_str()), (Status::Ok, "second"));
+}
+
+#[rocket::async_test]
+async fn downloads_only_by_id() {
+    let server = TestServer::start().await;
+    let (_, file) = upload_named(&server, "secret.txt", "content").await;
+    let stored = server.stored_files().remove(0);
+    let stored_name = stored.file_name().unwrap().to_string_lossy();
+
+    for uri in [
+        "/download/..%2F..%2F..%2F..%2Fetc%2Fpasswd".to_string(),
+        "/download/%2Fetc%2Fpasswd".to_string(),
+        "/download/..".to_string(),
+        "/download/C:%5CWindows%5Cwin.ini".to_string(),
+        "/download/secret.txt".to_string(),
+        format!("/download/{}", stored_name),
+        format!("/download/{}%2F..", file["id"]),
+        "/download/0".to_string(),
+        "/download/-1".to_string(),
+        "/download/99999999999999999999".to_string(),
+    ] {
+        let (status, content) = download(&server, &uri).await;
+        assert_ne!(status, Status::Ok, "{uri}");
+        assert!(!content.contains("content"), "{uri}");
+        assert!(!content.contains("root:"), "{uri}");
+    }
+}
@@ -0,0 +1,209 @@
+//! The account of the current user, whose password only changes with the current one.
+
+mod common;
+
+use api_server::models::Role;
+use common::{TestServer, CLIENT_ADDR, PASSWORD};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    serde::json::{serde_json::json, Value},
+};
+
+impl TestServer {
+    /// Changes the password of alice from `PASSWORD` to `new_password`.
+    async fn change_password(&self, new_password: &str) -> Status {
+        let change = json!({ "current_password": PASSWORD, "new_password": new_password });
+        self.send(Method::Patch, "/me", &self.api_key, Some(change))
+            .await
+            .0
+    }
+
+    /// Gets the current user with the access token of `tokens` as bearer.
+    async fn me_with_token(&self, tokens: &Value) -> Status {
+        self.client
+            .get("/me")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", tokens["access_token"].as_str().unwrap()),
+            ))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    /// Refreshes the session of `tokens`.
+    async fn refresh(&self, tokens: &Value) -> Status {
+        self.client
+            .post("/auth/refresh")
+            .header(ContentType::JSON)
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(json!({ "refresh_token": tokens["refresh_token"] }).to_string())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    /// Reads what a password change of alice touches, as `(password hash, sessions,
+    /// usable keys, audited updates)`.
+    async fn alice_rows(&self) -> (String, i64, i64, i64) {
+        let pool = self.pool().await;
+        let count = |sql| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(sql)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+        (
+            sqlx::query_scalar("SELECT password FROM users WHERE user_id = 'alice'")
+                .fetch_one(&pool)
+                .await
+                .unwrap(),
+            count("SELECT COUNT(*) FROM sessions WHERE user_id = 'alice'").await,
+            count("SELECT COUNT(*) FROM api_keys WHERE user_id = 'alice' AND NOT revoked").await,
+            count("SELECT COUNT(*) FROM audit_log WHERE action = 'user.update'").await,
+        )
+    }
+}
+
+#[rocket::async_test]
+async fn changes_passwords_given_the_current_one() {
+    let server = TestServer::start().await;
+    let (status, me) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "alice");
+
+    let (status, problem) = server
+        .send(
+            Method::Patch,
+            "/me",
+            &server.api_key,
+            Some(json!({ "current_password": "Wrong#123", "new_password": "Another#123" })),
+        )
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(problem["code"], "INCORRECT_PASSWORD");
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+
+    let (status, _) = server
+        .send(
+            Method::Patch,
+            "/me",
+            &server.api_key,
+            Some(json!({ "current_password": PASSWORD, "new_password": "Another#123" })),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        server.login("alice", PASSWORD).await.0,
+        Status::Unauthorized
+    );
+    assert_eq!(server.login("alice", "Another#123").await.0, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn needs_the_current_password_to_change_it() {
+    let server = TestServer::start().await;
+    let (_, tokens) = server.login("alice", PASSWORD).await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    // Neither a key nor a session is enough on their own
+    let (status, problem) = server
+        .send(
+            Method::Put,
+            "/users/alice",
+            &server.api_key,
+            Some(json!({ "password": "Stolen#123" })),
+        )
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(problem["code"], "FORBIDDEN");
+    let response = server
+        .client
+        .put("/users/alice")
+        .header(ContentType::JSON)
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", tokens["access_token"].as_str().unwrap()),
+        ))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .body(json!({ "password": "Stolen#123" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Forbidden);
+    assert_eq!(
+        server.login("alice", "Stolen#123").await.0,
+        Status::Unauthorized
+    );
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+
+    // Admins reset passwords of others
+    let (status, _) = server
+        .send(
+            Method::Put,
+            "/users/alice",
+            &admin_key,
+            Some(json!({ "password": "Reset#123" })),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(server.login("alice", "Reset#123").await.0, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn changes_passwords_in_one_transaction() {
+    let server = TestServer::start().await;
+    let (_, tokens) = server.login("alice", PASSWORD).await;
+    let (hash, sessions, keys, changes) = server.alice_rows().await;
+    assert!(hash.starts_with("$argon2"));
+    assert_eq!((sessions, keys, changes), (1, 1, 0));
+
+    // Nothing changes unless the change can be audited
+    sqlx::query(
+        "CREATE TRIGGER no_audit BEFORE INSERT ON audit_log BEGIN SELECT RAISE(ABORT, 'no'); END",
+    )
+    .execute(&server.pool().await)
+    .await
+    .unwrap();
+    assert_eq!(
+        server.change_password("Another#123").await,
+        Status::InternalServerError
+    );
+    assert_eq!(server.alice_rows().await, (hash.clone(), 1, 1, 0));
+    assert_eq!(server.me_with_token(&tokens).await, Status::Ok);
+    sqlx::query("DROP TRIGGER no_audit")
+        .execute(&server.pool().await)
+        .await
+        .unwrap();
+
+    assert_eq!(server.change_password("Another#123").await, Status::Ok);
+    let (new_hash, sessions, keys, changes) = server.alice_rows().await;
+    assert!(new_hash.starts_with("$argon2") && new_hash != hash);
+    assert!(!new_hash.contains("Another#123"));
+    assert_eq!((sessions, keys, changes), (0, 0, 1));
+
+    // Whatever was open with the previous password is closed
+    assert_eq!(server.me_with_token(&tokens).await, Status::Unauthorized);
+    assert_eq!(server.refresh(&tokens).await, Status::Unauthorized);
+    let (status, _) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let event: (String, String, Option<String>, Option<String>) = sqlx::query_as(
+        "SELECT actor, target, field, ip FROM audit_log WHERE action = 'user.update'",
+    )
+    .fetch_one(&server.pool().await)
+    .await
+    .unwrap();
+    assert_eq!(
+        event,
+        (
+            "alice".to_string(),
+            "alice".to_string(),
+            Some("password".to_string()),
+            Some("127.0.0.1".to_string())
+        )
+    );
+}
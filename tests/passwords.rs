@@ -0,0 +1,94 @@
+//! Password hashes, recomputed on login once the Argon2 parameters change.
+
+mod common;
+
+use api_server::{
+    secure::hash_pass::PasswordHasher,
+    server::config::{Argon2Config, Settings},
+};
+use common::{create_legacy_database, TestServer, PASSWORD};
+use rocket::http::Status;
+
+impl TestServer {
+    /// The stored password hash of `user_id`.
+    async fn password_hash(&self, user_id: &str) -> String {
+        sqlx::query_scalar("SELECT password FROM users WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_one(&self.pool().await)
+            .await
+            .unwrap()
+    }
+}
+
+fn argon2(mem_cost: u32) -> Argon2Config {
+    Argon2Config {
+        mem_cost,
+        time_cost: 1,
+        lanes: 1,
+    }
+}
+
+#[rocket::async_test]
+async fn tells_which_hashes_need_rehashing() {
+    let secret_key = Settings::default().server.secret_key;
+    let hasher = PasswordHasher::new(secret_key.to_owned(), argon2(4096));
+    let hash = hasher.hash(PASSWORD).unwrap();
+    assert!(hash.contains("$m=4096,t=1,p=1$"));
+    assert!(hasher.verify(PASSWORD, &hash).unwrap());
+    assert!(!hasher.needs_rehash(&hash));
+
+    let stronger = PasswordHasher::new(secret_key.to_owned(), argon2(8192));
+    assert!(stronger.verify(PASSWORD, &hash).unwrap());
+    assert!(stronger.needs_rehash(&hash));
+    assert!(!stronger.needs_rehash(&stronger.hash(PASSWORD).unwrap()));
+
+    // The pepper is mixed into the hash
+    let other_pepper = PasswordHasher::new("another secret key".to_string(), argon2(4096));
+    assert!(!other_pepper.verify(PASSWORD, &hash).unwrap());
+
+    for hash in [
+        "",
+        "plaintext",
+        "$argon2i$v=19$m=4096,t=1,p=1$c2FsdHNhbHQ$aGFzaA",
+    ] {
+        assert!(hasher.needs_rehash(hash), "{hash}");
+    }
+}
+
+#[rocket::async_test]
+async fn rehashes_passwords_on_login() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret_key = Settings::default().server.secret_key;
+    create_legacy_database(dir.path(), &secret_key, &[("old", "plaintext-key-of-old")]).await;
+    let server =
+        TestServer::start_in(dir.path(), |settings| settings.server.argon2 = argon2(4096)).await;
+    let hasher = PasswordHasher::new(secret_key.to_owned(), argon2(4096));
+    let legacy = server.password_hash("old").await;
+    let hash = server.password_hash("alice").await;
+    assert!(hasher.needs_rehash(&legacy));
+    assert!(!hasher.needs_rehash(&hash));
+
+    // Legacy hashes, salted with the secret key, are replaced on the next login
+    assert_eq!(
+        server.login("old", "Wrong#123").await.0,
+        Status::Unauthorized
+    );
+    assert_eq!(server.password_hash("old").await, legacy);
+    assert_eq!(server.login("old", PASSWORD).await.0, Status::Ok);
+    let rehashed = server.password_hash("old").await;
+    assert_ne!(rehashed, legacy);
+    assert!(!hasher.needs_rehash(&rehashed));
+
+    // Hashes up to date are kept
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+    assert_eq!(server.password_hash("alice").await, hash);
+
+    // As are the others once the parameters change
+    let server =
+        TestServer::start_in(dir.path(), |settings| settings.server.argon2 = argon2(8192)).await;
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+    let rehashed = server.password_hash("alice").await;
+    assert!(rehashed.contains("$m=8192,t=1,p=1$"));
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+    assert_eq!(server.password_hash("alice").await, rehashed);
+}
@@ -0,0 +1,77 @@
+//! Errors reported as RFC 7807 problems, without the details of server errors outside
+//! of the debug profile.
+
+mod common;
+
+use api_server::{server::build_server, storage};
+use common::{json, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+    serde::json::Value,
+    Config,
+};
+
+impl TestServer {
+    /// Starts another server on the same database and storage, with the release profile.
+    async fn release_client(&self) -> Client {
+        let storage = storage::connect(&self.settings.server).await.unwrap();
+        let rocket = build_server(self.settings.clone(), self.backend().await, storage);
+        let figment = rocket.figment().clone().select(Config::RELEASE_PROFILE);
+        Client::tracked(rocket.configure(figment)).await.unwrap()
+    }
+}
+
+/// Gets the current user as `api_key`, checking that the response is a problem.
+async fn get_me(client: &Client, api_key: &str) -> (Status, Value) {
+    let response = client
+        .get("/me")
+        .header(Header::new("X-API-KEY", api_key.to_string()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("application", "problem+json"))
+    );
+    let request_id = response
+        .headers()
+        .get_one("X-Request-Id")
+        .unwrap()
+        .to_string();
+    let (status, problem) = json(response).await;
+    assert_eq!(problem["status"], status.code);
+    assert_eq!(problem["instance"], "/me");
+    assert_eq!(problem["request_id"], request_id.as_str());
+    (status, problem)
+}
+
+#[rocket::async_test]
+async fn hides_server_errors_outside_debug() {
+    let server = TestServer::start().await;
+    let release = server.release_client().await;
+    assert_eq!(release.rocket().config().profile, Config::RELEASE_PROFILE);
+
+    // Client errors are explained whatever the profile
+    for client in [&server.client, &release] {
+        let (status, problem) = get_me(client, "unknown").await;
+        assert_eq!(status, Status::Unauthorized);
+        assert_eq!(problem["code"], "UNAUTHENTICATED");
+        assert_eq!(problem["detail"], "Unauthenticated user");
+    }
+
+    // The database error behind a server error is only shown while debugging
+    sqlx::query("DROP TABLE api_keys")
+        .execute(&server.pool().await)
+        .await
+        .unwrap();
+    let (status, debug) = get_me(&server.client, &server.api_key).await;
+    assert_eq!(status, Status::InternalServerError);
+    assert!(debug["detail"].as_str().unwrap().contains("api_keys"));
+
+    let (status, problem) = get_me(&release, &server.api_key).await;
+    assert_eq!(status, Status::InternalServerError);
+    assert_eq!(problem["code"], debug["code"]);
+    assert_eq!(problem["title"], "Internal Server Error");
+    assert!(problem.get("detail").is_none(), "{problem}");
+}
@@ -0,0 +1,265 @@
+//! Resumable uploads through the tus protocol, interrupted, resumed after a restart
+//! of the server, cancelled and expired.
+
+mod common;
+
+use common::{header, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::LocalResponse,
+};
+use sha2::{Digest, Sha256};
+
+impl TestServer {
+    async fn create(&self, length: usize, metadata: &str) -> LocalResponse<'_> {
+        self.client
+            .post("/uploads")
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("Upload-Length", length.to_string()))
+            .header(Header::new("Upload-Metadata", metadata.to_string()))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+    }
+
+    /// Creates an upload named `notes.txt`, returning its URL.
+    async fn create_notes(&self, length: usize) -> String {
+        // "notes.txt" and "text/plain"
+        let response = self
+            .create(length, "filename bm90ZXMudHh0,filetype dGV4dC9wbGFpbg==")
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        header(&response, "Location")
+    }
+
+    async fn head(&self, url: &str) -> LocalResponse<'_> {
+        self.client
+            .head(url.to_string())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+    }
+
+    async fn patch(&self, url: &str, offset: usize, chunk: &[u8]) -> LocalResponse<'_> {
+        self.client
+            .patch(url.to_string())
+            .header(ContentType::new("application", "offset+octet-stream"))
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("Upload-Offset", offset.to_string()))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(chunk)
+            .dispatch()
+            .await
+    }
+
+    async fn delete(&self, url: &str) -> LocalResponse<'_> {
+        self.client
+            .delete(url.to_string())
+            .header(Header::new("Tus-Resumable", "1.0.0"))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+    }
+
+    async fn download(&self, file_id: &str) -> (Status, Option<String>, Vec<u8>) {
+        let response = self
+            .client
+            .get(format!("/download/{}", file_id))
+            .header(Header::new("X-API-KEY", self.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+
+        let status = response.status();
+        let content_type = response.content_type().map(|c| c.to_string());
+        (status, content_type, response.into_bytes().await.unwrap())
+    }
+}
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[rocket::async_test]
+async fn advertises_capabilities() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |settings| {
+        settings.server.max_upload_size = 1000;
+    })
+    .await;
+
+    let response = server.client.options("/uploads").dispatch().await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(header(&response, "Tus-Version"), "1.0.0");
+    assert_eq!(
+        header(&response, "Tus-Extension"),
+        "creation,expiration,termination"
+    );
+    assert_eq!(header(&response, "Tus-Max-Size"), "1000");
+
+    let response = server.create(1001, "").await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    assert_eq!(header(&response, "Tus-Resumable"), "1.0.0");
+}
+
+#[rocket::async_test]
+async fn uploads_in_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let data = content(10_000);
+
+    let url = server.create_notes(data.len()).await;
+    let response = server.head(&url).await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(header(&response, "Upload-Offset"), "0");
+    assert_eq!(header(&response, "Upload-Length"), "10000");
+    assert_eq!(header(&response, "Cache-Control"), "no-store");
+
+    let response = server.patch(&url, 0, &data[..4000]).await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(header(&response, "Upload-Offset"), "4000");
+    assert!(response.headers().get_one("X-File-Id").is_none());
+
+    // Chunks sent again or skipping ahead are rejected
+    for offset in [0, 3999, 4001] {
+        let response = server.patch(&url, offset, &data[offset..]).await;
+        assert_eq!(response.status(), Status::Conflict, "{offset}");
+    }
+
+    let response = server.patch(&url, 4000, &data[4000..]).await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(header(&response, "Upload-Offset"), "10000");
+    let file_id = header(&response, "X-File-Id");
+
+    let response = server.head(&url).await;
+    assert_eq!(header(&response, "Upload-Offset"), "10000");
+    assert_eq!(header(&response, "X-File-Id"), file_id);
+
+    let (status, content_type, downloaded) = server.download(&file_id).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type.as_deref(), Some("text/plain"));
+    assert_eq!(downloaded, data);
+}
+
+#[rocket::async_test]
+async fn resumes_after_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = content(5000);
+
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let url = server.create_notes(data.len()).await;
+    let status = server.patch(&url, 0, &data[..1234]).await.status();
+    assert_eq!(status, Status::NoContent);
+    drop(server);
+
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let response = server.head(&url).await;
+    assert_eq!(header(&response, "Upload-Offset"), "1234");
+    let response = server.patch(&url, 1234, &data[1234..]).await;
+    assert_eq!(response.status(), Status::NoContent);
+
+    let file_id = header(&response, "X-File-Id");
+    let (_, _, downloaded) = server.download(&file_id).await;
+    assert_eq!(
+        hex::encode(Sha256::digest(&downloaded)),
+        hex::encode(Sha256::digest(&data))
+    );
+}
+
+#[rocket::async_test]
+async fn rejects_invalid_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let url = server.create_notes(100).await;
+
+    let response = server
+        .client
+        .patch(url.to_owned())
+        .header(ContentType::new("application", "offset+octet-stream"))
+        .header(Header::new("Upload-Offset", "0"))
+        .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .body("no version")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::PreconditionFailed);
+    assert_eq!(header(&response, "Tus-Version"), "1.0.0");
+
+    let response = server
+        .client
+        .patch(url.to_owned())
+        .header(ContentType::Binary)
+        .header(Header::new("Tus-Resumable", "1.0.0"))
+        .header(Header::new("Upload-Offset", "0"))
+        .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .body("wrong type")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+    let response = server.patch(&url, 0, &content(101)).await;
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let response = server.head(&url).await;
+    assert_eq!(header(&response, "Upload-Offset"), "0");
+
+    let response = server.create(10, "filename Li4vZXZpbA==").await;
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = server.create(10, "filename !!!").await;
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn terminates_uploads() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let url = server.create_notes(100).await;
+    server.patch(&url, 0, &content(50)).await;
+
+    let response = server.delete(&url).await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert_eq!(server.head(&url).await.status(), Status::NotFound);
+    assert_eq!(server.delete(&url).await.status(), Status::NotFound);
+
+    let partial_dir = dir.path().join("store").join("uploads");
+    assert_eq!(std::fs::read_dir(partial_dir).unwrap().count(), 0);
+}
+
+#[rocket::async_test]
+async fn expires_uploads() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |settings| {
+        settings.server.upload_ttl = 0;
+    })
+    .await;
+
+    let url = server.create_notes(100).await;
+    assert_eq!(server.head(&url).await.status(), Status::Gone);
+    assert_eq!(server.patch(&url, 0, b"late").await.status(), Status::Gone);
+
+    // Expired uploads are cleaned up when the next one is created
+    server.create_notes(100).await;
+    assert_eq!(server.head(&url).await.status(), Status::NotFound);
+    let partial_dir = dir.path().join("store").join("uploads");
+    assert_eq!(std::fs::read_dir(partial_dir).unwrap().count(), 1);
+}
+
+#[rocket::async_test]
+async fn completes_empty_uploads_on_creation() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+
+    let response = server.create(0, "filename ZW1wdHk=").await;
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(header(&response, "Upload-Offset"), "0");
+    let file_id = header(&response, "X-File-Id");
+
+    let (status, _, downloaded) = server.download(&file_id).await;
+    assert_eq!(status, Status::Ok);
+    assert!(downloaded.is_empty());
+}
@@ -0,0 +1,138 @@
+//! Roles of users: only admins manage other users, and `read_only` users cannot
+//! change anything.
+
+mod common;
+
+use api_server::{models::Role, server::config::Settings};
+use common::{create_legacy_database, TestServer, PASSWORD};
+use rocket::{
+    http::{Method, Status},
+    serde::json::{serde_json::json, Value},
+};
+
+/// Requests only admins can make, about `bob`
+fn admin_requests() -> Vec<(Method, &'static str, Option<Value>)> {
+    vec![
+        (Method::Get, "/users", None),
+        (
+            Method::Post,
+            "/users",
+            Some(
+                json!({ "user_id": "carol", "password": PASSWORD, "email_id": "carol@example.com" }),
+            ),
+        ),
+        (Method::Put, "/users/bob", Some(json!({ "role": "admin" }))),
+        (
+            Method::Put,
+            "/users/bob/quota",
+            Some(json!({ "max_bytes": 1 })),
+        ),
+        (Method::Delete, "/users/bob", None),
+        (Method::Post, "/users/bob/restore", None),
+        (Method::Delete, "/users/bob/purge", None),
+        (Method::Get, "/audit", None),
+    ]
+}
+
+#[rocket::async_test]
+async fn restricts_admin_endpoints() {
+    let server = TestServer::start().await;
+    server.create_user("bob", Role::User).await;
+    let reader_key = server.create_user("reader", Role::ReadOnly).await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    for api_key in [&server.api_key, &reader_key] {
+        for (method, uri, body) in admin_requests() {
+            let (status, problem) = server.send(method, uri, api_key, body).await;
+            assert_eq!(status, Status::Forbidden, "{method} {uri}");
+            assert_eq!(problem["code"], "FORBIDDEN", "{method} {uri}");
+        }
+        // Nor can they act on the account of another user
+        let (status, _) = server
+            .send(
+                Method::Put,
+                "/users/bob",
+                api_key,
+                Some(json!({ "password": "Another#123" })),
+            )
+            .await;
+        assert_eq!(status, Status::Forbidden);
+        let (status, _) = server.send(Method::Get, "/users/bob", api_key, None).await;
+        assert_eq!(status, Status::Forbidden);
+    }
+    // Even to make themselves admins
+    let (status, _) = server
+        .send(
+            Method::Put,
+            "/users/alice",
+            &server.api_key,
+            Some(json!({ "role": "admin" })),
+        )
+        .await;
+    assert_eq!(status, Status::Forbidden);
+
+    for (method, uri, body) in admin_requests() {
+        let (status, body) = server.send(method, uri, &admin_key, body).await;
+        assert!(status.class().is_success(), "{method} {uri}: {body}");
+    }
+}
+
+#[rocket::async_test]
+async fn keeps_read_only_users_from_writing() {
+    let server = TestServer::start().await;
+    let reader_key = server.create_user("reader", Role::ReadOnly).await;
+
+    for (method, uri, body) in [
+        (
+            Method::Put,
+            "/users/reader",
+            Some(json!({ "password": "Another#123" })),
+        ),
+        (
+            Method::Patch,
+            "/me",
+            Some(json!({ "current_password": PASSWORD, "new_password": "Another#123" })),
+        ),
+        (Method::Delete, "/me", None),
+        (
+            Method::Post,
+            "/users/reader/keys",
+            Some(json!({ "label": "ci" })),
+        ),
+        (Method::Post, "/upload", None),
+    ] {
+        let (status, problem) = server.send(method, uri, &reader_key, body).await;
+        assert_eq!(status, Status::Forbidden, "{method} {uri}");
+        assert_eq!(problem["code"], "FORBIDDEN", "{method} {uri}");
+    }
+
+    // Reading is allowed
+    let (status, me) = server.send(Method::Get, "/me", &reader_key, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["role"], "read_only");
+    for uri in ["/users/reader", "/users/reader/keys", "/files", "/me/usage"] {
+        let (status, _) = server.send(Method::Get, uri, &reader_key, None).await;
+        assert_eq!(status, Status::Ok, "{uri}");
+    }
+}
+
+#[rocket::async_test]
+async fn keeps_existing_users_regular_users() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret_key = Settings::default().server.secret_key;
+    create_legacy_database(dir.path(), &secret_key, &[("old", "plaintext-key-of-old")]).await;
+
+    // Adding roles does not make anyone an admin
+    let server = TestServer::start_in(dir.path(), |_| {}).await;
+    let user = server
+        .backend()
+        .await
+        .get_user_with_id("old")
+        .await
+        .unwrap();
+    assert_eq!(user.role, Role::User);
+    let (status, _) = server
+        .send(Method::Get, "/users", &server.api_key, None)
+        .await;
+    assert_eq!(status, Status::Forbidden);
+}
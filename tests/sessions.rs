@@ -0,0 +1,165 @@
+//! Login sessions: short-lived access tokens, refreshed with single-use refresh tokens
+//! until logout.
+
+mod common;
+
+use api_server::server::config::Argon2Config;
+use common::{json, TestServer, CLIENT_ADDR, PASSWORD};
+use rocket::{
+    http::{ContentType, Header, Status},
+    serde::json::{serde_json, Value},
+};
+use std::time::{Duration, Instant};
+
+impl TestServer {
+    /// Gets the current user with `access_token` as bearer.
+    async fn me(&self, access_token: &str) -> (Status, Value) {
+        let response = self
+            .client
+            .get("/me")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", access_token),
+            ))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> (Status, Value) {
+        let response = self
+            .client
+            .post("/auth/refresh")
+            .header(ContentType::JSON)
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(serde_json::json!({ "refresh_token": refresh_token }).to_string())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    async fn logout(&self, access_token: &str) -> Status {
+        self.client
+            .post("/auth/logout")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", access_token),
+            ))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+            .status()
+    }
+}
+
+fn token<'a>(tokens: &'a Value, name: &str) -> &'a str {
+    tokens[name].as_str().unwrap()
+}
+
+#[rocket::async_test]
+async fn logs_in_with_a_password() {
+    let server = TestServer::start().await;
+
+    for (user_id, password) in [("alice", "Wrong#123"), ("nobody", PASSWORD)] {
+        let (status, problem) = server.login(user_id, password).await;
+        assert_eq!(status, Status::Unauthorized, "{user_id}");
+        assert_eq!(problem["code"], "UNAUTHENTICATED");
+    }
+
+    let (status, tokens) = server.login("alice", PASSWORD).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(tokens["token_type"], "Bearer");
+    let (status, me) = server.me(token(&tokens, "access_token")).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "alice");
+
+    // Tampered tokens are rejected
+    let tampered = token(&tokens, "access_token").replacen('.', "x.", 1);
+    assert_eq!(server.me(&tampered).await.0, Status::Unauthorized);
+    assert_eq!(server.me("").await.0, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn hides_which_users_exist() {
+    // Costly enough for hashing to stand out of the rest of the request
+    let server = TestServer::start_with(|settings| {
+        settings.server.argon2 = Argon2Config {
+            mem_cost: 16384,
+            time_cost: 2,
+            lanes: 1,
+        }
+    })
+    .await;
+    let time_login = |user_id: &'static str| async {
+        let mut fastest = Duration::MAX;
+        for _ in 0..3 {
+            let start = Instant::now();
+            let (status, _) = server.login(user_id, "Wrong#123").await;
+            assert_eq!(status, Status::Unauthorized);
+            fastest = fastest.min(start.elapsed());
+        }
+        fastest
+    };
+
+    // Unknown users are hashed against too
+    let known = time_login("alice").await;
+    let unknown = time_login("nobody").await;
+    assert!(unknown * 3 > known, "{unknown:?} against {known:?}");
+
+    // Hashes that cannot be used do not fail the server
+    sqlx::query("UPDATE users SET password = 'not a hash' WHERE user_id = 'alice'")
+        .execute(&server.pool().await)
+        .await
+        .unwrap();
+    let (status, problem) = server.login("alice", PASSWORD).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "UNAUTHENTICATED");
+}
+
+#[rocket::async_test]
+async fn uses_refresh_tokens_once() {
+    let server = TestServer::start().await;
+    let (_, tokens) = server.login("alice", PASSWORD).await;
+
+    let (status, refreshed) = server.refresh(token(&tokens, "refresh_token")).await;
+    assert_eq!(status, Status::Ok);
+    assert_ne!(
+        token(&refreshed, "refresh_token"),
+        token(&tokens, "refresh_token")
+    );
+    assert_eq!(
+        server.me(token(&refreshed, "access_token")).await.0,
+        Status::Ok
+    );
+
+    // Replaying a refresh token fails, the new one still works
+    let (status, problem) = server.refresh(token(&tokens, "refresh_token")).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "UNAUTHENTICATED");
+    let (status, _) = server.refresh(token(&refreshed, "refresh_token")).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(server.refresh("unknown").await.0, Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn closes_sessions_on_logout() {
+    let server = TestServer::start().await;
+    let (_, tokens) = server.login("alice", PASSWORD).await;
+    let (_, other) = server.login("alice", PASSWORD).await;
+    let access_token = token(&tokens, "access_token");
+
+    assert_eq!(server.logout(access_token).await, Status::Ok);
+    // Neither token of the session can be used any more
+    let (status, problem) = server.me(access_token).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "UNAUTHENTICATED");
+    assert_eq!(
+        server.refresh(token(&tokens, "refresh_token")).await.0,
+        Status::Unauthorized
+    );
+    assert_eq!(server.logout(access_token).await, Status::Unauthorized);
+
+    // Other sessions are left open
+    assert_eq!(server.me(token(&other, "access_token")).await.0, Status::Ok);
+}
@@ -0,0 +1,133 @@
+//! The object stores keeping the content of files: a local directory, and an S3
+//! bucket when built with the `s3` feature. The S3 test is ignored by default, run
+//! it against a MinIO server with:
+//!
+//! `S3_TEST_BUCKET=test S3_TEST_ENDPOINT=http://localhost:9000 cargo test --features s3 --test storage -- --ignored`
+//!
+//! with the credentials in `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+
+use api_server::storage::{LocalStorage, ObjectMeta, Storage};
+
+async fn put(storage: &dyn Storage, key: &str, content: &[u8]) {
+    let size = storage.put(key, &mut &content[..]).await.unwrap();
+    assert_eq!(size, content.len() as u64);
+}
+
+fn objects(keys: &[(&str, u64)]) -> Vec<ObjectMeta> {
+    keys.iter()
+        .map(|(key, size)| ObjectMeta {
+            key: key.to_string(),
+            size: *size,
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+async fn keeps_local_keys_inside_the_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("root");
+    let storage = LocalStorage::new(&root);
+    let outside = dir.path().join("outside");
+
+    for key in [
+        "",
+        "..",
+        "../outside",
+        "a/../../outside",
+        "./a",
+        outside.to_str().unwrap(),
+        "/etc/passwd",
+    ] {
+        let err = storage.put(key, &mut &b"x"[..]).await.unwrap_err();
+        assert!(err.to_string().contains("Invalid key"), "{key}: {err}");
+        assert!(storage.get(key).await.is_err(), "{key}");
+        assert!(storage.get_range(key, 0..1).await.is_err(), "{key}");
+        assert!(storage.head(key).await.is_err(), "{key}");
+        assert!(storage.delete(key).await.is_err(), "{key}");
+    }
+    assert!(!outside.exists());
+    assert!(!root.exists());
+}
+
+#[rocket::async_test]
+async fn lists_local_keys_by_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = LocalStorage::new(dir.path());
+    for key in ["alice/a.txt", "alice/b/c.txt", "alicex/d.txt", "bob/e.txt"] {
+        put(&storage, key, key.as_bytes()).await;
+    }
+
+    assert_eq!(
+        storage.list("alice/").await.unwrap(),
+        objects(&[("alice/a.txt", 11), ("alice/b/c.txt", 13)])
+    );
+    assert_eq!(
+        storage.list("alice").await.unwrap(),
+        objects(&[
+            ("alice/a.txt", 11),
+            ("alice/b/c.txt", 13),
+            ("alicex/d.txt", 12)
+        ])
+    );
+    assert_eq!(
+        storage.list("alice/b").await.unwrap(),
+        objects(&[("alice/b/c.txt", 13)])
+    );
+    assert_eq!(storage.list("").await.unwrap().len(), 4);
+    assert_eq!(storage.list("carol/").await.unwrap(), []);
+
+    storage.delete("alice/a.txt").await.unwrap();
+    assert_eq!(
+        storage.list("alice/").await.unwrap(),
+        objects(&[("alice/b/c.txt", 13)])
+    );
+}
+
+#[cfg(feature = "s3")]
+#[rocket::async_test]
+#[ignore = "needs S3_TEST_BUCKET and S3_TEST_ENDPOINT"]
+async fn round_trips_s3_objects() {
+    use api_server::{server::config::S3Config, storage::S3Storage};
+    use rocket::tokio::io::AsyncReadExt;
+
+    let storage = S3Storage::new(&S3Config {
+        bucket: std::env::var("S3_TEST_BUCKET").expect("S3_TEST_BUCKET must be set"),
+        region: "us-east-1".to_string(),
+        endpoint: std::env::var("S3_TEST_ENDPOINT").ok(),
+        path_style: true,
+        access_key_id: None,
+        secret_access_key: None,
+    })
+    .await;
+    let prefix = format!("test-{}/", rand::random::<u32>());
+    let key = format!("{}alice/notes.txt", prefix);
+    put(&storage, &key, b"some notes").await;
+    put(&storage, &format!("{}bob/other.txt", prefix), b"other").await;
+
+    assert_eq!(storage.head(&key).await.unwrap().size, 10);
+    let (meta, mut reader) = storage.get(&key).await.unwrap();
+    assert_eq!(meta.size, 10);
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content).await.unwrap();
+    assert_eq!(content, b"some notes");
+    let mut range = Vec::new();
+    storage
+        .get_range(&key, 5..10)
+        .await
+        .unwrap()
+        .read_to_end(&mut range)
+        .await
+        .unwrap();
+    assert_eq!(range, b"notes");
+    assert_eq!(
+        storage.list(&format!("{}alice/", prefix)).await.unwrap(),
+        objects(&[(&key, 10)])
+    );
+
+    for object in storage.list(&prefix).await.unwrap() {
+        storage.delete(&object.key).await.unwrap();
+    }
+    assert!(storage.get(&key).await.is_err());
+    assert!(storage.head(&key).await.is_err());
+    assert_eq!(storage.list(&prefix).await.unwrap(), []);
+}
@@ -0,0 +1,273 @@
+//! Storage quotas of users and of the server, enforced on uploads at once and on
+//! resumable uploads, and adjusted by admins.
+
+mod common;
+
+use api_server::models::Role;
+use common::{json, TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    serde::json::{serde_json, Value},
+};
+use std::time::Duration;
+
+impl TestServer {
+    /// Uploads `size` bytes at once as `alice`, returning the status and the problem
+    /// or file.
+    async fn upload_bytes(&self, size: usize) -> (Status, Value) {
+        self.upload("data.bin", "application/octet-stream", &vec![b'x'; size])
+            .await
+    }
+
+    async fn usage(&self, uri: &str, api_key: &str) -> (Status, Value) {
+        let response = self
+            .client
+            .get(uri.to_string())
+            .header(Header::new("X-API-KEY", api_key.to_string()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+
+    async fn set_quota(&self, api_key: &str, quota: Value) -> (Status, Value) {
+        let response = self
+            .client
+            .put("/users/alice/quota")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-KEY", api_key.to_string()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(quota.to_string())
+            .dispatch()
+            .await;
+        json(response).await
+    }
+}
+
+#[rocket::async_test]
+async fn enforces_user_quotas() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.user_bytes = Some(100);
+        settings.server.quota.user_files = Some(2);
+    })
+    .await;
+
+    assert_eq!(server.upload_bytes(60).await.0, Status::Ok);
+    let (status, problem) = server.upload_bytes(50).await;
+    assert_eq!(status, Status::InsufficientStorage);
+    assert_eq!(problem["code"], "QUOTA_EXCEEDED");
+    assert_eq!(server.stored_files().len(), 1);
+
+    assert_eq!(server.upload_bytes(40).await.0, Status::Ok);
+    let (status, problem) = server.upload_bytes(0).await;
+    assert_eq!(status, Status::InsufficientStorage);
+    assert!(problem["detail"].as_str().unwrap().contains("2 files"));
+
+    let (status, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        usage,
+        serde_json::json!({ "bytes": 100, "files": 2, "max_bytes": 100, "max_files": 2 })
+    );
+}
+
+#[rocket::async_test]
+async fn aborts_oversized_uploads() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.max_upload_size = 1000;
+        settings.server.quota.total_bytes = Some(1500);
+    })
+    .await;
+
+    let (status, problem) = server.upload_bytes(1_000_000).await;
+    assert_eq!(status, Status::PayloadTooLarge);
+    assert_eq!(problem["code"], "UPLOAD_TOO_LARGE");
+
+    // The server quota is shared by all users
+    assert_eq!(server.upload_bytes(1000).await.0, Status::Ok);
+    let (status, problem) = server.upload_bytes(501).await;
+    assert_eq!(status, Status::InsufficientStorage);
+    assert!(problem["detail"].as_str().unwrap().contains("server"));
+    assert_eq!(server.upload_bytes(500).await.0, Status::Ok);
+    assert_eq!(server.stored_files().len(), 2);
+
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 1500);
+    assert_eq!(usage["max_bytes"], Value::Null);
+}
+
+#[rocket::async_test]
+async fn enforces_server_file_quotas() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.total_files = Some(2);
+    })
+    .await;
+
+    // Uploads in progress count as files
+    assert_eq!(server.upload_bytes(10).await.0, Status::Ok);
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "10")], &[])
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let url = response.headers().get_one("Location").unwrap().to_string();
+    let (status, problem) = server.upload_bytes(10).await;
+    assert_eq!(status, Status::InsufficientStorage);
+    assert_eq!(problem["code"], "QUOTA_EXCEEDED");
+    assert!(problem["detail"]
+        .as_str()
+        .unwrap()
+        .contains("2 files on the server"));
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "10")], &[])
+        .await;
+    assert_eq!(response.status(), Status::InsufficientStorage);
+
+    assert_eq!(
+        server.tus(Method::Delete, &url, &[], &[]).await.status(),
+        Status::NoContent
+    );
+    assert_eq!(server.upload_bytes(10).await.0, Status::Ok);
+    assert_eq!(server.stored_files().len(), 2);
+}
+
+#[rocket::async_test]
+async fn adjusts_user_quotas() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.user_bytes = Some(10);
+        settings.server.quota.user_files = Some(5);
+    })
+    .await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+    assert_eq!(server.upload_bytes(20).await.0, Status::InsufficientStorage);
+
+    let (status, _) = server
+        .set_quota(&server.api_key, serde_json::json!({ "max_bytes": 1000 }))
+        .await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = server
+        .set_quota(&admin_key, serde_json::json!({ "max_bytes": -1 }))
+        .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+
+    let (status, usage) = server
+        .set_quota(
+            &admin_key,
+            serde_json::json!({ "max_bytes": 1000, "max_files": null }),
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(usage["max_bytes"], 1000);
+    // Unset limits are the server defaults
+    assert_eq!(usage["max_files"], 5);
+    assert_eq!(server.upload_bytes(20).await.0, Status::Ok);
+
+    let (status, usage) = server.usage("/users/alice/usage", &admin_key).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(usage["bytes"], 20);
+    let (status, _) = server.usage("/users/root/usage", &server.api_key).await;
+    assert_eq!(status, Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn reserves_resumable_uploads() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.user_bytes = Some(100);
+        settings.server.upload_ttl = 2;
+    })
+    .await;
+
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "101")], &[])
+        .await;
+    assert_eq!(response.status(), Status::InsufficientStorage);
+
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "60")], &[])
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let url = response.headers().get_one("Location").unwrap().to_string();
+
+    // The full length is taken from the start, before any content is received
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 60);
+    assert_eq!(usage["files"], 1);
+    assert_eq!(server.upload_bytes(50).await.0, Status::InsufficientStorage);
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "50")], &[])
+        .await;
+    assert_eq!(response.status(), Status::InsufficientStorage);
+
+    // Released once deleted
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "40")], &[])
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let other = response.headers().get_one("Location").unwrap().to_string();
+    assert_eq!(
+        server.tus(Method::Delete, &other, &[], &[]).await.status(),
+        Status::NoContent
+    );
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 60);
+
+    // The reserved room is there to complete the upload
+    let response = server
+        .tus(Method::Patch, &url, &[("Upload-Offset", "0")], &[b'x'; 60])
+        .await;
+    assert_eq!(response.status(), Status::NoContent);
+    assert!(response.headers().get_one("X-File-Id").is_some());
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 60);
+    assert_eq!(usage["files"], 1);
+
+    // And released once expired
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "40")], &[])
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 100);
+    rocket::tokio::time::sleep(Duration::from_millis(2100)).await;
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 60);
+    assert_eq!(usage["files"], 1);
+}
+
+#[rocket::async_test]
+async fn enforces_quotas_on_concurrent_uploads() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.user_bytes = Some(100);
+    })
+    .await;
+
+    // Both fit on their own, not together
+    let (first, second) = rocket::tokio::join!(
+        server.tus(Method::Post, "/uploads", &[("Upload-Length", "60")], &[]),
+        server.tus(Method::Post, "/uploads", &[("Upload-Length", "60")], &[]),
+    );
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort_by_key(|status| status.code);
+    assert_eq!(statuses, [Status::Created, Status::InsufficientStorage]);
+
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.total_bytes = Some(100);
+    })
+    .await;
+    let (first, second) = rocket::tokio::join!(server.upload_bytes(60), server.upload_bytes(60));
+    let mut statuses = [first.0, second.0];
+    statuses.sort_by_key(|status| status.code);
+    assert_eq!(statuses, [Status::Ok, Status::InsufficientStorage]);
+    assert_eq!(server.stored_files().len(), 1);
+    let (_, usage) = server.usage("/me/usage", &server.api_key).await;
+    assert_eq!(usage["bytes"], 60);
+
+    let server = TestServer::start_with(|settings| {
+        settings.server.quota.total_files = Some(1);
+    })
+    .await;
+    let (first, second) = rocket::tokio::join!(server.upload_bytes(10), server.upload_bytes(10));
+    let mut statuses = [first.0, second.0];
+    statuses.sort_by_key(|status| status.code);
+    assert_eq!(statuses, [Status::Ok, Status::InsufficientStorage]);
+    assert_eq!(server.stored_files().len(), 1);
+}
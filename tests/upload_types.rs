@@ -0,0 +1,321 @@
+//! Types of uploaded files detected from their content, checked against their names
+//! and the allowed types, and sent back on download.
+
+mod common;
+
+use api_server::server::config::ContentTypeConfig;
+use common::{TestServer, CLIENT_ADDR};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    serde::json::{serde_json, Value},
+};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+const PDF: &[u8] = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n1 0 obj\n<< >>\nendobj\n";
+const SVG: &[u8] = b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>\n";
+const HTML: &[u8] = b"<!DOCTYPE html>\n<script>alert(document.cookie)</script>\n";
+const XHTML: &[u8] =
+    b"<html xmlns=\"http://www.w3.org/1999/xhtml\"><script>alert(1)</script></html>";
+
+/// The start of an x86-64 executable
+fn elf() -> Vec<u8> {
+    let mut elf = b"\x7fELF\x02\x01\x01\0".to_vec();
+    elf.resize(64, 0);
+    elf
+}
+
+/// Starts a server allowing the types of `content_types`.
+async fn start(content_types: ContentTypeConfig) -> TestServer {
+    TestServer::start_with(|settings| settings.server.content_types = content_types).await
+}
+
+fn patterns(patterns: &[&str]) -> Vec<String> {
+    patterns.iter().map(ToString::to_string).collect()
+}
+
+#[rocket::async_test]
+async fn records_detected_types() {
+    // Active content included
+    let server = start(ContentTypeConfig {
+        allowed: Vec::new(),
+        denied: Vec::new(),
+    })
+    .await;
+
+    for (file_name, content_type, content, detected, inline) in [
+        ("dot.png", "image/png", PNG, "image/png", true),
+        // Detected rather than trusted from the client
+        ("dot", "application/octet-stream", PNG, "image/png", true),
+        (
+            "dot.PNG",
+            "application/octet-stream",
+            PNG,
+            "image/png",
+            true,
+        ),
+        (
+            "manual.pdf",
+            "application/pdf",
+            PDF,
+            "application/pdf",
+            true,
+        ),
+        // Text is taken to be what its name says, and only displayed if plain
+        ("logo.svg", "image/svg+xml", SVG, "image/svg+xml", false),
+        ("page.html", "text/html", HTML, "text/html", false),
+        ("notes.txt", "text/plain", b"plain text", "text/plain", true),
+        (
+            "notes",
+            "application/octet-stream",
+            b"",
+            "application/octet-stream",
+            false,
+        ),
+    ] {
+        let (status, file) = server.upload(file_name, content_type, content).await;
+        assert_eq!(status, Status::Ok, "{file_name}: {file}");
+        assert_eq!(file["content_type"], detected, "{file_name}");
+
+        let response = server
+            .client
+            .get(format!("/download/{}", file["id"]))
+            .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await;
+        assert_eq!(
+            response.headers().get_one("Content-Type"),
+            Some(detected),
+            "{file_name}"
+        );
+        assert_eq!(
+            response.headers().get_one("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            (!inline).then_some("attachment"),
+            "{file_name}"
+        );
+        assert_eq!(response.into_bytes().await.unwrap(), content);
+    }
+}
+
+#[rocket::async_test]
+async fn rejects_mismatched_types() {
+    let server = start(ContentTypeConfig::default()).await;
+
+    for (file_name, content_type, content) in [
+        ("cat.png", "image/png", elf()),
+        ("cat.png", "application/octet-stream", elf()),
+        ("notes.txt", "text/plain", elf()),
+        ("notes", "text/plain", elf()),
+        ("fake.png", "image/png", b"plain text".to_vec()),
+        ("dot.png", "image/jpeg", PNG.to_vec()),
+        ("dot.jpg", "application/octet-stream", PNG.to_vec()),
+        ("manual.svg", "image/svg+xml", PDF.to_vec()),
+    ] {
+        let (status, problem) = server.upload(file_name, content_type, &content).await;
+        assert_eq!(
+            status,
+            Status::UnsupportedMediaType,
+            "{file_name} as {content_type}"
+        );
+        assert_eq!(problem["code"], "FILE_TYPE_MISMATCH");
+    }
+    assert_eq!(server.stored_files().len(), 0);
+}
+
+#[rocket::async_test]
+async fn denies_active_content_by_default() {
+    let server = start(ContentTypeConfig::default()).await;
+
+    for (file_name, content_type, content) in [
+        ("page.html", "text/html", HTML),
+        ("page.htm", "application/octet-stream", HTML),
+        ("logo.svg", "image/svg+xml", SVG),
+        ("page.xhtml", "application/xhtml+xml", XHTML),
+    ] {
+        let (status, problem) = server.upload(file_name, content_type, content).await;
+        assert_eq!(status, Status::UnsupportedMediaType, "{file_name}");
+        assert_eq!(problem["code"], "FILE_TYPE_NOT_ALLOWED", "{file_name}");
+    }
+    assert_eq!(server.stored_files().len(), 0);
+}
+
+#[rocket::async_test]
+async fn serves_active_content_as_attachments() {
+    let server = start(ContentTypeConfig {
+        allowed: Vec::new(),
+        denied: Vec::new(),
+    })
+    .await;
+    let (status, file) = server.upload("logo.svg", "image/svg+xml", SVG).await;
+    assert_eq!(status, Status::Ok);
+
+    // Signed links are followed without credentials, from anywhere
+    let response = server
+        .client
+        .post(format!("/files/{}/links", file["id"]))
+        .header(ContentType::JSON)
+        .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .body("{}")
+        .dispatch()
+        .await;
+    let link: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    let url = link["url"].as_str().unwrap().to_string();
+    for range in [None, Some("bytes=0-4"), Some("bytes=0-4,10-14")] {
+        let mut request = server
+            .client
+            .get(url.to_owned())
+            .remote(CLIENT_ADDR.parse().unwrap());
+        if let Some(range) = range {
+            request = request.header(Header::new("Range", range));
+        }
+        let response = request.dispatch().await;
+        assert!(response.status().class().is_success(), "{range:?}");
+        assert_eq!(
+            response.headers().get_one("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("attachment"),
+            "{range:?}"
+        );
+    }
+
+    let response = server
+        .client
+        .head(url)
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Content-Disposition"),
+        Some("attachment")
+    );
+}
+
+#[rocket::async_test]
+async fn enforces_allowed_types() {
+    let server = start(ContentTypeConfig {
+        allowed: patterns(&["image/*", "text/plain"]),
+        denied: patterns(&["image/svg+xml"]),
+    })
+    .await;
+
+    assert_eq!(
+        server.upload("dot.png", "image/png", PNG).await.0,
+        Status::Ok
+    );
+    assert_eq!(
+        server.upload("notes.txt", "text/plain", b"text").await.0,
+        Status::Ok
+    );
+    for (file_name, content_type, content) in [
+        ("logo.svg", "image/svg+xml", SVG.to_vec()),
+        ("manual.pdf", "application/pdf", PDF.to_vec()),
+        ("tool", "application/octet-stream", elf()),
+        ("data", "application/octet-stream", b"bytes".to_vec()),
+    ] {
+        let (status, problem) = server.upload(file_name, content_type, &content).await;
+        assert_eq!(status, Status::UnsupportedMediaType, "{file_name}");
+        assert_eq!(problem["code"], "FILE_TYPE_NOT_ALLOWED", "{file_name}");
+    }
+    assert_eq!(server.stored_files().len(), 2);
+
+    let invalid = ContentTypeConfig {
+        allowed: patterns(&["image"]),
+        denied: Vec::new(),
+    };
+    assert!(invalid.validate().is_err());
+    for pattern in ["*/png", "image/", "image/png/x", "text/plain"] {
+        let config = ContentTypeConfig {
+            allowed: Vec::new(),
+            denied: patterns(&[pattern]),
+        };
+        assert_eq!(
+            config.validate().is_ok(),
+            pattern == "text/plain",
+            "{pattern}"
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn checks_types_of_resumable_uploads() {
+    let server = start(ContentTypeConfig {
+        allowed: patterns(&["image/*"]),
+        denied: Vec::new(),
+    })
+    .await;
+
+    // "doc.pdf" and "application/pdf"
+    let response = server
+        .tus(
+            Method::Post,
+            "/uploads",
+            &[
+                ("Upload-Length", "64"),
+                (
+                    "Upload-Metadata",
+                    "filename ZG9jLnBkZg==,filetype YXBwbGljYXRpb24vcGRm",
+                ),
+            ],
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+    // "cat.png" and "image/png"
+    let metadata = "filename Y2F0LnBuZw==,filetype aW1hZ2UvcG5n";
+    let response = server
+        .tus(
+            Method::Post,
+            "/uploads",
+            &[("Upload-Length", "64"), ("Upload-Metadata", metadata)],
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), Status::Created);
+    let url = response.headers().get_one("Location").unwrap().to_string();
+    let response = server
+        .tus(Method::Patch, &url, &[("Upload-Offset", "0")], &elf())
+        .await;
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+    // Discarded, as it can never be recorded
+    assert_eq!(
+        server.tus(Method::Head, &url, &[], &[]).await.status(),
+        Status::NotFound
+    );
+
+    let length = PNG.len().to_string();
+    let response = server
+        .tus(
+            Method::Post,
+            "/uploads",
+            &[("Upload-Length", &length), ("Upload-Metadata", metadata)],
+            &[],
+        )
+        .await;
+    let url = response.headers().get_one("Location").unwrap().to_string();
+    let response = server
+        .tus(Method::Patch, &url, &[("Upload-Offset", "0")], PNG)
+        .await;
+    assert_eq!(response.status(), Status::NoContent);
+    let file_id = response.headers().get_one("X-File-Id").unwrap().to_string();
+
+    let response = server
+        .client
+        .get("/files")
+        .header(Header::new("X-API-KEY", server.api_key.to_owned()))
+        .remote(CLIENT_ADDR.parse().unwrap())
+        .dispatch()
+        .await;
+    let page: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert_eq!(page["items"][0]["id"].to_string(), file_id);
+    assert_eq!(page["items"][0]["content_type"], "image/png");
+}
@@ -0,0 +1,166 @@
+//! Users deleted until restored, and purged along with everything they stored.
+
+mod common;
+
+use api_server::models::Role;
+use common::{files_below, TestServer, CLIENT_ADDR, PASSWORD};
+use rocket::{
+    http::{Header, Method, Status},
+    serde::json::Value,
+};
+
+impl TestServer {
+    /// Gets the current user with `access_token` as bearer.
+    async fn me_with_token(&self, access_token: &Value) -> Status {
+        self.client
+            .get("/me")
+            .header(Header::new(
+                "Authorization",
+                format!("Bearer {}", access_token.as_str().unwrap()),
+            ))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .dispatch()
+            .await
+            .status()
+    }
+
+    /// Lists the IDs of all users, deleted ones included.
+    async fn all_user_ids(&self, admin_key: &str) -> Vec<(String, bool)> {
+        let (_, page) = self
+            .send(Method::Get, "/users?include_deleted=true", admin_key, None)
+            .await;
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| {
+                (
+                    user["user_id"].as_str().unwrap().to_string(),
+                    !user["deleted_at"].is_null(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[rocket::async_test]
+async fn deletes_users_until_restored() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+    let (_, tokens) = server.login("alice", PASSWORD).await;
+
+    let (status, _) = server
+        .send(Method::Delete, "/users/alice", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    // Neither keys nor sessions authenticate deleted users
+    let (status, problem) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(problem["code"], "UNAUTHENTICATED");
+    assert_eq!(
+        server.me_with_token(&tokens["access_token"]).await,
+        Status::Unauthorized
+    );
+    assert_eq!(
+        server.login("alice", PASSWORD).await.0,
+        Status::Unauthorized
+    );
+
+    // Kept aside until purged
+    let (_, page) = server.send(Method::Get, "/users", &admin_key, None).await;
+    assert_eq!(page["total"], 1);
+    assert_eq!(
+        server.all_user_ids(&admin_key).await,
+        [("alice".to_string(), true), ("root".to_string(), false)]
+    );
+    let (status, _) = server
+        .send(Method::Delete, "/users/alice", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, user) = server
+        .send(Method::Post, "/users/alice/restore", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(user["deleted_at"], Value::Null);
+
+    // With their keys, though their sessions were closed
+    let (status, me) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(me["user_id"], "alice");
+    assert_eq!(
+        server.me_with_token(&tokens["access_token"]).await,
+        Status::Unauthorized
+    );
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+
+    // Only deleted users can be restored
+    for user_id in ["alice", "nobody"] {
+        let (status, problem) = server
+            .send(
+                Method::Post,
+                &format!("/users/{}/restore", user_id),
+                &admin_key,
+                None,
+            )
+            .await;
+        assert_eq!(status, Status::NotFound, "{user_id}");
+        assert_eq!(problem["code"], "USER_NOT_FOUND");
+    }
+
+    // Users can delete themselves the same way
+    let (status, _) = server
+        .send(Method::Delete, "/me", &server.api_key, None)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        server.all_user_ids(&admin_key).await,
+        [("alice".to_string(), true), ("root".to_string(), false)]
+    );
+}
+
+#[rocket::async_test]
+async fn purges_users_along_with_their_files() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    // A file and an upload in progress
+    let (status, _) = server.upload("notes.txt", "text/plain", b"notes").await;
+    assert_eq!(status, Status::Ok);
+    let response = server
+        .tus(Method::Post, "/uploads", &[("Upload-Length", "10")], &[])
+        .await;
+    let url = response.headers().get_one("Location").unwrap().to_string();
+    let response = server
+        .tus(Method::Patch, &url, &[("Upload-Offset", "0")], b"part")
+        .await;
+    assert_eq!(response.status(), Status::NoContent);
+    let uploads = server.storage_dir().join("uploads");
+    assert_eq!(server.stored_files().len(), 1);
+    assert!(!files_below(&uploads).is_empty());
+
+    let (status, _) = server
+        .send(Method::Delete, "/users/alice/purge", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(server.stored_files(), Vec::<std::path::PathBuf>::new());
+    assert_eq!(files_below(&uploads), Vec::<std::path::PathBuf>::new());
+    assert_eq!(
+        server.all_user_ids(&admin_key).await,
+        [("root".to_string(), false)]
+    );
+    let (status, _) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    // Gone for good
+    for uri in ["/users/alice/purge", "/users/nobody/purge"] {
+        let (status, problem) = server.send(Method::Delete, uri, &admin_key, None).await;
+        assert_eq!(status, Status::NotFound, "{uri}");
+        assert_eq!(problem["code"], "USER_NOT_FOUND");
+    }
+    let (status, _) = server
+        .send(Method::Post, "/users/alice/restore", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::NotFound);
+}
@@ -0,0 +1,148 @@
+//! The user list, filtered, sorted and walked one page at a time.
+
+mod common;
+
+use api_server::models::Role;
+use common::{TestServer, PASSWORD};
+use rocket::{
+    http::{Method, Status},
+    serde::json::{serde_json::json, Value},
+};
+use std::collections::HashSet;
+
+const USERS: usize = 12;
+
+/// Creates `USERS` users whose emails sort the other way round from their IDs.
+async fn start() -> (TestServer, String) {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+    for i in 0..USERS {
+        let user = json!({
+            "user_id": format!("user-{:02}", i),
+            "password": PASSWORD,
+            "email_id": format!("{:02}@example.com", USERS - i),
+        });
+        let (status, _) = server
+            .send(Method::Post, "/users", &admin_key, Some(user))
+            .await;
+        assert_eq!(status, Status::Ok);
+    }
+
+    (server, admin_key)
+}
+
+/// Lists the users matching `query` page by page, returning the IDs in order.
+async fn walk(server: &TestServer, api_key: &str, query: &str) -> Vec<String> {
+    let mut user_ids = Vec::new();
+    let mut uri = format!("/users?limit=5&{}", query);
+    for _ in 0..=USERS {
+        let (status, page) = server.send(Method::Get, &uri, api_key, None).await;
+        assert_eq!(status, Status::Ok, "{uri}: {page}");
+        let items = page["items"].as_array().unwrap();
+        assert!(items.len() <= 5);
+        user_ids.extend(
+            items
+                .iter()
+                .map(|user| user["user_id"].as_str().unwrap().to_string()),
+        );
+
+        match &page["next_cursor"] {
+            Value::Null => {
+                assert_eq!(page["total"], user_ids.len());
+                return user_ids;
+            }
+            cursor => {
+                assert!(!items.is_empty());
+                uri = format!(
+                    "/users?limit=5&{}&after={}",
+                    query,
+                    cursor.as_str().unwrap()
+                );
+            }
+        }
+    }
+    panic!("{query}: more pages than users");
+}
+
+#[rocket::async_test]
+async fn walks_every_page_in_both_directions() {
+    let (server, admin_key) = start().await;
+    let by_id: Vec<String> = (0..USERS).map(|i| format!("user-{:02}", i)).collect();
+    let by_email: Vec<String> = by_id.iter().rev().cloned().collect();
+
+    for (sort, expected) in [
+        ("user_id", &by_id),
+        ("-user_id", &by_email),
+        ("email_id", &by_email),
+        ("-email_id", &by_id),
+    ] {
+        let user_ids = walk(
+            &server,
+            &admin_key,
+            &format!("user_id_prefix=user-&sort={}", sort),
+        )
+        .await;
+        assert_eq!(
+            user_ids.iter().collect::<HashSet<_>>().len(),
+            user_ids.len()
+        );
+        assert_eq!(&user_ids, expected, "{sort}");
+    }
+
+    // Without filter, the fixture users too
+    let user_ids = walk(&server, &admin_key, "sort=-user_id").await;
+    assert_eq!(user_ids.len(), USERS + 2);
+    assert_eq!(user_ids[0], "user-11");
+    assert_eq!(user_ids[USERS + 1], "alice");
+}
+
+#[rocket::async_test]
+async fn filters_users() {
+    let (server, admin_key) = start().await;
+
+    let (status, page) = server
+        .send(
+            Method::Get,
+            "/users?email_id=03@example.com",
+            &admin_key,
+            None,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["user_id"], "user-09");
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    // The total counts every page
+    let (_, page) = server
+        .send(
+            Method::Get,
+            "/users?user_id_prefix=user-1&limit=1",
+            &admin_key,
+            None,
+        )
+        .await;
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"].as_array().unwrap().len(), 1);
+    assert!(page["next_cursor"].is_string());
+
+    // Prefixes have no wildcards
+    for query in [
+        "user_id_prefix=nobody",
+        "user_id_prefix=user_",
+        "user_id_prefix=USER-",
+        "email_id=nobody@example.com",
+    ] {
+        let (_, page) = server
+            .send(Method::Get, &format!("/users?{}", query), &admin_key, None)
+            .await;
+        assert_eq!(page["total"], 0, "{query}");
+        assert_eq!(page["items"], json!([]));
+    }
+
+    let (status, problem) = server
+        .send(Method::Get, "/users?after=not-a-cursor", &admin_key, None)
+        .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(problem["code"], "BAD_REQUEST");
+}
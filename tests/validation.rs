@@ -0,0 +1,145 @@
+//! Invalid input rejected field by field, and conflicting users, reported as problems.
+
+mod common;
+
+use api_server::models::Role;
+use common::{json, TestServer, CLIENT_ADDR, PASSWORD};
+use rocket::{
+    http::{ContentType, Header, Method, Status},
+    serde::json::{serde_json::json, Value},
+};
+
+impl TestServer {
+    /// Creates a user from `user` as `api_key`, checking that errors come as problems.
+    async fn post_user(&self, api_key: &str, user: Value) -> (Status, Value) {
+        let response = self
+            .client
+            .post("/users")
+            .header(ContentType::JSON)
+            .header(Header::new("X-API-KEY", api_key.to_string()))
+            .remote(CLIENT_ADDR.parse().unwrap())
+            .body(user.to_string())
+            .dispatch()
+            .await;
+        let content_type = response.content_type();
+        let (status, body) = json(response).await;
+        if status.class().is_client_error() {
+            assert_eq!(
+                content_type,
+                Some(ContentType::new("application", "problem+json"))
+            );
+            assert_eq!(body["status"], status.code);
+            assert_eq!(body["title"], status.reason_lossy());
+            assert_eq!(body["type"], "about:blank");
+            assert_eq!(body["instance"], "/users");
+            assert!(body["request_id"].is_string());
+        }
+        (status, body)
+    }
+}
+
+fn new_user(user_id: &str, password: &str, email_id: &str) -> Value {
+    json!({ "user_id": user_id, "password": password, "email_id": email_id })
+}
+
+/// The rejected fields of a problem, as `(field, code)`.
+fn field_errors(problem: &Value) -> Vec<(&str, &str)> {
+    problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            assert!(error["message"].is_string());
+            (
+                error["field"].as_str().unwrap(),
+                error["code"].as_str().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[rocket::async_test]
+async fn reports_invalid_fields() {
+    let server = TestServer::start_with(|settings| {
+        settings.server.password_policy.require_digit = true;
+    })
+    .await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    let (status, problem) = server
+        .post_user(&admin_key, new_user("bad id!", "short", "not-an-email"))
+        .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(problem["code"], "INVALID_INPUT");
+    assert_eq!(
+        field_errors(&problem),
+        [
+            ("email_id", "email"),
+            ("password", "length"),
+            ("user_id", "charset")
+        ]
+    );
+
+    for (user, field, code) in [
+        (
+            new_user(&"c".repeat(65), PASSWORD, "carol@example.com"),
+            "user_id",
+            "length",
+        ),
+        (
+            new_user("carol", "NoDigits#", "carol@example.com"),
+            "password",
+            "complexity",
+        ),
+        (new_user("carol", PASSWORD, ""), "email_id", "email"),
+    ] {
+        let (status, problem) = server.post_user(&admin_key, user).await;
+        assert_eq!(status, Status::UnprocessableEntity, "{field}");
+        assert_eq!(field_errors(&problem), [(field, code)]);
+    }
+
+    // Also on updates
+    let (status, problem) = server
+        .send(
+            Method::Put,
+            "/users/alice",
+            &admin_key,
+            Some(json!({ "password": "short" })),
+        )
+        .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(field_errors(&problem), [("password", "length")]);
+    let (status, problem) = server
+        .send(
+            Method::Patch,
+            "/me",
+            &server.api_key,
+            Some(json!({ "current_password": PASSWORD, "new_password": "NoDigits#" })),
+        )
+        .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(field_errors(&problem), [("new_password", "complexity")]);
+    assert_eq!(server.login("alice", PASSWORD).await.0, Status::Ok);
+
+    let (status, _) = server
+        .post_user(&admin_key, new_user("carol", PASSWORD, "carol@example.com"))
+        .await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn reports_conflicting_users() {
+    let server = TestServer::start().await;
+    let admin_key = server.create_user("root", Role::Admin).await;
+
+    for user in [
+        new_user("alice", PASSWORD, "other@example.com"),
+        new_user("carol", PASSWORD, "alice@example.com"),
+    ] {
+        let (status, problem) = server.post_user(&admin_key, user).await;
+        assert_eq!(status, Status::Conflict);
+        assert_eq!(problem["code"], "USER_CONFLICT");
+    }
+    let (_, me) = server.send(Method::Get, "/me", &server.api_key, None).await;
+    assert_eq!(me["email_id"], "alice@example.com");
+}